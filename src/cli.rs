@@ -0,0 +1,703 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ndarray::{Array2, Axis};
+use serde::{Deserialize, Serialize};
+use crate::cluster_algos::error::ClusterError;
+use crate::cluster_algos::dbscan::{suggest_min_points, k_distance_curve, knee_point, NOISE};
+use crate::utils::distances::CondensedMatrix;
+use crate::utils::mathfuncs::{center_scale, silhouette_score_precomputed, adjusted_rand_index, normalized_mutual_info};
+use crate::utils::io::{CsvReader, Column, Dataset, MissingValues};
+use crate::utils::npy::{read_npy, read_npz, read_npz_array, read_npy_labels, write_npy, write_npy_labels};
+use crate::plots::{scatter_plot, reachability_plot, k_distance_plot, dendrogram_plot};
+pub mod model;
+
+use model::{SavedModel, Scores};
+
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  2  invalid arguments
+  3  a file could not be read or written
+  4  the input data or model file is invalid
+  5  the model does not support the request";
+
+#[derive(Parser)]
+#[command(name = "clustering", version, about = "Fit, apply and inspect clustering models", after_help = EXIT_CODES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Cluster a dataset, optionally saving the model and the labels
+    Fit(Box<FitArgs>),
+    /// Label new points with a saved model
+    Predict(PredictArgs),
+    /// Score labels against ground truth and by silhouette
+    Evaluate(EvaluateArgs),
+    /// Draw a dataset with its labels, or a diagram of a saved model, to images/
+    Plot(PlotArgs),
+    /// Write a synthetic dataset of four noisy squares
+    Generate(GenerateArgs),
+    /// Run the built-in comparison on synthetic data and plot every result to images/
+    Demo(DemoArgs)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Algo {
+    Kmeans,
+    Kmedoids,
+    Kmodes,
+    Kprototypes,
+    Dbscan,
+    Hdbscan,
+    Optics,
+    MeanShift,
+    AffinityPropagation,
+    Spectral,
+    Birch,
+    Agglomerative
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MissingArg {
+    /// Fail on the first missing value
+    Error,
+    /// Leave rows with missing values out
+    Drop,
+    /// Replace missing values by their column mean
+    Mean,
+    /// Replace missing values by --fill-value
+    Fill
+}
+
+/// How delimited input files are read, see `utils::io::CsvReader`. Files ending in
+/// `.npy` or `.npz` are read as NumPy arrays, see `utils::npy`.
+#[derive(Args, Clone, Debug)]
+pub struct DataFormat {
+    /// Field separator, detected from the first lines if not given
+    #[arg(long)]
+    pub delimiter: Option<char>,
+    /// The first line holds column names, detected if neither flag is given
+    #[arg(long, conflicts_with = "no_header")]
+    pub header: bool,
+    #[arg(long)]
+    pub no_header: bool,
+    /// Columns to cluster, by index or header name, comma separated. All but the label column by default
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+    /// Column with ground truth labels, left out of the data
+    #[arg(long)]
+    pub label_column: Option<String>,
+    /// Array of a .npz input to read, the first one by default
+    #[arg(long)]
+    pub array: Option<String>,
+    /// Handling of empty fields and NA, NaN, null, none or ?
+    #[arg(long, value_enum, default_value_t = MissingArg::Error)]
+    pub missing: MissingArg,
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub fill_value: f32
+}
+
+#[derive(Args)]
+pub struct FitArgs {
+    #[arg(long)]
+    pub algo: Algo,
+    /// Delimited file with one point per row
+    #[arg(long)]
+    pub input: PathBuf,
+    #[command(flatten)]
+    pub format: DataFormat,
+    /// Where to save the fitted model as JSON
+    #[arg(long)]
+    pub model: Option<PathBuf>,
+    /// Where to write the labels, one per line or as an int32 array for .npy. Printed
+    /// if none of --model, --output, --annotated, --centroids and --linkage-matrix is given
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Where to write the input rows with an added cluster column and any per-point scores
+    #[arg(long)]
+    pub annotated: Option<PathBuf>,
+    /// Where to save the cluster centers as a float32 .npy array
+    #[arg(long)]
+    pub centroids: Option<PathBuf>,
+    /// Where to save the SciPy linkage matrix of an agglomerative fit as a float32 .npy array
+    #[arg(long)]
+    pub linkage_matrix: Option<PathBuf>,
+    #[command(flatten)]
+    pub params: model::Hyperparameters
+}
+
+#[derive(Args)]
+pub struct PredictArgs {
+    /// Model saved by `fit --model`
+    #[arg(long)]
+    pub model: PathBuf,
+    #[arg(long)]
+    pub input: PathBuf,
+    #[command(flatten)]
+    pub format: DataFormat,
+    /// Where to write the labels, one per line or as an int32 array for .npy, instead
+    /// of printing them
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Where to write the input rows with an added cluster column and, for models
+    /// labelling by nearest reference, the distance to it
+    #[arg(long)]
+    pub annotated: Option<PathBuf>
+}
+
+#[derive(Args)]
+pub struct EvaluateArgs {
+    /// Labels to score, one per line or as a .npy integer array
+    #[arg(long, conflicts_with = "model")]
+    pub predicted: Option<PathBuf>,
+    /// Score the labels a saved model was fitted with
+    #[arg(long)]
+    pub model: Option<PathBuf>,
+    /// Ground truth labels, one per line or as a .npy integer array. Also taken from --label-column of --input
+    #[arg(long)]
+    pub labels: Option<PathBuf>,
+    /// The clustered points, to compute the silhouette
+    #[arg(long)]
+    pub input: Option<PathBuf>,
+    #[command(flatten)]
+    pub format: DataFormat
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PlotKind {
+    /// The first two columns coloured by label
+    Scatter,
+    /// Merge tree of an agglomerative model
+    Dendrogram,
+    /// Sorted distances to the k-th neighbour, for choosing the DBScan epsilon
+    KDistance,
+    /// Reachability distances of an OPTICS model
+    Reachability
+}
+
+#[derive(Args)]
+pub struct PlotArgs {
+    #[arg(long, value_enum, default_value_t = PlotKind::Scatter)]
+    pub kind: PlotKind,
+    /// File name of the image, without extension
+    #[arg(long, default_value = "plot")]
+    pub name: String,
+    #[arg(long)]
+    pub input: Option<PathBuf>,
+    #[command(flatten)]
+    pub format: DataFormat,
+    /// Labels to colour the points by, one per line or as a .npy integer array. Defaults to the model's labels
+    #[arg(long)]
+    pub labels: Option<PathBuf>,
+    #[arg(long)]
+    pub model: Option<PathBuf>,
+    /// Neighbour of the k-distance plot, twice the dimension by default
+    #[arg(long)]
+    pub min_points: Option<usize>,
+    /// Merges shown by the dendrogram before truncating
+    #[arg(long, default_value_t = 30)]
+    pub last_p: usize,
+    /// Height below which dendrogram links are coloured by cluster
+    #[arg(long)]
+    pub color_threshold: Option<f32>,
+    /// Height of a horizontal cut line in the dendrogram
+    #[arg(long)]
+    pub cut: Option<f32>
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+    #[arg(long)]
+    pub output: PathBuf,
+    /// Field separator, a tab for .tsv files and a comma otherwise
+    #[arg(long)]
+    pub delimiter: Option<char>,
+    /// Start with a line of column names
+    #[arg(long)]
+    pub header: bool,
+    /// Where to write the true labels, -1 for the noise points
+    #[arg(long)]
+    pub labels: Option<PathBuf>,
+    /// Points per square
+    #[arg(long, default_value_t = 100)]
+    pub cluster_size: usize,
+    /// Noise points on top of a tenth of the cluster size
+    #[arg(long, default_value_t = 20)]
+    pub noise: usize,
+    /// Center every column and scale it to unit variance
+    #[arg(long)]
+    pub center_scale: bool
+}
+
+#[derive(Args)]
+pub struct DemoArgs {
+    /// Algorithms to run, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [
+        Algo::Agglomerative, Algo::Kmeans, Algo::Dbscan, Algo::Hdbscan, Algo::Optics,
+        Algo::MeanShift, Algo::AffinityPropagation, Algo::Spectral, Algo::Birch
+    ])]
+    pub algos: Vec<Algo>,
+    /// Also run the older centroid-linkage AgglomerativeCluster
+    #[arg(long)]
+    pub legacy_agglomerative: bool
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    /// Flags that contradict each other or the model.
+    Usage(String),
+    /// Reading or writing a file failed.
+    Io(String),
+    /// A file could be read, but its contents are not usable.
+    InvalidInput(String),
+    /// The model cannot do what was asked, e.g. label new points after HDBScan.
+    Unsupported(String)
+}
+
+impl CliError {
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Io(_) => 3,
+            CliError::InvalidInput(_) => 4,
+            CliError::Unsupported(_) => 5
+        }
+    }
+
+    fn io(path: &Path, error: impl fmt::Display) -> CliError {
+        CliError::Io(format!("{}: {}", path.display(), error))
+    }
+}
+
+impl From<ClusterError> for CliError {
+    fn from(error: ClusterError) -> CliError {
+        match error {
+            ClusterError::InvalidParameter(message) => CliError::Usage(message),
+            ClusterError::InvalidInput(message) => CliError::InvalidInput(message)
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message)
+            | CliError::Io(message)
+            | CliError::InvalidInput(message)
+            | CliError::Unsupported(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+pub fn run(cli: Cli) -> Result<(), CliError> {
+    match cli.command {
+        Command::Fit(args) => fit(*args),
+        Command::Predict(args) => predict(args),
+        Command::Evaluate(args) => evaluate(args),
+        Command::Plot(args) => plot(args),
+        Command::Generate(args) => generate(args),
+        Command::Demo(args) => {
+            crate::demo(&args.algos, args.legacy_agglomerative);
+            Ok(())
+        }
+    }
+}
+
+fn fit(args: FitArgs) -> Result<(), CliError> {
+    if args.annotated.is_some() && is_numpy(&args.input) {
+        return Err(CliError::Usage("--annotated needs a delimited --input".to_string()));
+    }
+    let dataset = read_dataset(&args.input, &args.format)?;
+    let data = &dataset.data;
+    let (saved, mut scores) = model::fit(args.algo, &args.params, data)?;
+    let n_clusters = saved.labels.iter().filter(|&&label| label != NOISE).max().map_or(0, |&max| max + 1);
+    let n_noise = saved.labels.iter().filter(|&&label| label == NOISE).count();
+    eprintln!("{:?}: {} clusters, {} noise points", args.algo, n_clusters, n_noise);
+    if let Some(path) = &args.model {
+        let file = File::create(path).map_err(|e| CliError::io(path, e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &saved).map_err(|e| CliError::io(path, e))?;
+    }
+    if let Some(path) = &args.annotated {
+        if let Some(distances) = saved.distances(data) {
+            scores.push(("distance".to_string(), distances));
+        }
+        write_annotated(&dataset, path, &saved.labels, &scores)?;
+    }
+    if let Some(path) = &args.centroids {
+        let centroids = saved.centroids();
+        if centroids.shape()[0] == 0 {
+            return Err(CliError::Unsupported(format!("{:?} has no centroids to save", args.algo)));
+        }
+        write_npy(path, &centroids).map_err(|e| CliError::io(path, e))?;
+    }
+    if let Some(path) = &args.linkage_matrix {
+        let tree = saved.tree.as_ref()
+            .ok_or_else(|| CliError::Unsupported("--linkage-matrix needs an agglomerative fit".to_string()))?;
+        write_npy(path, &tree.linkage_matrix()).map_err(|e| CliError::io(path, e))?;
+    }
+    let saved_something = [&args.model, &args.annotated, &args.centroids, &args.linkage_matrix].iter().any(|path| path.is_some());
+    if args.output.is_some() || !saved_something {
+        write_label_lines(args.output.as_deref(), &saved.labels)?;
+    }
+    Ok(())
+}
+
+fn predict(args: PredictArgs) -> Result<(), CliError> {
+    if args.annotated.is_some() && is_numpy(&args.input) {
+        return Err(CliError::Usage("--annotated needs a delimited --input".to_string()));
+    }
+    let saved = read_model(&args.model)?;
+    let dataset = read_dataset(&args.input, &args.format)?;
+    let labels = saved.predict(&dataset.data)?;
+    if let Some(path) = &args.annotated {
+        let scores: Scores = saved.distances(&dataset.data)
+            .map(|distances| ("distance".to_string(), distances))
+            .into_iter()
+            .collect();
+        write_annotated(&dataset, path, &labels, &scores)?;
+    }
+    if args.output.is_some() || args.annotated.is_none() {
+        write_label_lines(args.output.as_deref(), &labels)?;
+    }
+    Ok(())
+}
+
+fn evaluate(args: EvaluateArgs) -> Result<(), CliError> {
+    let predicted = match (&args.predicted, &args.model) {
+        (Some(path), _) => read_labels(path)?,
+        (None, Some(path)) => read_model(path)?.labels,
+        (None, None) => return Err(CliError::Usage("evaluate needs --predicted or --model".to_string()))
+    };
+    if args.labels.is_none() && args.input.is_none() {
+        return Err(CliError::Usage("evaluate needs --labels, --input or both".to_string()));
+    }
+    let dataset = args.input.as_deref().map(|path| read_dataset(path, &args.format)).transpose()?;
+    let truth = match (&args.labels, &dataset) {
+        (Some(path), _) => Some(read_labels(path)?),
+        (None, Some(dataset)) => dataset.labels.clone(),
+        (None, None) => None
+    };
+    let data = dataset.map(|dataset| dataset.data);
+    if let Some(truth) = &truth {
+        check_length(truth.len(), predicted.len(), "true labels")?;
+    }
+    if let Some(data) = &data {
+        check_length(data.shape()[0], predicted.len(), "points")?;
+    }
+    let n_clusters = predicted.iter().filter(|&&label| label != NOISE).max().map_or(0, |&max| max + 1);
+    println!("clusters: {}", n_clusters);
+    println!("noise: {}", predicted.iter().filter(|&&label| label == NOISE).count());
+    if let Some(truth) = &truth {
+        println!("adjusted_rand_index: {}", adjusted_rand_index(truth, &predicted));
+        println!("normalized_mutual_info: {}", normalized_mutual_info(truth, &predicted));
+    }
+    if let Some(data) = &data {
+        println!("silhouette: {}", silhouette_score_precomputed(&CondensedMatrix::euclidean(data), &predicted));
+    }
+    Ok(())
+}
+
+fn plot(args: PlotArgs) -> Result<(), CliError> {
+    let saved = args.model.as_deref().map(read_model).transpose()?;
+    let data = args.input.as_deref().map(|path| read_dataset(path, &args.format)).transpose()?.map(|dataset| dataset.data);
+    let result = match args.kind {
+        PlotKind::Scatter => {
+            let data = data.ok_or_else(|| CliError::Usage("scatter plots need --input".to_string()))?;
+            if data.shape()[1] < 2 {
+                return Err(CliError::InvalidInput("scatter plots need at least two columns".to_string()));
+            }
+            let labels = match (&args.labels, &saved) {
+                (Some(path), _) => read_labels(path)?,
+                (None, Some(saved)) => saved.labels.clone(),
+                (None, None) => vec![0; data.shape()[0]]
+            };
+            check_length(data.shape()[0], labels.len(), "labels")?;
+            let centroids = saved.as_ref().map_or_else(|| Array2::zeros((0, 2)), |saved| saved.centroids());
+            let plot_centroids = centroids.shape()[0] > 0 && centroids.shape()[1] >= 2;
+            scatter_plot(&args.name, &data, &labels, &centroids, plot_centroids)
+        }
+        PlotKind::Dendrogram => {
+            let tree = saved.and_then(|saved| saved.tree)
+                .ok_or_else(|| CliError::Unsupported("dendrograms need --model of an agglomerative fit".to_string()))?;
+            dendrogram_plot(&args.name, &tree.merges, Some(args.last_p), args.color_threshold, args.cut)
+        }
+        PlotKind::KDistance => {
+            let data = data.ok_or_else(|| CliError::Usage("k-distance plots need --input".to_string()))?;
+            let min_points = args.min_points.unwrap_or_else(|| suggest_min_points(data.shape()[1]));
+            let curve = k_distance_curve(&data, min_points);
+            let knee = knee_point(&curve);
+            let epsilon = curve.get(knee).copied().unwrap_or(0.0);
+            eprintln!("suggested epsilon: {}", epsilon);
+            k_distance_plot(&args.name, &curve, knee, epsilon)
+        }
+        PlotKind::Reachability => {
+            let saved = saved.filter(|saved| !saved.ordering.is_empty())
+                .ok_or_else(|| CliError::Unsupported("reachability plots need --model of an OPTICS fit".to_string()))?;
+            let reachability: Vec<f32> = saved.reachability.iter().map(|r| r.unwrap_or(f32::INFINITY)).collect();
+            reachability_plot(&args.name, &saved.ordering, &reachability, &saved.labels)
+        }
+    };
+    result.map_err(|e| CliError::Io(format!("images/{}.png: {}", args.name, e)))?;
+    eprintln!("wrote images/{}.png", args.name);
+    Ok(())
+}
+
+fn generate(args: GenerateArgs) -> Result<(), CliError> {
+    let mut data = crate::get_data(args.noise, args.cluster_size);
+    if args.center_scale {
+        center_scale(&mut data);
+    }
+    let delimiter = args.delimiter.unwrap_or(if args.output.extension().is_some_and(|ext| ext == "tsv") { '\t' } else { ',' });
+    let file = File::create(&args.output).map_err(|e| CliError::io(&args.output, e))?;
+    let mut writer = BufWriter::new(file);
+    let write = |writer: &mut BufWriter<File>| -> io::Result<()> {
+        if args.header {
+            let names: Vec<String> = (0..data.shape()[1]).map(|j| format!("x{}", j)).collect();
+            writeln!(writer, "{}", names.join(&delimiter.to_string()))?;
+        }
+        for row in data.rows() {
+            let fields: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            writeln!(writer, "{}", fields.join(&delimiter.to_string()))?;
+        }
+        writer.flush()
+    };
+    write(&mut writer).map_err(|e| CliError::io(&args.output, e))?;
+    if let Some(path) = &args.labels {
+        let mut labels: Vec<i32> = (0..4).flat_map(|k| vec![k; args.cluster_size]).collect();
+        labels.resize(data.shape()[0], NOISE);
+        write_label_lines(Some(path), &labels)?;
+    }
+    Ok(())
+}
+
+fn read_dataset(path: &Path, format: &DataFormat) -> Result<Dataset, CliError> {
+    if is_numpy(path) {
+        return read_array_dataset(path, format);
+    }
+    if format.array.is_some() {
+        return Err(CliError::Usage("--array needs a .npz input".to_string()));
+    }
+    let mut reader = CsvReader::new();
+    if let Some(delimiter) = format.delimiter {
+        reader.set_delimiter(delimiter);
+    }
+    if format.header || format.no_header {
+        reader.set_header(format.header);
+    }
+    reader.set_columns(format.columns.iter().map(|column| Column::parse(column)).collect());
+    if let Some(column) = &format.label_column {
+        reader.set_label_column(Column::parse(column));
+    }
+    reader.set_missing(match format.missing {
+        MissingArg::Error => MissingValues::Error,
+        MissingArg::Drop => MissingValues::DropRows,
+        MissingArg::Mean => MissingValues::Mean,
+        MissingArg::Fill => MissingValues::Fill(format.fill_value)
+    });
+    let dataset = reader.read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData => CliError::InvalidInput(e.to_string()),
+        _ => CliError::io(path, e)
+    })?;
+    if dataset.data.shape()[0] == 0 {
+        return Err(CliError::InvalidInput(format!("{}: no data rows", path.display())));
+    }
+    Ok(dataset)
+}
+
+/// A `.npy` or `.npz` input as a `Dataset`, columns and label column given by index.
+/// Missing values are NaNs.
+fn read_array_dataset(path: &Path, format: &DataFormat) -> Result<Dataset, CliError> {
+    let index = |column: &String| column.trim().parse::<usize>()
+        .map_err(|_| CliError::Usage(format!("columns of NumPy arrays are given by index, not {:?}", column)));
+    let selected: Vec<usize> = format.columns.iter().map(index).collect::<Result<_, _>>()?;
+    let label_index = format.label_column.as_ref().map(index).transpose()?;
+    let array = match (path.extension().is_some_and(|ext| ext == "npz"), &format.array) {
+        (false, Some(_)) => return Err(CliError::Usage("--array needs a .npz input".to_string())),
+        (false, None) => read_npy(path),
+        (true, Some(name)) => read_npz_array(path, name),
+        (true, None) => read_npz(path).and_then(|arrays| arrays.into_iter().next().map(|(_, array)| array)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: archive holds no arrays", path.display()))))
+    };
+    let array = array.map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData => CliError::InvalidInput(e.to_string()),
+        _ => CliError::io(path, e)
+    })?;
+    let width = array.shape()[1];
+    if let Some(&j) = selected.iter().chain(label_index.iter()).find(|&&j| j >= width) {
+        return Err(CliError::InvalidInput(format!("{}: column {} out of range for {} columns", path.display(), j, width)));
+    }
+    let selected = if selected.is_empty() { (0..width).filter(|&j| Some(j) != label_index).collect() } else { selected };
+
+    let mut rows = Vec::new();
+    for (row, point) in array.rows().into_iter().enumerate() {
+        if selected.iter().any(|&j| point[j].is_nan()) {
+            match format.missing {
+                MissingArg::Error => return Err(CliError::InvalidInput(format!("{}: missing value in row {}", path.display(), row))),
+                MissingArg::Drop => continue,
+                MissingArg::Mean | MissingArg::Fill => {}
+            }
+        }
+        rows.push(row);
+    }
+    let mut data = array.select(Axis(0), &rows).select(Axis(1), &selected);
+    for mut column in data.columns_mut() {
+        let present: Vec<f32> = column.iter().copied().filter(|value| !value.is_nan()).collect();
+        let fill = match format.missing {
+            MissingArg::Fill => format.fill_value,
+            _ => present.iter().sum::<f32>() / present.len().max(1) as f32
+        };
+        column.mapv_inplace(|value| if value.is_nan() { fill } else { value });
+    }
+    if data.shape()[0] == 0 {
+        return Err(CliError::InvalidInput(format!("{}: no data rows", path.display())));
+    }
+    let labels = label_index.map(|j| rows.iter().map(|&row| {
+        let label = array[[row, j]];
+        if label.is_nan() { NOISE } else { label as i32 }
+    }).collect());
+    Ok(Dataset {
+        data,
+        column_names: selected.iter().map(|j| format!("x{}", j)).collect(),
+        labels,
+        label_names: Vec::new(),
+        rows,
+        source: path.to_path_buf(),
+        delimiter: ',',
+        has_header: false
+    })
+}
+
+fn is_numpy(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "npy" || ext == "npz")
+}
+
+fn write_annotated(dataset: &Dataset, path: &Path, labels: &[i32], scores: &Scores) -> Result<(), CliError> {
+    let extra: Vec<(&str, &[f32])> = scores.iter().map(|(name, values)| (name.as_str(), values.as_slice())).collect();
+    dataset.write_with_labels(path, labels, &extra).map_err(|e| CliError::io(path, e))
+}
+
+fn read_labels(path: &Path) -> Result<Vec<i32>, CliError> {
+    if path.extension().is_some_and(|ext| ext == "npy") {
+        return read_npy_labels(path).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => CliError::InvalidInput(e.to_string()),
+            _ => CliError::io(path, e)
+        });
+    }
+    let contents = std::fs::read_to_string(path).map_err(|e| CliError::io(path, e))?;
+    contents.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| line.trim().parse::<i32>().map_err(|_| {
+            CliError::InvalidInput(format!("{}:{}: {:?} is not an integer label", path.display(), i + 1, line))
+        }))
+        .collect()
+}
+
+fn read_model(path: &Path) -> Result<SavedModel, CliError> {
+    let file = File::open(path).map_err(|e| CliError::io(path, e))?;
+    let saved: SavedModel = serde_json::from_reader(io::BufReader::new(file))
+        .map_err(|e| CliError::InvalidInput(format!("{}: not a saved model: {}", path.display(), e)))?;
+    saved.validate().map_err(|e| CliError::InvalidInput(format!("{}: {}", path.display(), e)))?;
+    Ok(saved)
+}
+
+/// Writes one label per line to `path`, or to stdout without one. A `.npy` path gets
+/// an int32 array instead.
+fn write_label_lines(path: Option<&Path>, labels: &[i32]) -> Result<(), CliError> {
+    if let Some(path) = path.filter(|path| path.extension().is_some_and(|ext| ext == "npy")) {
+        return write_npy_labels(path, labels).map_err(|e| CliError::io(path, e));
+    }
+    let write = |writer: &mut dyn Write| -> io::Result<()> {
+        for label in labels {
+            writeln!(writer, "{}", label)?;
+        }
+        writer.flush()
+    };
+    match path {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| CliError::io(path, e))?;
+            write(&mut BufWriter::new(file)).map_err(|e| CliError::io(path, e))
+        }
+        None => write(&mut io::stdout().lock()).map_err(|e| CliError::Io(format!("stdout: {}", e)))
+    }
+}
+
+fn check_length(found: usize, expected: usize, what: &str) -> Result<(), CliError> {
+    if found != expected {
+        return Err(CliError::InvalidInput(format!("{} {} for {} labels", found, what, expected)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four 3x3 grids with spacing 0.1 around the corners of a square of side 10.
+    fn squares() -> Array2<f32> {
+        Array2::from_shape_fn((36, 2), |(i, j)| {
+            let corner = [[0.0, 0.0], [10.0, 0.0], [0.0, 10.0], [10.0, 10.0]][i / 9][j];
+            let offset = if j == 0 { i % 3 } else { (i / 3) % 3 };
+            corner + 0.1 * offset as f32
+        })
+    }
+
+    /// Parses `fit` with `flags` as the command line would and fits on `squares`.
+    fn fit_with(flags: &[&str]) -> Result<(SavedModel, Scores), CliError> {
+        let mut argv = vec!["clustering", "fit", "--input", "unused.csv"];
+        argv.extend_from_slice(flags);
+        let Command::Fit(args) = Cli::try_parse_from(argv).unwrap().command else {
+            unreachable!("parsed a fit command");
+        };
+        model::fit(args.algo, &args.params, &squares())
+    }
+
+    fn usage_error(flags: &[&str]) -> String {
+        match fit_with(flags) {
+            Err(error @ CliError::Usage(_)) => {
+                assert_eq!(error.exit_code(), 2);
+                error.to_string()
+            }
+            Err(error) => panic!("{:?} failed with {:?}, expected a usage error", flags, error),
+            Ok(_) => panic!("{:?} succeeded, expected a usage error", flags)
+        }
+    }
+
+    #[test]
+    fn kmeans_rejects_settings_that_run_nothing() {
+        assert!(usage_error(&["--algo", "kmeans", "--k", "0", "--retries", "0"]).contains("retries"));
+        assert!(usage_error(&["--algo", "kmeans", "--k", "4", "--retries", "0"]).contains("retries"));
+        assert!(usage_error(&["--algo", "kmeans", "--k", "0", "--max-centers", "2"]).contains("max_centers"));
+        assert!(usage_error(&["--algo", "kmeans", "--k", "4", "--max-iter", "0"]).contains("max_iter"));
+        assert!(usage_error(&["--algo", "kmeans", "--k=-1"]).contains("--k"));
+    }
+
+    #[test]
+    fn kmeans_finds_the_squares() {
+        let (saved, _) = fit_with(&["--algo", "kmeans", "--k", "4", "--retries", "3"]).unwrap();
+        let mut clusters: Vec<i32> = saved.labels.chunks(9).map(|square| square[0]).collect();
+        assert!(saved.labels.chunks(9).all(|square| square.iter().all(|&label| label == square[0])));
+        clusters.sort_unstable();
+        assert_eq!(clusters, vec![0, 1, 2, 3]);
+        let (searched, _) = fit_with(&["--algo", "kmeans", "--k", "0", "--max-centers", "6", "--accept", "0.95"]).unwrap();
+        assert_eq!(searched.labels.iter().max(), Some(&3));
+    }
+
+    #[test]
+    fn range_checks_are_usage_errors() {
+        usage_error(&["--algo", "optics", "--xi", "1"]);
+        usage_error(&["--algo", "birch", "--threshold", "0"]);
+        usage_error(&["--algo", "birch", "--branching-factor", "1"]);
+        usage_error(&["--algo", "mean-shift", "--quantile", "0"]);
+        usage_error(&["--algo", "affinity-propagation", "--damping", "1"]);
+        usage_error(&["--algo", "spectral", "--affinity", "nearest-neighbours", "--neighbours", "36"]);
+        usage_error(&["--algo", "dbscan", "--epsilon", "1", "--min-points", "0"]);
+        usage_error(&["--algo", "spectral", "--k", "0", "--max-centers=-2"]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,584 @@
+use clap::{Args, ValueEnum};
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use crate::cli::{Algo, CliError};
+use crate::cluster_algos::agglomerative::{AggloClusterer, Linkage, knn_graph};
+use crate::cluster_algos::affinity_propagation::AffinityPropagation;
+use crate::cluster_algos::birch::{Birch, GlobalClustering};
+use crate::cluster_algos::dbscan::{DBScan, estimate_parameters, NOISE};
+use crate::cluster_algos::hdbscan::{HDBScan, ClusterSelection};
+use crate::cluster_algos::kmedoids::KMedoids;
+use crate::cluster_algos::kmodes::{KModes, KPrototypes, ModeInit, mismatches};
+use crate::cluster_algos::lloyd::Kmeans;
+use crate::cluster_algos::mean_shift::{MeanShift, Kernel, estimate_bandwidth};
+use crate::cluster_algos::optics::Optics;
+use crate::cluster_algos::spectral::{SpectralClustering, Affinity, AssignLabels};
+use crate::utils::mathfuncs::l2_view;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum KmeansInit {
+    #[value(name = "kmeans++")]
+    KmeansPlusPlus,
+    RandomChoice
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ModeInitArg {
+    Huang,
+    Cao
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum SelectionArg {
+    ExcessOfMass,
+    Leaf
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum KernelArg {
+    Flat,
+    Gaussian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum AffinityArg {
+    Rbf,
+    NearestNeighbours
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum AssignLabelsArg {
+    Kmeans,
+    Discretize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum GlobalArg {
+    Subclusters,
+    Kmeans,
+    Agglomerative
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum LinkageArg {
+    Single,
+    Complete,
+    Average,
+    Weighted,
+    Ward,
+    Centroid,
+    Median
+}
+
+/// Every hyperparameter of every algorithm, each one only read by the algorithms
+/// named in its help. Saved with the model.
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    /// Number of clusters [kmeans (0 searches up to --max-centers), kmedoids, kmodes,
+    /// kprototypes, spectral (0 uses the eigengap), birch, agglomerative]
+    #[arg(long, default_value_t = 4, help_heading = "Hyperparameters")]
+    pub k: i32,
+    /// Largest number of clusters tried when --k is 0 [kmeans, spectral]
+    #[arg(long, default_value_t = 10, help_heading = "Hyperparameters")]
+    pub max_centers: i32,
+    /// Silhouette score that ends the search over the number of clusters [kmeans]
+    #[arg(long, default_value_t = 0.7, help_heading = "Hyperparameters")]
+    pub accept: f32,
+    /// Seeding of the centroids [kmeans]
+    #[arg(long, value_enum, default_value_t = KmeansInit::KmeansPlusPlus, help_heading = "Hyperparameters")]
+    pub init: KmeansInit,
+    /// Seeding of the modes [kmodes, kprototypes]
+    #[arg(long, value_enum, default_value_t = ModeInitArg::Cao, help_heading = "Hyperparameters")]
+    pub mode_init: ModeInitArg,
+    /// Iteration limit, the algorithm's own default if not given [kmeans, kmedoids, kmodes,
+    /// kprototypes, mean-shift, affinity-propagation, spectral]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub max_iter: Option<i32>,
+    /// Restarts keeping the best result [kmeans, kmodes, kprototypes]
+    #[arg(long, default_value_t = 10, help_heading = "Hyperparameters")]
+    pub retries: i32,
+    /// Columns holding integer category codes, counted from 0. All columns by default
+    /// for kmodes, required for kprototypes [kmodes, kprototypes]
+    #[arg(long, value_delimiter = ',', help_heading = "Hyperparameters")]
+    pub categorical: Vec<usize>,
+    /// Weight of a categorical mismatch, half the mean numeric standard deviation by
+    /// default [kprototypes]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub categorical_weight: Option<f32>,
+    /// Neighbourhood radius, estimated from the k-distance knee if not given [dbscan]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub epsilon: Option<f32>,
+    /// Neighbours, the point included, that make a core point. Twice the dimension for
+    /// dbscan and 10 for optics by default [dbscan, optics]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub min_points: Option<usize>,
+    /// Smallest cluster, 15 for hdbscan and --min-points for optics by default [hdbscan, optics]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub min_cluster_size: Option<usize>,
+    /// Neighbours used for the core distance, --min-cluster-size by default [hdbscan]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub min_samples: Option<usize>,
+    /// How clusters are picked from the condensed tree [hdbscan]
+    #[arg(long, value_enum, default_value_t = SelectionArg::ExcessOfMass, help_heading = "Hyperparameters")]
+    pub cluster_selection: SelectionArg,
+    /// Allow the root of the condensed tree as the only cluster [hdbscan]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub allow_single_cluster: bool,
+    /// Largest neighbourhood radius searched [optics]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub max_epsilon: Option<f32>,
+    /// Relative drop in reachability that delimits a cluster [optics]
+    #[arg(long, default_value_t = 0.05, help_heading = "Hyperparameters")]
+    pub xi: f32,
+    /// Kernel width, estimated from --quantile if not given [mean-shift]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub bandwidth: Option<f32>,
+    /// Quantile of the neighbour distances used to estimate the bandwidth [mean-shift]
+    #[arg(long, default_value_t = 0.3, help_heading = "Hyperparameters")]
+    pub quantile: f32,
+    /// Weighting of the points within the bandwidth [mean-shift]
+    #[arg(long, value_enum, default_value_t = KernelArg::Flat, help_heading = "Hyperparameters")]
+    pub kernel: KernelArg,
+    /// Seed from a grid of bins instead of every point [mean-shift]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub bin_seeding: bool,
+    /// Points a bin needs to become a seed [mean-shift]
+    #[arg(long, default_value_t = 1, help_heading = "Hyperparameters")]
+    pub min_bin_freq: usize,
+    /// Label points farther than the bandwidth from every center as noise [mean-shift]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub orphans_as_noise: bool,
+    /// In [0.5, 1) [affinity-propagation]
+    #[arg(long, default_value_t = 0.5, help_heading = "Hyperparameters")]
+    pub damping: f32,
+    /// Self-similarity, the median similarity by default. Larger gives more clusters
+    /// [affinity-propagation]
+    #[arg(long, allow_negative_numbers = true, help_heading = "Hyperparameters")]
+    pub preference: Option<f32>,
+    /// Iterations without change in the exemplars that end the fit [affinity-propagation]
+    #[arg(long, default_value_t = 15, help_heading = "Hyperparameters")]
+    pub convergence_iter: i32,
+    /// Similarity graph [spectral]
+    #[arg(long, value_enum, default_value_t = AffinityArg::Rbf, help_heading = "Hyperparameters")]
+    pub affinity: AffinityArg,
+    /// Width of the rbf affinity exp(-gamma d^2) [spectral]
+    #[arg(long, default_value_t = 1.0, help_heading = "Hyperparameters")]
+    pub gamma: f32,
+    /// Neighbours of the nearest-neighbours affinity [spectral]
+    #[arg(long, default_value_t = 10, help_heading = "Hyperparameters")]
+    pub neighbours: usize,
+    /// How the embedding is turned into labels [spectral]
+    #[arg(long, value_enum, default_value_t = AssignLabelsArg::Kmeans, help_heading = "Hyperparameters")]
+    pub assign_labels: AssignLabelsArg,
+    /// Largest subcluster radius [birch]
+    #[arg(long, default_value_t = 0.5, help_heading = "Hyperparameters")]
+    pub threshold: f32,
+    /// Entries per CF-tree node [birch]
+    #[arg(long, default_value_t = 50, help_heading = "Hyperparameters")]
+    pub branching_factor: usize,
+    /// Clustering of the subclusters into --k clusters [birch]
+    #[arg(long, value_enum, default_value_t = GlobalArg::Kmeans, help_heading = "Hyperparameters")]
+    pub global: GlobalArg,
+    /// Distance between merged clusters [agglomerative]
+    #[arg(long, value_enum, default_value_t = LinkageArg::Centroid, help_heading = "Hyperparameters")]
+    pub linkage: LinkageArg,
+    /// Cut the tree at this height instead of at --k clusters [agglomerative]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub distance_threshold: Option<f32>,
+    /// Only merge clusters connected in the k-nearest-neighbour graph [agglomerative]
+    #[arg(long, help_heading = "Hyperparameters")]
+    pub connectivity_neighbours: Option<usize>
+}
+
+/// Named per-point values written next to the labels, e.g. HDBScan probabilities.
+pub type Scores = Vec<(String, Vec<f32>)>;
+
+/// How a saved model labels new points.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Predictor {
+    /// Label of the nearest reference point, `NOISE` beyond `max_distance`.
+    Nearest { references: Vec<Vec<f32>>, labels: Vec<i32>, max_distance: Option<f32> },
+    /// Nearest prototype by squared distance over the `numeric` columns plus `gamma`
+    /// per mismatch in the `categorical` ones.
+    Prototypes { numeric: Vec<usize>, categorical: Vec<usize>, centroids: Vec<Vec<f32>>, modes: Vec<Vec<u32>>, gamma: f32 },
+    /// The algorithm has no notion of labelling unseen points.
+    Unsupported
+}
+
+/// Everything `fit --model` writes: the settings, the training labels and what is
+/// needed to label new points or draw the model's diagrams.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedModel {
+    pub algo: Algo,
+    pub params: Hyperparameters,
+    pub n_features: usize,
+    pub labels: Vec<i32>,
+    pub predictor: Predictor,
+    /// Merge tree of agglomerative models.
+    pub tree: Option<AggloClusterer>,
+    /// Cluster ordering of OPTICS models.
+    pub ordering: Vec<usize>,
+    /// Reachability of OPTICS models in point order, `None` where undefined.
+    pub reachability: Vec<Option<f32>>
+}
+
+impl SavedModel {
+
+    /// Checks that the predictor's arrays fit together, for model files edited by hand.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.predictor {
+            Predictor::Nearest { references, labels, .. } => {
+                if references.iter().any(|row| row.len() != self.n_features) {
+                    return Err(format!("every reference needs {} values", self.n_features));
+                }
+                if labels.len() != references.len() {
+                    return Err("need one label per reference".to_string());
+                }
+            }
+            Predictor::Prototypes { numeric, categorical, centroids, modes, .. } => {
+                if numeric.iter().chain(categorical.iter()).any(|&column| column >= self.n_features) {
+                    return Err(format!("column out of range for {} columns", self.n_features));
+                }
+                if centroids.len() != modes.len()
+                    || centroids.iter().any(|row| row.len() != numeric.len())
+                    || modes.iter().any(|row| row.len() != categorical.len()) {
+                    return Err("centroids and modes do not match the columns".to_string());
+                }
+            }
+            Predictor::Unsupported => {}
+        }
+        Ok(())
+    }
+
+    /// Centers to draw in scatter plots, empty without any.
+    pub fn centroids(&self) -> Array2<f32> {
+        match &self.predictor {
+            Predictor::Nearest { references, .. } if !matches!(self.algo, Algo::Dbscan | Algo::Birch) => to_array(references, self.n_features),
+            _ => Array2::zeros((0, self.n_features))
+        }
+    }
+
+    /// Distance of every row to its nearest reference, for nearest-reference predictors.
+    pub fn distances(&self, data: &Array2<f32>) -> Option<Vec<f32>> {
+        match &self.predictor {
+            Predictor::Nearest { references, .. } if !references.is_empty() => {
+                let references = to_array(references, self.n_features);
+                Some(data.rows()
+                    .into_iter()
+                    .map(|point| references.rows().into_iter().map(|reference| l2_view(point, reference)).fold(f32::INFINITY, f32::min))
+                    .collect())
+            }
+            _ => None
+        }
+    }
+
+    pub fn predict(&self, data: &Array2<f32>) -> Result<Vec<i32>, CliError> {
+        if data.shape()[1] != self.n_features {
+            return Err(CliError::InvalidInput(format!("the model was fitted on {} columns, got {}", self.n_features, data.shape()[1])));
+        }
+        match &self.predictor {
+            Predictor::Nearest { references, labels, max_distance } => {
+                let references = to_array(references, self.n_features);
+                Ok(data.rows()
+                    .into_iter()
+                    .map(|point| {
+                        let mut best = (NOISE, f32::INFINITY);
+                        for (reference, &label) in references.rows().into_iter().zip(labels.iter()) {
+                            let dist = l2_view(point, reference);
+                            if dist < best.1 {
+                                best = (label, dist);
+                            }
+                        }
+                        if max_distance.is_some_and(|max| best.1 > max) { NOISE } else { best.0 }
+                    })
+                    .collect())
+            }
+            Predictor::Prototypes { numeric, categorical, centroids, modes, gamma } => {
+                let numeric_data = data.select(Axis(1), numeric);
+                let codes = category_codes(data, categorical)?;
+                let centroids = to_array(centroids, numeric.len());
+                let modes = Array2::from_shape_vec((modes.len(), categorical.len()), modes.concat()).unwrap();
+                Ok((0..data.shape()[0])
+                    .map(|i| {
+                        let mut best = (0, f32::INFINITY);
+                        for c in 0..modes.shape()[0] {
+                            let diff = &numeric_data.row(i) - &centroids.row(c);
+                            let dist = diff.dot(&diff) + gamma * mismatches(codes.row(i), modes.row(c)) as f32;
+                            if dist < best.1 {
+                                best = (c, dist);
+                            }
+                        }
+                        best.0.to_i32().unwrap()
+                    })
+                    .collect())
+            }
+            Predictor::Unsupported => Err(CliError::Unsupported(format!("{:?} models cannot label new points", self.algo)))
+        }
+    }
+}
+
+/// Fits `algo` with `params` on `data`, returning the model and any named per-point
+/// scores. Hyperparameters outside their range are reported as `CliError::Usage`.
+pub fn fit(algo: Algo, params: &Hyperparameters, data: &Array2<f32>) -> Result<(SavedModel, Scores), CliError> {
+    let n_features = data.shape()[1];
+    if params.k < 0 {
+        return Err(CliError::Usage("--k must not be negative".to_string()));
+    }
+    let k = params.k.to_usize().unwrap();
+    let mut tree = None;
+    let mut ordering = Vec::new();
+    let mut reachability = Vec::new();
+    let mut scores = Vec::new();
+    let (labels, predictor) = match algo {
+        Algo::Kmeans => {
+            let mut model = Kmeans::new(data, params.k);
+            model.config_silhouette(params.accept);
+            model.set_initializer(match params.init {
+                KmeansInit::KmeansPlusPlus => "kmeans++",
+                KmeansInit::RandomChoice => "random_choice"
+            });
+            model.set_fitting_time(params.max_iter.unwrap_or(model.max_iter), params.retries);
+            model.set_max_centers(params.max_centers);
+            let labels = model.fit_predict(data)?;
+            (labels, nearest(&model.centroids, None, None))
+        }
+        Algo::Kmedoids => {
+            let mut model = KMedoids::new(k);
+            model.set_max_iter(params.max_iter.unwrap_or(model.max_iter));
+            let labels = model.fit_predict(data);
+            (labels, nearest(&model.centroids, None, None))
+        }
+        Algo::Kmodes => {
+            let categorical = if params.categorical.is_empty() { (0..n_features).collect() } else { params.categorical.clone() };
+            let codes = category_codes(data, &categorical)?;
+            let mut model = KModes::new(k);
+            model.set_init(mode_init(params.mode_init));
+            model.set_fitting_time(params.max_iter.unwrap_or(model.max_iter), params.retries);
+            let labels = model.fit_predict(&codes);
+            let predictor = prototypes(Vec::new(), categorical, &Array2::zeros((model.modes.shape()[0], 0)), &model.modes, 1.0);
+            (labels, predictor)
+        }
+        Algo::Kprototypes => {
+            if params.categorical.is_empty() {
+                return Err(CliError::Usage("kprototypes needs --categorical".to_string()));
+            }
+            let categorical = params.categorical.clone();
+            let numeric: Vec<usize> = (0..n_features).filter(|j| !categorical.contains(j)).collect();
+            let codes = category_codes(data, &categorical)?;
+            let mut model = KPrototypes::new(k);
+            model.set_init(mode_init(params.mode_init));
+            model.set_fitting_time(params.max_iter.unwrap_or(model.max_iter), params.retries);
+            if let Some(gamma) = params.categorical_weight {
+                model.set_gamma(gamma);
+            }
+            let labels = model.fit_predict(&data.select(Axis(1), &numeric), &codes);
+            let predictor = prototypes(numeric, categorical, &model.centroids, &model.modes, model.gamma.unwrap());
+            (labels, predictor)
+        }
+        Algo::Dbscan => {
+            let mut model = DBScan::new(data);
+            let (epsilon, min_points) = match (params.epsilon, params.min_points) {
+                (Some(epsilon), Some(min_points)) => (epsilon, min_points),
+                (epsilon, min_points) => {
+                    let estimate = estimate_parameters(data);
+                    (epsilon.unwrap_or(estimate.epsilon), min_points.unwrap_or(estimate.min_points))
+                }
+            };
+            if min_points == 0 {
+                return Err(CliError::Usage("--min-points must be at least 1".to_string()));
+            }
+            model.set_epsilon(epsilon);
+            model.set_min_points(min_points);
+            let labels = model.fit_predict(data);
+            (labels, nearest(&model.core_samples, Some(model.core_labels.clone()), Some(epsilon)))
+        }
+        Algo::Hdbscan => {
+            let mut model = HDBScan::new(params.min_cluster_size.unwrap_or(15));
+            if let Some(min_samples) = params.min_samples {
+                model.set_min_samples(min_samples);
+            }
+            model.set_cluster_selection(match params.cluster_selection {
+                SelectionArg::ExcessOfMass => ClusterSelection::ExcessOfMass,
+                SelectionArg::Leaf => ClusterSelection::Leaf
+            });
+            model.set_allow_single_cluster(params.allow_single_cluster);
+            let labels = model.fit_predict(data);
+            scores.push(("probability".to_string(), model.probabilities.clone()));
+            scores.push(("outlier_score".to_string(), model.outlier_scores.clone()));
+            (labels, Predictor::Unsupported)
+        }
+        Algo::Optics => {
+            if params.min_points == Some(0) {
+                return Err(CliError::Usage("--min-points must be at least 1".to_string()));
+            }
+            if !(params.xi > 0.0 && params.xi < 1.0) {
+                return Err(CliError::Usage(format!("--xi must be in (0, 1), got {}", params.xi)));
+            }
+            let mut model = Optics::new(params.min_points.unwrap_or(10));
+            if let Some(max_epsilon) = params.max_epsilon {
+                model.set_max_epsilon(max_epsilon);
+            }
+            model.set_xi(params.xi);
+            if let Some(min_cluster_size) = params.min_cluster_size {
+                model.set_min_cluster_size(min_cluster_size);
+            }
+            let labels = model.fit_predict(data);
+            ordering = model.ordering.clone();
+            reachability = model.reachability.iter().map(|&r| Some(r).filter(|r| r.is_finite())).collect();
+            (labels, Predictor::Unsupported)
+        }
+        Algo::MeanShift => {
+            let mut model = MeanShift::new(data);
+            let bandwidth = match params.bandwidth {
+                Some(bandwidth) if bandwidth > 0.0 => bandwidth,
+                Some(bandwidth) => return Err(CliError::Usage(format!("--bandwidth must be positive, got {}", bandwidth))),
+                None if params.quantile > 0.0 && params.quantile <= 1.0 => estimate_bandwidth(data, params.quantile),
+                None => return Err(CliError::Usage(format!("--quantile must be in (0, 1], got {}", params.quantile)))
+            };
+            model.set_bandwidth(bandwidth);
+            model.set_kernel(match params.kernel {
+                KernelArg::Flat => Kernel::Flat,
+                KernelArg::Gaussian => Kernel::Gaussian
+            });
+            model.set_bin_seeding(params.bin_seeding, params.min_bin_freq);
+            model.set_cluster_all(!params.orphans_as_noise);
+            if let Some(max_iter) = params.max_iter {
+                model.set_max_iter(max_iter);
+            }
+            let labels = model.fit_predict(data);
+            (labels, nearest(&model.centroids, None, Some(bandwidth).filter(|_| params.orphans_as_noise)))
+        }
+        Algo::AffinityPropagation => {
+            let mut model = AffinityPropagation::new();
+            model.set_damping(params.damping).map_err(|e| CliError::Usage(format!("--damping: {}", e)))?;
+            if let Some(preference) = params.preference {
+                model.set_preference(preference);
+            }
+            model.set_fitting_time(params.max_iter.unwrap_or(model.max_iter), params.convergence_iter);
+            let labels = model.fit_predict(data);
+            (labels, nearest(&model.centroids, None, None))
+        }
+        Algo::Spectral => {
+            if params.affinity == AffinityArg::NearestNeighbours {
+                check_neighbours("--neighbours", params.neighbours, data)?;
+            }
+            let mut model = SpectralClustering::new(data, params.k);
+            model.set_affinity(match params.affinity {
+                AffinityArg::Rbf => Affinity::Rbf { gamma: params.gamma },
+                AffinityArg::NearestNeighbours => Affinity::NearestNeighbours { k: params.neighbours }
+            });
+            model.set_assign_labels(match params.assign_labels {
+                AssignLabelsArg::Kmeans => AssignLabels::Kmeans,
+                AssignLabelsArg::Discretize => AssignLabels::Discretize
+            });
+            model.set_max_centers(params.max_centers);
+            if let Some(max_iter) = params.max_iter {
+                model.max_iter = max_iter;
+            }
+            (model.fit_predict(data)?, Predictor::Unsupported)
+        }
+        Algo::Birch => {
+            if params.threshold.is_nan() || params.threshold <= 0.0 {
+                return Err(CliError::Usage(format!("--threshold must be positive, got {}", params.threshold)));
+            }
+            if params.branching_factor < 2 {
+                return Err(CliError::Usage(format!("--branching-factor must be at least 2, got {}", params.branching_factor)));
+            }
+            let mut model = Birch::new(match params.global {
+                GlobalArg::Subclusters => GlobalClustering::Subclusters,
+                GlobalArg::Kmeans => GlobalClustering::Kmeans(k),
+                GlobalArg::Agglomerative => GlobalClustering::Agglomerative(k)
+            });
+            model.set_threshold(params.threshold);
+            model.set_branching_factor(params.branching_factor);
+            let labels = model.fit_predict(data);
+            (labels, nearest(&model.subcluster_centers, Some(model.subcluster_labels.clone()), None))
+        }
+        Algo::Agglomerative => {
+            let mut model = AggloClusterer::new();
+            model.set_linkage(match params.linkage {
+                LinkageArg::Single => Linkage::Single,
+                LinkageArg::Complete => Linkage::Complete,
+                LinkageArg::Average => Linkage::Average,
+                LinkageArg::Weighted => Linkage::Weighted,
+                LinkageArg::Ward => Linkage::Ward,
+                LinkageArg::Centroid => Linkage::Centroid,
+                LinkageArg::Median => Linkage::Median
+            });
+            if let Some(neighbours) = params.connectivity_neighbours {
+                check_neighbours("--connectivity-neighbours", neighbours, data)?;
+                model.set_connectivity(knn_graph(data, neighbours));
+            }
+            model.fit(data);
+            let labels = match params.distance_threshold {
+                Some(threshold) => model.cut_at_distance(threshold),
+                None => model.cut_at_k(k)
+            };
+            tree = Some(model);
+            (labels, Predictor::Unsupported)
+        }
+    };
+    let saved = SavedModel {
+        algo,
+        params: params.clone(),
+        n_features,
+        labels,
+        predictor,
+        tree,
+        ordering,
+        reachability
+    };
+    Ok((saved, scores))
+}
+
+/// Neighbour counts must leave at least one other point out of reach.
+fn check_neighbours(flag: &str, neighbours: usize, data: &Array2<f32>) -> Result<(), CliError> {
+    let n = data.shape()[0];
+    if neighbours == 0 || neighbours >= n {
+        return Err(CliError::Usage(format!("{} must be between 1 and {} for {} points, got {}", flag, n.saturating_sub(1), n, neighbours)));
+    }
+    Ok(())
+}
+
+/// Nearest-reference predictor over the rows of `references`, labelled `0..` by default.
+fn nearest(references: &Array2<f32>, labels: Option<Vec<i32>>, max_distance: Option<f32>) -> Predictor {
+    Predictor::Nearest {
+        references: references.rows().into_iter().map(|row| row.to_vec()).collect(),
+        labels: labels.unwrap_or_else(|| (0..references.shape()[0]).map(|i| i.to_i32().unwrap()).collect()),
+        max_distance
+    }
+}
+
+fn prototypes(numeric: Vec<usize>, categorical: Vec<usize>, centroids: &Array2<f32>, modes: &Array2<u32>, gamma: f32) -> Predictor {
+    Predictor::Prototypes {
+        numeric,
+        categorical,
+        centroids: centroids.rows().into_iter().map(|row| row.to_vec()).collect(),
+        modes: modes.rows().into_iter().map(|row| row.to_vec()).collect(),
+        gamma
+    }
+}
+
+fn mode_init(init: ModeInitArg) -> ModeInit {
+    match init {
+        ModeInitArg::Huang => ModeInit::Huang,
+        ModeInitArg::Cao => ModeInit::Cao
+    }
+}
+
+/// The given columns as category codes, which must be non-negative integers.
+fn category_codes(data: &Array2<f32>, columns: &[usize]) -> Result<Array2<u32>, CliError> {
+    if let Some(&column) = columns.iter().find(|&&j| j >= data.shape()[1]) {
+        return Err(CliError::Usage(format!("categorical column {} out of range for {} columns", column, data.shape()[1])));
+    }
+    let selected = data.select(Axis(1), columns);
+    if let Some(value) = selected.iter().find(|&&value| value < 0.0 || value.fract() != 0.0 || value > u32::MAX as f32) {
+        return Err(CliError::InvalidInput(format!("category codes must be non-negative integers, found {}", value)));
+    }
+    Ok(selected.mapv(|value| value as u32))
+}
+
+fn to_array(rows: &[Vec<f32>], width: usize) -> Array2<f32> {
+    Array2::from_shape_vec((rows.len(), width), rows.concat()).unwrap()
+}
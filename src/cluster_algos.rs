@@ -1,3 +1,12 @@
 pub mod lloyd;
 pub mod agglomerative;
-pub mod dbscan;
\ No newline at end of file
+pub mod dbscan;
+pub mod hdbscan;
+pub mod optics;
+pub mod mean_shift;
+pub mod affinity_propagation;
+pub mod spectral;
+pub mod birch;
+pub mod kmedoids;
+pub mod kmodes;
+pub mod error;
\ No newline at end of file
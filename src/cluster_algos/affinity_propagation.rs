@@ -0,0 +1,303 @@
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::dbscan::NOISE;
+use crate::cluster_algos::error::ClusterError;
+
+pub struct AffinityPropagation {
+    pub damping: f32,
+    pub max_iter: i32,
+    pub convergence_iter: i32,
+    /// Self-similarity of every point, the median similarity if `None`.
+    /// Larger values give more exemplars.
+    pub preference: Option<f32>,
+    /// Seed of the noise that breaks ties between equally good exemplars.
+    pub seed: u64,
+    /// Row indices of the exemplars, cluster `i` is represented by `exemplars[i]`.
+    pub exemplars: Vec<usize>,
+    /// The exemplar rows, only filled when fitted on feature vectors.
+    pub centroids: Array2<f32>,
+    pub partition: Vec<i32>,
+    pub n_iter: i32,
+    /// False if `max_iter` ran out first, every point is then labelled `NOISE`.
+    pub converged: bool
+}
+
+impl AffinityPropagation {
+
+    pub fn new() -> AffinityPropagation {
+        AffinityPropagation {
+            damping: 0.5,
+            max_iter: 200,
+            convergence_iter: 15,
+            preference: None,
+            seed: 0,
+            exemplars: Vec::new(),
+            centroids: Array2::zeros((0, 0)),
+            partition: Vec::new(),
+            n_iter: 0,
+            converged: false
+        }
+    }
+
+    /// `damping` must lie in [0.5, 1).
+    pub fn set_damping(&mut self, damping: f32) -> Result<(), ClusterError> {
+        if !(0.5..1.0).contains(&damping) {
+            return Err(ClusterError::InvalidParameter(format!("damping must be in [0.5, 1), got {}", damping)));
+        }
+        self.damping = damping;
+        Ok(())
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn set_preference(&mut self, preference: f32) {
+        self.preference = Some(preference);
+    }
+
+    /// Stops once the exemplars have not changed for `convergence_iter` iterations.
+    pub fn set_fitting_time(&mut self, max_iter: i32, convergence_iter: i32) {
+        self.max_iter = max_iter;
+        self.convergence_iter = convergence_iter;
+    }
+
+    /// Clusters feature vectors using the negative squared euclidean distance as similarity.
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        let n = data.shape()[0];
+        let mut similarity = Array2::<f32>::zeros((n, n));
+        for i in 0..n {
+            for j in i + 1..n {
+                let s = -square(l2_view(data.row(i), data.row(j)));
+                similarity[[i, j]] = s;
+                similarity[[j, i]] = s;
+            }
+        }
+        self.fit_predict_precomputed(&similarity);
+        self.centroids = data.select(Axis(0), &self.exemplars);
+        self.partition.clone()
+    }
+
+    /// Clusters from a square matrix where `similarity[[i, k]]` is how well `k`
+    /// would serve as exemplar for `i`. The diagonal is replaced by the preference.
+    /// Without convergence within `max_iter` there are no exemplars and every point
+    /// is `NOISE`, as the exemplars of the last iteration need not be stable.
+    pub fn fit_predict_precomputed(&mut self, similarity: &Array2<f32>) -> Vec<i32> {
+        let n = similarity.shape()[0];
+        assert_eq!(n, similarity.shape()[1], "similarity matrix must be square");
+        self.centroids = Array2::zeros((0, 0));
+        self.n_iter = 0;
+        self.converged = false;
+        if n == 0 {
+            self.exemplars = Vec::new();
+            self.partition = Vec::new();
+            return Vec::new();
+        }
+        let mut s = similarity.to_owned();
+        let preference = self.preference.unwrap_or_else(|| median_off_diagonal(similarity));
+        for i in 0..n {
+            s[[i, i]] = preference;
+        }
+        // tiny noise breaks ties between equally good exemplars, which would otherwise oscillate
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        for value in s.iter_mut() {
+            *value += (f32::EPSILON * value.abs() + f32::MIN_POSITIVE * 100.0) * rng.gen_range(0.0..1.0);
+        }
+
+        let mut responsibility = Array2::<f32>::zeros((n, n));
+        let mut availability = Array2::<f32>::zeros((n, n));
+        let history_len = self.convergence_iter.max(1).to_usize().unwrap();
+        let mut history: Vec<Vec<bool>> = Vec::with_capacity(history_len);
+        let mut is_exemplar = vec![false; n];
+        for iteration in 0..self.max_iter {
+            self.n_iter = iteration + 1;
+            self.update_responsibility(&s, &availability, &mut responsibility);
+            self.update_availability(&responsibility, &mut availability);
+
+            is_exemplar = (0..n).map(|k| availability[[k, k]] + responsibility[[k, k]] > 0.0).collect();
+            if history.len() == history_len {
+                history.remove(0);
+            }
+            history.push(is_exemplar.clone());
+            if history.len() == history_len
+                && is_exemplar.iter().any(|&e| e)
+                && history.iter().all(|past| *past == is_exemplar) {
+                self.converged = true;
+                break;
+            }
+        }
+
+        let mut exemplars: Vec<usize> = (0..n).filter(|&k| is_exemplar[k]).collect();
+        if exemplars.is_empty() || !self.converged {
+            exemplars.clear();
+            self.exemplars = exemplars;
+            self.partition = vec![NOISE; n];
+            return self.partition.clone();
+        }
+        let mut labels = assign_to_exemplars(&s, &exemplars);
+        // move every exemplar to the member that is most similar to the rest of its cluster
+        for (k, exemplar) in exemplars.iter_mut().enumerate() {
+            let members: Vec<usize> = (0..n).filter(|&i| labels[i] == k).collect();
+            let mut best = f32::NEG_INFINITY;
+            for &candidate in members.iter() {
+                let total: f32 = members.iter().map(|&i| s[[i, candidate]]).sum();
+                if total > best {
+                    best = total;
+                    *exemplar = candidate;
+                }
+            }
+        }
+        labels = assign_to_exemplars(&s, &exemplars);
+        self.exemplars = exemplars;
+        self.partition = labels.into_iter().map(|k| k.to_i32().unwrap()).collect();
+        self.partition.clone()
+    }
+
+    fn update_responsibility(&self, s: &Array2<f32>, availability: &Array2<f32>, responsibility: &mut Array2<f32>) {
+        let n = s.shape()[0];
+        for i in 0..n {
+            let mut first = f32::NEG_INFINITY;
+            let mut second = f32::NEG_INFINITY;
+            let mut first_idx = 0;
+            for k in 0..n {
+                let value = availability[[i, k]] + s[[i, k]];
+                if value > first {
+                    second = first;
+                    first = value;
+                    first_idx = k;
+                }
+                else if value > second {
+                    second = value;
+                }
+            }
+            for k in 0..n {
+                let competitor = if k == first_idx { second } else { first };
+                let new = s[[i, k]] - competitor;
+                responsibility[[i, k]] = self.damping * responsibility[[i, k]] + (1.0 - self.damping) * new;
+            }
+        }
+    }
+
+    fn update_availability(&self, responsibility: &Array2<f32>, availability: &mut Array2<f32>) {
+        let n = responsibility.shape()[0];
+        for k in 0..n {
+            let positive_sum: f32 = (0..n)
+                .filter(|&i| i != k)
+                .map(|i| responsibility[[i, k]].max(0.0))
+                .sum();
+            for i in 0..n {
+                let new = if i == k {
+                    positive_sum
+                }
+                else {
+                    (responsibility[[k, k]] + positive_sum - responsibility[[i, k]].max(0.0)).min(0.0)
+                };
+                availability[[i, k]] = self.damping * availability[[i, k]] + (1.0 - self.damping) * new;
+            }
+        }
+    }
+}
+
+impl Default for AffinityPropagation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Index into `exemplars` of the most similar exemplar for every point,
+/// exemplars are always assigned to themselves.
+fn assign_to_exemplars(s: &Array2<f32>, exemplars: &[usize]) -> Vec<usize> {
+    let mut labels: Vec<usize> = (0..s.shape()[0])
+        .map(|i| {
+            let mut best = 0;
+            for (k, &exemplar) in exemplars.iter().enumerate() {
+                if s[[i, exemplar]] > s[[i, exemplars[best]]] {
+                    best = k;
+                }
+            }
+            best
+        })
+        .collect();
+    for (k, &exemplar) in exemplars.iter().enumerate() {
+        labels[exemplar] = k;
+    }
+    labels
+}
+
+fn median_off_diagonal(similarity: &Array2<f32>) -> f32 {
+    let n = similarity.shape()[0];
+    let mut values: Vec<f32> = Vec::with_capacity(n * n.saturating_sub(1));
+    for i in 0..n {
+        for j in 0..n {
+            if i != j {
+                values.push(similarity[[i, j]]);
+            }
+        }
+    }
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16 irregularly spread points within 0.5 of `(x, y)`.
+    fn blob(x: f32, y: f32) -> Array2<f32> {
+        Array2::from_shape_fn((16, 2), |(i, j)| {
+            let offset = ((i * 7 + j * 3) % 11) as f32 / 22.0;
+            if j == 0 { x + offset } else { y + offset * (i % 3) as f32 / 2.0 }
+        })
+    }
+
+    fn two_blobs() -> Array2<f32> {
+        ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap()
+    }
+
+    #[test]
+    fn separates_two_blobs() {
+        let data = two_blobs();
+        let mut model = AffinityPropagation::new();
+        model.set_damping(0.9).unwrap();
+        let labels = model.fit_predict(&data);
+        assert!(model.converged);
+        assert_eq!(model.exemplars.len(), 2);
+        assert!(labels[..16].iter().all(|&label| label == labels[0]));
+        assert!(labels[16..].iter().all(|&label| label == labels[16]));
+        assert_ne!(labels[0], labels[16]);
+    }
+
+    #[test]
+    fn same_seed_gives_same_labels() {
+        let data = two_blobs();
+        let mut first = AffinityPropagation::new();
+        let mut second = AffinityPropagation::new();
+        assert_eq!(first.fit_predict(&data), second.fit_predict(&data));
+        assert_eq!(first.exemplars, second.exemplars);
+    }
+
+    #[test]
+    fn no_convergence_labels_noise() {
+        let data = two_blobs();
+        let mut model = AffinityPropagation::new();
+        model.set_fitting_time(3, 15);
+        assert_eq!(model.fit_predict(&data), vec![NOISE; 32]);
+        assert!(!model.converged);
+        assert!(model.exemplars.is_empty());
+    }
+
+    #[test]
+    fn rejects_damping_out_of_range() {
+        let mut model = AffinityPropagation::new();
+        assert!(model.set_damping(0.4).is_err());
+        assert!(model.set_damping(1.0).is_err());
+        assert_eq!(model.set_damping(0.9), Ok(()));
+        assert_eq!(model.damping, 0.9);
+    }
+}
\ No newline at end of file
@@ -1,217 +1,1140 @@
 use ndarray::prelude::*;
 use num::ToPrimitive;
 use crate::utils::mathfuncs::*;
-use std::rc::Rc;
+use crate::utils::distances::CondensedMatrix;
+use crate::cluster_algos::dbscan::NOISE;
+use crate::cluster_algos::error::ClusterError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashMap};
 
+/// How the distance between two clusters is derived from their members.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Linkage {
+    /// Closest pair of members.
+    Single,
+    /// Farthest pair of members.
+    Complete,
+    /// Mean distance over all pairs of members (UPGMA).
+    Average,
+    /// Mean of the distances of the two merged halves (WPGMA).
+    Weighted,
+    /// Increase in within-cluster variance caused by the merge.
+    Ward,
+    /// Distance between the cluster means (UPGMC).
+    Centroid,
+    /// Distance between the midpoints of the merged halves (WPGMC).
+    Median
+}
+
+impl Linkage {
+    /// Ward, centroid and median linkage are updated on squared euclidean distances.
+    pub fn uses_squared(&self) -> bool {
+        matches!(self, Linkage::Ward | Linkage::Centroid | Linkage::Median)
+    }
+
+    /// Lance-Williams update: the distance from cluster `k` to the union of `i` and `j`.
+    pub fn update(&self, d_ki: f32, d_kj: f32, d_ij: f32, n_i: usize, n_j: usize, n_k: usize) -> f32 {
+        let (n_i, n_j, n_k) = (n_i as f32, n_j as f32, n_k as f32);
+        match self {
+            Linkage::Single => d_ki.min(d_kj),
+            Linkage::Complete => d_ki.max(d_kj),
+            Linkage::Average => (n_i * d_ki + n_j * d_kj) / (n_i + n_j),
+            Linkage::Weighted => 0.5 * (d_ki + d_kj),
+            Linkage::Ward => ((n_i + n_k) * d_ki + (n_j + n_k) * d_kj - n_k * d_ij) / (n_i + n_j + n_k),
+            Linkage::Centroid => {
+                let total = n_i + n_j;
+                (n_i * d_ki + n_j * d_kj) / total - n_i * n_j * d_ij / (total * total)
+            }
+            Linkage::Median => 0.5 * d_ki + 0.5 * d_kj - 0.25 * d_ij
+        }
+    }
+
+    /// Converts a distance maintained by `update` back to a euclidean merge height.
+    pub fn height(&self, distance: f32) -> f32 {
+        if self.uses_squared() { distance.max(0.0).sqrt() } else { distance }
+    }
+}
+
+/// One merge of the dendrogram. Nodes below `n` are data points, merge `i` creates
+/// node `n + i` from `left` and `right` at euclidean `height`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Merge {
+    pub left: usize,
+    pub right: usize,
+    pub height: f32,
+    pub size: usize
+}
+
+/// The `n - 1` merges of the hierarchy over the rows of `data` in merge order.
+/// Single linkage is read off a minimum spanning tree, the other reducible linkages
+/// use the nearest-neighbour chain, centroid and median linkage a priority queue
+/// of nearest neighbours. Everything but single linkage keeps a condensed matrix.
+pub fn linkage_tree(data: &Array2<f32>, linkage: Linkage) -> Vec<Merge> {
+    let n = data.shape()[0];
+    if n < 2 {
+        return Vec::new();
+    }
+    if linkage == Linkage::Single {
+        let steps = minimum_spanning_tree(n, |i, j| l2_view(data.row(i), data.row(j)));
+        return label_steps(steps, n, linkage, false);
+    }
+    reduce(CondensedMatrix::euclidean(data), linkage)
+}
+
+/// Like `linkage_tree` on precomputed distances. Ward, centroid and median linkage
+/// assume the distances are euclidean.
+pub fn linkage_tree_precomputed(distances: &CondensedMatrix, linkage: Linkage) -> Vec<Merge> {
+    let n = distances.n_points();
+    if n < 2 {
+        return Vec::new();
+    }
+    if linkage == Linkage::Single {
+        let steps = minimum_spanning_tree(n, |i, j| distances.get(i, j));
+        return label_steps(steps, n, linkage, false);
+    }
+    reduce(distances.clone(), linkage)
+}
+
+fn reduce(mut distances: CondensedMatrix, linkage: Linkage) -> Vec<Merge> {
+    let n = distances.n_points();
+    if linkage.uses_squared() {
+        distances.values.iter_mut().for_each(|d| *d = square(*d));
+    }
+    match linkage {
+        Linkage::Centroid | Linkage::Median => label_steps(generic_linkage(distances, linkage), n, linkage, true),
+        _ => label_steps(nn_chain(distances, linkage), n, linkage, false)
+    }
+}
+
+/// Prim's algorithm on the implicit complete graph, `n - 1` edges in O(n) memory.
+fn minimum_spanning_tree(n: usize, distance: impl Fn(usize, usize) -> f32) -> Vec<(usize, usize, f32)> {
+    let mut edges = Vec::with_capacity(n - 1);
+    let mut in_tree = vec![false; n];
+    let mut best = vec![f32::INFINITY; n];
+    let mut from = vec![0; n];
+    let mut current = 0;
+    in_tree[0] = true;
+    for _ in 1..n {
+        let mut next = usize::MAX;
+        let mut min = f32::INFINITY;
+        for j in 0..n {
+            if in_tree[j] {
+                continue;
+            }
+            let dist = distance(current, j);
+            if dist < best[j] {
+                best[j] = dist;
+                from[j] = current;
+            }
+            if best[j] < min || next == usize::MAX {
+                min = best[j];
+                next = j;
+            }
+        }
+        in_tree[next] = true;
+        edges.push((from[next], next, best[next]));
+        current = next;
+    }
+    edges
+}
+
+/// Follows nearest neighbours until two clusters are each other's nearest neighbour
+/// and merges them. Only valid for linkages that never get closer to other clusters
+/// by merging, the merges come out of order.
+fn nn_chain(mut distances: CondensedMatrix, linkage: Linkage) -> Vec<(usize, usize, f32)> {
+    let n = distances.n_points();
+    // ascending slots of the clusters not merged away yet
+    let mut active: Vec<usize> = (0..n).collect();
+    let mut sizes = vec![1; n];
+    let mut chain: Vec<usize> = Vec::with_capacity(n);
+    let mut steps = Vec::with_capacity(n - 1);
+    for _ in 1..n {
+        if chain.is_empty() {
+            chain.push(active[0]);
+        }
+        let (a, b, dist) = loop {
+            let a = chain[chain.len() - 1];
+            // prefer the previous chain element on ties so the chain cannot cycle
+            let previous = if chain.len() > 1 { Some(chain[chain.len() - 2]) } else { None };
+            let (mut b, mut min) = match previous {
+                Some(p) => (p, distances.get(a, p)),
+                None => (usize::MAX, f32::INFINITY)
+            };
+            distances.for_each_in_row(a, &active, |x, dist| {
+                if dist < min || b == usize::MAX {
+                    min = dist;
+                    b = x;
+                }
+            });
+            if previous == Some(b) {
+                chain.truncate(chain.len() - 2);
+                break (a, b, min);
+            }
+            chain.push(b);
+        };
+        let (size_a, size_b) = (sizes[a], sizes[b]);
+        for &k in active.iter() {
+            if k != a && k != b {
+                let (ka, kb) = (distances.index(k, a), distances.index(k, b));
+                distances.values[kb] = linkage.update(distances.values[ka], distances.values[kb], dist, size_a, size_b, sizes[k]);
+            }
+        }
+        active.remove(active.binary_search(&a).unwrap());
+        sizes[b] = size_a + size_b;
+        steps.push((a, b, dist));
+    }
+    steps
+}
+
+#[derive(PartialEq)]
+struct Candidate {
+    distance: f32,
+    slot: usize,
+    other: usize
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // reversed so that `BinaryHeap` pops the closest pair first
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.distance.total_cmp(&self.distance)
+            .then(other.slot.cmp(&self.slot))
+            .then(other.other.cmp(&self.other))
+    }
+}
+
+/// Keeps the nearest neighbour of every cluster in a heap with lazy deletion. Works for
+/// linkages where merging can bring clusters closer together, the merges come out in order.
+fn generic_linkage(mut distances: CondensedMatrix, linkage: Linkage) -> Vec<(usize, usize, f32)> {
+    let n = distances.n_points();
+    let mut active: Vec<usize> = (0..n).collect();
+    let mut is_active = vec![true; n];
+    let mut sizes = vec![1; n];
+    let mut neighbour = vec![0; n];
+    let mut min_dist = vec![f32::INFINITY; n];
+    let mut heap = BinaryHeap::with_capacity(2 * n);
+    let nearest = |distances: &CondensedMatrix, active: &[usize], i: usize| {
+        let mut best = (usize::MAX, f32::INFINITY);
+        distances.for_each_in_row(i, active, |j, dist| {
+            if dist < best.1 || best.0 == usize::MAX {
+                best = (j, dist);
+            }
+        });
+        best
+    };
+    for i in 0..n {
+        (neighbour[i], min_dist[i]) = nearest(&distances, &active, i);
+        heap.push(Candidate { distance: min_dist[i], slot: i, other: neighbour[i] });
+    }
+    let mut steps = Vec::with_capacity(n - 1);
+    for _ in 1..n {
+        let a = loop {
+            let candidate = heap.pop().expect("every active cluster has a candidate");
+            let slot = candidate.slot;
+            if is_active[slot] && candidate.other == neighbour[slot] && is_active[candidate.other]
+                && candidate.distance.to_bits() == min_dist[slot].to_bits() {
+                break slot;
+            }
+        };
+        let (b, dist) = (neighbour[a], min_dist[a]);
+        let (size_a, size_b) = (sizes[a], sizes[b]);
+        for &k in active.iter() {
+            if k != a && k != b {
+                let (ka, kb) = (distances.index(k, a), distances.index(k, b));
+                distances.values[kb] = linkage.update(distances.values[ka], distances.values[kb], dist, size_a, size_b, sizes[k]);
+            }
+        }
+        active.remove(active.binary_search(&a).unwrap());
+        is_active[a] = false;
+        sizes[b] = size_a + size_b;
+        steps.push((a, b, dist));
+        if active.len() == 1 {
+            break;
+        }
+        (neighbour[b], min_dist[b]) = nearest(&distances, &active, b);
+        heap.push(Candidate { distance: min_dist[b], slot: b, other: neighbour[b] });
+        for &k in active.iter() {
+            if k == b {
+                continue;
+            }
+            if neighbour[k] == a || neighbour[k] == b {
+                (neighbour[k], min_dist[k]) = nearest(&distances, &active, k);
+            }
+            else if distances.get(k, b) < min_dist[k] {
+                (neighbour[k], min_dist[k]) = (b, distances.get(k, b));
+            }
+            else {
+                continue;
+            }
+            heap.push(Candidate { distance: min_dist[k], slot: k, other: neighbour[k] });
+        }
+    }
+    steps
+}
+
+/// Symmetric graph linking every row of `data` to its `k` nearest other rows.
+pub fn knn_graph(data: &Array2<f32>, k: usize) -> Vec<Vec<usize>> {
+    let n = data.shape()[0];
+    let mut graph = vec![Vec::new(); n];
+    for i in 0..n {
+        let mut candidates: Vec<(f32, usize)> = (0..n)
+            .filter(|&j| j != i)
+            .map(|j| (l2_view(data.row(i), data.row(j)), j))
+            .collect();
+        if k < candidates.len() {
+            candidates.select_nth_unstable_by(k, |a, b| a.0.total_cmp(&b.0));
+        }
+        for &(_, j) in candidates.iter().take(k) {
+            graph[i].push(j);
+            graph[j].push(i);
+        }
+    }
+    for neighbours in graph.iter_mut() {
+        neighbours.sort_unstable();
+        neighbours.dedup();
+    }
+    graph
+}
+
+/// Graph linking all rows of `data` closer than `radius` to each other.
+pub fn radius_graph(data: &Array2<f32>, radius: f32) -> Vec<Vec<usize>> {
+    let n = data.shape()[0];
+    let mut graph = vec![Vec::new(); n];
+    for i in 0..n {
+        for j in i + 1..n {
+            if l2_view(data.row(i), data.row(j)) <= radius {
+                graph[i].push(j);
+                graph[j].push(i);
+            }
+        }
+    }
+    graph
+}
+
+/// 4-neighbourhood of the pixels of a `rows` x `cols` image stored row by row.
+pub fn grid_graph(rows: usize, cols: usize) -> Vec<Vec<usize>> {
+    let mut graph = vec![Vec::new(); rows * cols];
+    for r in 0..rows {
+        for c in 0..cols {
+            let i = r * cols + c;
+            if c + 1 < cols {
+                graph[i].push(i + 1);
+                graph[i + 1].push(i);
+            }
+            if r + 1 < rows {
+                graph[i].push(i + cols);
+                graph[i + cols].push(i);
+            }
+        }
+    }
+    graph
+}
+
+/// Like `linkage_tree`, but only clusters joined by an edge of `connectivity` may merge.
+/// Single, complete, average and weighted linkage only look at the distances along edges,
+/// ward, centroid and median linkage use the cluster centers. Disconnected components
+/// are only joined once each has merged into one cluster, along an edge between their
+/// closest points and no lower than the merges before.
+pub fn constrained_linkage_tree(data: &Array2<f32>, linkage: Linkage, connectivity: &[Vec<usize>]) -> Vec<Merge> {
+    let n = data.shape()[0];
+    assert_eq!(connectivity.len(), n, "connectivity needs one adjacency list per point");
+    if n < 2 {
+        return Vec::new();
+    }
+    let distance = |i: usize, j: usize| {
+        let dist = l2_view(data.row(i), data.row(j));
+        if linkage.uses_squared() { square(dist) } else { dist }
+    };
+    // distance and number of underlying edges to every adjacent cluster
+    let mut adjacency: Vec<HashMap<usize, (f32, usize)>> = vec![HashMap::new(); n];
+    for (i, neighbours) in connectivity.iter().enumerate() {
+        for &j in neighbours.iter().filter(|&&j| j != i) {
+            assert!(j < n, "connectivity refers to point {} out of {}", j, n);
+            adjacency[i].insert(j, (distance(i, j), 1));
+            adjacency[j].insert(i, (distance(i, j), 1));
+        }
+    }
+    let mut bridges = component_bridges(data, &adjacency);
+    let mut centers = data.clone();
+    let mut sizes = vec![1; n];
+    let mut active = vec![true; n];
+    let mut heap = BinaryHeap::new();
+    for (i, neighbours) in adjacency.iter().enumerate() {
+        for (&j, &(dist, _)) in neighbours.iter().filter(|(&j, _)| i < j) {
+            heap.push(Candidate { distance: dist, slot: i, other: j });
+        }
+    }
+    let mut owners: Vec<usize> = (0..n).collect();
+    let mut highest = f32::NEG_INFINITY;
+    let mut bridged = false;
+    let mut steps = Vec::with_capacity(n - 1);
+    loop {
+        let Some(Candidate { distance: dist, slot: a, other: b }) = heap.pop() else {
+            if bridges.is_empty() {
+                break;
+            }
+            // every component has merged into a single cluster, link them up
+            bridged = true;
+            for (i, j) in std::mem::take(&mut bridges) {
+                let (a, b) = (find(&mut owners, i), find(&mut owners, j));
+                let dist = match linkage {
+                    Linkage::Ward => {
+                        let gap = l2_view(centers.row(a), centers.row(b));
+                        2.0 * (sizes[a] * sizes[b]) as f32 / (sizes[a] + sizes[b]) as f32 * square(gap)
+                    }
+                    Linkage::Centroid | Linkage::Median => square(l2_view(centers.row(a), centers.row(b))),
+                    _ => distance(i, j)
+                };
+                adjacency[a].insert(b, (dist, 1));
+                adjacency[b].insert(a, (dist, 1));
+                heap.push(Candidate { distance: dist, slot: a.min(b), other: a.max(b) });
+            }
+            continue;
+        };
+        let current = if active[a] && active[b] { adjacency[a].get(&b) } else { None };
+        if current.is_none_or(|&(d, _)| d.to_bits() != dist.to_bits()) {
+            continue;
+        }
+        let (size_a, size_b) = (sizes[a], sizes[b]);
+        let merged = match linkage {
+            Linkage::Median => (&centers.row(a) + &centers.row(b)) * 0.5,
+            _ => (&centers.row(a) * size_a as f32 + &centers.row(b) * size_b as f32) / (size_a + size_b) as f32
+        };
+        centers.row_mut(b).assign(&merged);
+        sizes[b] = size_a + size_b;
+        active[a] = false;
+        let links_a = std::mem::take(&mut adjacency[a]);
+        let mut links_b = std::mem::take(&mut adjacency[b]);
+        links_b.remove(&a);
+        for (k, link_a) in links_a.into_iter().filter(|&(k, _)| k != b) {
+            let link = match links_b.get(&k) {
+                Some(&link_b) => merge_links(linkage, link_a, link_b),
+                None => link_a
+            };
+            links_b.insert(k, link);
+        }
+        for (&k, link) in links_b.iter_mut() {
+            adjacency[k].remove(&a);
+            if linkage.uses_squared() {
+                let gap = l2_view(centers.row(k), centers.row(b));
+                link.0 = match linkage {
+                    Linkage::Ward => 2.0 * (sizes[k] * sizes[b]) as f32 / (sizes[k] + sizes[b]) as f32 * square(gap),
+                    _ => square(gap)
+                };
+            }
+            adjacency[k].insert(b, *link);
+            heap.push(Candidate { distance: link.0, slot: k.min(b), other: k.max(b) });
+        }
+        adjacency[b] = links_b;
+        owners[a] = b;
+        highest = highest.max(dist);
+        steps.push((a, b, if bridged { highest } else { dist }));
+    }
+    label_steps(steps, n, linkage, true)
+}
+
+/// Distance between a cluster and the union of two clusters it is adjacent to,
+/// from the distances along the edges to either of them.
+fn merge_links(linkage: Linkage, (d_a, edges_a): (f32, usize), (d_b, edges_b): (f32, usize)) -> (f32, usize) {
+    let edges = edges_a + edges_b;
+    let dist = match linkage {
+        Linkage::Single => d_a.min(d_b),
+        Linkage::Complete => d_a.max(d_b),
+        Linkage::Average => (d_a * edges_a as f32 + d_b * edges_b as f32) / edges as f32,
+        // the squared linkages are recomputed from the centers
+        _ => 0.5 * (d_a + d_b)
+    };
+    (dist, edges)
+}
+
+/// Edges joining the connected components of `adjacency` into one, each between the
+/// closest points of a component and the components before it.
+fn component_bridges(data: &Array2<f32>, adjacency: &[HashMap<usize, (f32, usize)>]) -> Vec<(usize, usize)> {
+    let n = adjacency.len();
+    let mut component = vec![usize::MAX; n];
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for start in 0..n {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        let label = members.len();
+        let mut stack = vec![start];
+        let mut current = Vec::new();
+        component[start] = label;
+        while let Some(point) = stack.pop() {
+            current.push(point);
+            for &next in adjacency[point].keys() {
+                if component[next] == usize::MAX {
+                    component[next] = label;
+                    stack.push(next);
+                }
+            }
+        }
+        members.push(current);
+    }
+    let mut bridges = Vec::new();
+    for c in 1..members.len() {
+        let mut best = (0, 0, f32::INFINITY);
+        for &i in members[c].iter() {
+            for earlier in members[..c].iter() {
+                for &j in earlier.iter() {
+                    let dist = l2_view(data.row(i), data.row(j));
+                    if dist < best.2 {
+                        best = (i, j, dist);
+                    }
+                }
+            }
+        }
+        bridges.push((best.0, best.1));
+    }
+    bridges
+}
+
+fn find(parents: &mut [usize], mut x: usize) -> usize {
+    while parents[x] != x {
+        parents[x] = parents[parents[x]];
+        x = parents[x];
+    }
+    x
+}
+
+/// Turns merges of clusters named by any of their points into merges of dendrogram nodes,
+/// sorting them by distance first unless they already are in merge order.
+fn label_steps(mut steps: Vec<(usize, usize, f32)>, n: usize, linkage: Linkage, sorted: bool) -> Vec<Merge> {
+    if !sorted {
+        steps.sort_by(|a, b| a.2.total_cmp(&b.2));
+    }
+    let mut parents: Vec<usize> = (0..2 * n - 1).collect();
+    let mut sizes = vec![1; 2 * n - 1];
+    let mut merges = Vec::with_capacity(steps.len());
+    for (i, (a, b, distance)) in steps.into_iter().enumerate() {
+        let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+        let node = n + i;
+        sizes[node] = sizes[a] + sizes[b];
+        parents[a] = node;
+        parents[b] = node;
+        merges.push(Merge { left: a.min(b), right: a.max(b), height: linkage.height(distance), size: sizes[node] });
+    }
+    merges
+}
+
+/// Node of the hierarchy arena. Ids below `n` are data points, merge `i` creates id `n + i`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Cluster {
-    cluster_1: Option<Rc<Cluster>>,
-    cluster_2: Option<Rc<Cluster>>,
-    pub members: Vec<usize>,
-    index: usize,
-    center: Array1<f32>
+    pub id: usize,
+    pub children: Option<(usize, usize)>,
+    pub size: usize,
+    /// Height of the merge that created the cluster, 0 for single points.
+    pub height: f32
 }
 
-impl Cluster {
-    fn new(item: usize, center: Array1<f32>) -> Self {
-        Self {
-            cluster_1: None, 
-            cluster_2: None,
-            members: vec![item],
-            index: 0,
-            center: center
-        }
-    }
-
-    fn next_cluster(cluster_1: Rc<Cluster>, cluster_2: Rc<Cluster>, index: usize) -> Self {
-        let mut members = Vec::new();
-        members.append(cluster_1.members.clone().as_mut());
-        members.append(cluster_2.members.clone().as_mut());
-        let mut center = Array1::zeros(cluster_1.center.len());
-        for i in 0..center.len() {
-            center[i] = *cluster_1.center.get(i).unwrap() + *cluster_2.center.get(i).unwrap();
-        } 
-        Self {
-            cluster_1: Some(Rc::clone(&cluster_1)),
-            cluster_2: Some(Rc::clone(&cluster_2)),
-            members: members,
-            index: index,
-            center: center
+/// Depth-first walk over the points below a node of the arena.
+pub struct Members<'a> {
+    nodes: &'a [Cluster],
+    stack: Vec<usize>
+}
+
+impl Iterator for Members<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while let Some(node) = self.stack.pop() {
+            match self.nodes[node].children {
+                Some((left, right)) => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+                None => return Some(node)
+            }
         }
+        None
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AggloClusterer {
-    pub head: Option<Rc<Cluster>>,
+    /// Id of the root in `nodes`.
+    pub head: Option<usize>,
+    /// All points and merges of the last fit, indexed by id.
+    pub nodes: Vec<Cluster>,
+    pub linkage: Linkage,
+    /// Adjacency lists restricting which points may end up merged, see `knn_graph`,
+    /// `radius_graph` and `grid_graph`.
+    pub connectivity: Option<Vec<Vec<usize>>>,
+    /// The merges of the last fit, see `linkage_tree`.
+    pub merges: Vec<Merge>
+}
+
+impl Default for AggloClusterer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AggloClusterer {
     pub fn new() -> Self {
         Self {
-            head: None
+            head: None,
+            nodes: Vec::new(),
+            linkage: Linkage::Centroid,
+            connectivity: None,
+            merges: Vec::new()
         }
     }
 
-    fn _insert(&mut self, mut cluster: Cluster) {
-        if let Some(cur) = &self.head {
-            for (i, coord) in cluster.center.iter_mut().enumerate() {
-                *coord += *cur.center.get(i).unwrap();
+    pub fn set_linkage(&mut self, linkage: Linkage) {
+        self.linkage = linkage;
+    }
+
+    pub fn set_connectivity(&mut self, connectivity: Vec<Vec<usize>>) {
+        self.connectivity = Some(connectivity);
+    }
+
+    /// The points below node `id`, computed on demand.
+    pub fn members(&self, id: usize) -> Members<'_> {
+        Members { nodes: &self.nodes, stack: vec![id] }
+    }
+
+    /// Labels every point with the position in `clusters` of the node containing it,
+    /// points below none of them are `NOISE`.
+    pub fn get_partitions(&self, clusters: &[usize]) -> Vec<i32> {
+        let n = if self.head.is_some() { self.merges.len() + 1 } else { 0 };
+        let mut partitions = vec![NOISE; n];
+        for (j, &cluster) in clusters.iter().enumerate() {
+            for member in self.members(cluster) {
+                partitions[member] = j as i32;
             }
-            cluster.members.append(cur.members.clone().as_mut());
-            cluster.cluster_2 = Some(Rc::clone(cur));
         }
-        self.head = Some(Rc::new(cluster));
+        partitions
     }
 
-    pub fn retrieve_clusters(&self, n_clusters: usize) -> Vec<Rc<Cluster>> {
-        //println!("retrieveing");
-        if let Some(mut current) = self.head.clone() {
-            if n_clusters == 1 {
-                return vec![Rc::clone(&current)];
-            }
-            //let mut clusters = Vec::new();
-            let mut all_clusters = Vec::new();
-            while all_clusters.len() < n_clusters {
-                if all_clusters.len() > 1 {
-                    all_clusters.remove(0);
-                }
-                if let Some(cluster_1) = &current.cluster_1 {
-                    all_clusters.push(Rc::clone(cluster_1));
+    /// The `n_clusters` clusters left before the last `n_clusters - 1` merges,
+    /// latest merge first.
+    pub fn retrieve_clusters(&self, n_clusters: usize) -> Vec<usize> {
+        let Some(head) = self.head else {
+            return Vec::new();
+        };
+        // always split the most recent merge, which has the largest id
+        let mut heap = BinaryHeap::from([head]);
+        while heap.len() < n_clusters.max(1) {
+            let Some(&id) = heap.peek() else { break };
+            let Some((left, right)) = self.nodes[id].children else { break };
+            heap.pop();
+            heap.push(left);
+            heap.push(right);
+        }
+        heap.into_sorted_vec().into_iter().rev().collect()
+    }
+
+    /// Flat clusters from undoing the last `k - 1` merges.
+    pub fn cut_at_k(&self, k: usize) -> Vec<i32> {
+        let kept = (self.merges.len() + 1).saturating_sub(k.max(1));
+        self.flat_labels(|i| i < kept)
+    }
+
+    /// Flat clusters in which no merge lies above `threshold`. With centroid or median
+    /// linkage a merge counts at the largest height found below it.
+    pub fn cut_at_distance(&self, threshold: f32) -> Vec<i32> {
+        let heights: Vec<f32> = self.merges.iter().map(|merge| merge.height).collect();
+        let max_heights = self.subtree_max(&heights);
+        self.flat_labels(|i| max_heights[i] <= threshold)
+    }
+
+    /// Flat clusters in which no merge has an inconsistency coefficient above
+    /// `threshold`, see `inconsistency`.
+    pub fn cut_by_inconsistency(&self, threshold: f32, depth: usize) -> Vec<i32> {
+        let coefficients: Vec<f32> = self.inconsistency(depth).column(3).to_vec();
+        let max_coefficients = self.subtree_max(&coefficients);
+        self.flat_labels(|i| max_coefficients[i] <= threshold)
+    }
+
+    /// SciPy's inconsistency statistics, one row per merge: mean and standard deviation
+    /// of the heights of the merges up to `depth` levels below it (itself included),
+    /// their count, and how many standard deviations the merge lies above the mean.
+    pub fn inconsistency(&self, depth: usize) -> Array2<f32> {
+        let n = self.merges.len() + 1;
+        let mut statistics = Array2::<f32>::zeros((self.merges.len(), 4));
+        for (i, merge) in self.merges.iter().enumerate() {
+            let mut heights = Vec::new();
+            let mut level = vec![n + i];
+            for _ in 0..depth.max(1) {
+                let mut next = Vec::new();
+                for &node in level.iter().filter(|&&node| node >= n) {
+                    let below = &self.merges[node - n];
+                    heights.push(below.height);
+                    next.push(below.left);
+                    next.push(below.right);
                 }
-                if let Some(cluster_2) = &current.cluster_2 {
-                    all_clusters.push(Rc::clone(cluster_2));
+                level = next;
+            }
+            let count = heights.len() as f32;
+            let mean = heights.iter().sum::<f32>() / count;
+            let std = if heights.len() > 1 {
+                (heights.iter().map(|&h| square(h - mean)).sum::<f32>() / (count - 1.0)).sqrt()
+            }
+            else {
+                0.0
+            };
+            let coefficient = if std > 0.0 { (merge.height - mean) / std } else { 0.0 };
+            statistics.row_mut(i).assign(&array![mean, std, count, coefficient]);
+        }
+        statistics
+    }
+
+    /// Largest value of every merge and all merges below it.
+    fn subtree_max(&self, values: &[f32]) -> Vec<f32> {
+        let n = self.merges.len() + 1;
+        let mut max = values.to_vec();
+        for (i, merge) in self.merges.iter().enumerate() {
+            for child in [merge.left, merge.right] {
+                if child >= n {
+                    max[i] = max[i].max(max[child - n]);
                 }
-                all_clusters.sort_by(|a, b| {
-                    b.index.cmp(&a.index)
-                });
-                current = Rc::clone(&all_clusters[0]);
             }
-            //println!("done");
-            all_clusters
         }
-        else {
-            Vec::new()
+        max
+    }
+
+    /// Labels points by the clusters formed from the merges `joined` accepts,
+    /// numbered in order of their first point.
+    fn flat_labels(&self, joined: impl Fn(usize) -> bool) -> Vec<i32> {
+        if self.head.is_none() {
+            return Vec::new();
+        }
+        let n = self.merges.len() + 1;
+        let mut parents: Vec<usize> = (0..2 * n - 1).collect();
+        for (i, merge) in self.merges.iter().enumerate() {
+            if joined(i) {
+                parents[merge.left] = n + i;
+                parents[merge.right] = n + i;
+            }
         }
+        let mut labels = vec![NOISE; 2 * n - 1];
+        let mut next_label = 0;
+        (0..n).map(|point| {
+            let root = find(&mut parents, point);
+            if labels[root] == NOISE {
+                labels[root] = next_label;
+                next_label += 1;
+            }
+            labels[root]
+        }).collect()
     }
 
     pub fn fit(&mut self, data: &Array2<f32>) {
-        let mut all_clusters: Vec<Rc<Cluster>> = data.axis_iter(Axis(0)).enumerate().map(|(i, _item)| {
-            Rc::new(Cluster::new(i, data.row(i).clone().to_owned()))
-        }).collect();
-        let mut index = 1;
-        let mut min: f32;
-        let mut min_idx: (usize, usize);
-        //println!("{}", all_clusters.len());
-        while all_clusters.len() > 1 {
-            min = f32::MAX;
-            min_idx = (0, 0);
-            for i in 0..all_clusters.len() - 1 {
-                for j in i + 1..all_clusters.len() {
-                    let mut center_1 = all_clusters[i].center.clone();
-                    let mut center_2 = all_clusters[j].center.clone();
-                    center_1 /= all_clusters[i].members.len() as f32;
-                    center_2 /= all_clusters[j].members.len() as f32;
-                    let distance = l2(&center_1, &center_2, false);
-                    if distance < min {
-                        min_idx = (i, j);
-                        min = distance;
-                    }
+        self.merges = match &self.connectivity {
+            Some(connectivity) => constrained_linkage_tree(data, self.linkage, connectivity),
+            None => linkage_tree(data, self.linkage)
+        };
+        self.build_nodes(data.shape()[0]);
+    }
+
+    /// Builds the hierarchy from precomputed distances, see `linkage_tree_precomputed`.
+    /// Connectivity constraints need feature vectors and are rejected here.
+    pub fn fit_precomputed(&mut self, distances: &CondensedMatrix) -> Result<(), ClusterError> {
+        if self.connectivity.is_some() {
+            return Err(ClusterError::InvalidParameter("connectivity constraints need feature vectors, not precomputed distances".to_string()));
+        }
+        self.merges = linkage_tree_precomputed(distances, self.linkage);
+        self.build_nodes(distances.n_points());
+        Ok(())
+    }
+
+    /// Rebuilds a hierarchy from an (n - 1) x 4 SciPy linkage matrix, as written by
+    /// `linkage_matrix`. Every merge must join two existing, unmerged nodes at a height
+    /// no lower than theirs, so centroid and median trees with inversions are rejected.
+    pub fn from_linkage_matrix(linkage_matrix: &Array2<f32>) -> Result<Self, ClusterError> {
+        let invalid = |message: String| Err(ClusterError::InvalidInput(message));
+        if linkage_matrix.shape()[1] != 4 {
+            return invalid(format!("linkage matrix must have 4 columns, got {}", linkage_matrix.shape()[1]));
+        }
+        let n = linkage_matrix.shape()[0] + 1;
+        let mut sizes = vec![1; n];
+        let mut heights = vec![0.0; n];
+        let mut merged = vec![false; 2 * n - 1];
+        let mut merges = Vec::with_capacity(n - 1);
+        for (i, row) in linkage_matrix.rows().into_iter().enumerate() {
+            let mut children = [0; 2];
+            for (child, &value) in children.iter_mut().zip([row[0], row[1]].iter()) {
+                if !(value >= 0.0 && value.fract() == 0.0) {
+                    return invalid(format!("merge {} has child {}, children must be non-negative integers", i, value));
                 }
+                *child = value.to_usize().unwrap();
+                if *child >= n + i || merged[*child] {
+                    return invalid(format!("merge {} uses node {}, which does not exist yet or is already merged", i, child));
+                }
+                merged[*child] = true;
+            }
+            let height = row[2];
+            if height.is_nan() || height < 0.0 {
+                return invalid(format!("merge {} has height {}, heights must be non-negative", i, height));
+            }
+            if children.iter().any(|&child| heights[child] > height) {
+                return invalid(format!("merge {} at height {} lies below one of its children", i, height));
+            }
+            let size = sizes[children[0]] + sizes[children[1]];
+            if row[3].to_usize() != Some(size) {
+                return invalid(format!("merge {} has size {}, its children hold {} points", i, row[3], size));
             }
-            let new_cluster = Cluster::next_cluster(Rc::clone(&all_clusters[min_idx.0]), Rc::clone(&all_clusters[min_idx.1]), index);
-            index += 1;
-            all_clusters[min_idx.0] = Rc::new(new_cluster);
-            all_clusters.remove(min_idx.1);
-            //println!("{}", all_clusters.len());
+            sizes.push(size);
+            heights.push(height);
+            merges.push(Merge { left: children[0].min(children[1]), right: children[0].max(children[1]), height, size });
         }
-        self.head = Some(all_clusters[0].clone());
+        let mut clusterer = Self::new();
+        clusterer.merges = merges;
+        clusterer.build_nodes(n);
+        Ok(clusterer)
+    }
+
+    /// The hierarchy as a SciPy linkage matrix: one row per merge holding both
+    /// children, the merge height and the number of points below it.
+    pub fn linkage_matrix(&self) -> Array2<f32> {
+        let mut matrix = Array2::<f32>::zeros((self.merges.len(), 4));
+        for (mut row, merge) in matrix.rows_mut().into_iter().zip(self.merges.iter()) {
+            row.assign(&array![merge.left as f32, merge.right as f32, merge.height, merge.size as f32]);
+        }
+        matrix
     }
-}
 
-pub fn get_partitions(clusters: &Vec<Rc<Cluster>>, data: &Array2<f32>) -> Vec<i32> {
-    //println!("{}", data.len_of(Axis(0)));
-    let mut partitions = Vec::new();
-    for i in 0..data.len_of(Axis(0)) {
-        for (j, cluster) in clusters.iter().enumerate() {
-            if cluster.members.contains(&i) {
-                partitions.push(j as i32);
+    /// The hierarchy in Newick format with points named by their row index and
+    /// branch lengths given by the difference in merge height.
+    pub fn to_newick(&self) -> String {
+        enum Step {
+            Open(usize, Option<f32>),
+            Comma,
+            Close(usize, Option<f32>)
+        }
+        let n = self.merges.len() + 1;
+        let mut newick = String::new();
+        if self.head.is_none() {
+            newick.push(';');
+            return newick;
+        }
+        let height = |node: usize| if node < n { 0.0 } else { self.merges[node - n].height };
+        let branch = |node: usize, parent: Option<f32>| parent.map_or(String::new(), |h| format!(":{}", h - height(node)));
+        // explicit stack, chained hierarchies are far deeper than the call stack allows
+        let mut stack = vec![Step::Open(2 * n - 2, None)];
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Open(node, parent) if node < n => {
+                    newick += &format!("{}{}", node, branch(node, parent));
+                }
+                Step::Open(node, parent) => {
+                    let merge = &self.merges[node - n];
+                    newick.push('(');
+                    stack.push(Step::Close(node, parent));
+                    stack.push(Step::Open(merge.right, Some(merge.height)));
+                    stack.push(Step::Comma);
+                    stack.push(Step::Open(merge.left, Some(merge.height)));
+                }
+                Step::Comma => newick.push(','),
+                Step::Close(node, parent) => {
+                    newick.push(')');
+                    newick += &branch(node, parent);
+                }
             }
         }
+        newick.push(';');
+        newick
+    }
+
+    fn build_nodes(&mut self, n: usize) {
+        if n == 0 {
+            self.nodes = Vec::new();
+            self.head = None;
+            return;
+        }
+        self.nodes = (0..n).map(|id| Cluster { id, children: None, size: 1, height: 0.0 }).collect();
+        for (i, merge) in self.merges.iter().enumerate() {
+            self.nodes.push(Cluster { id: n + i, children: Some((merge.left, merge.right)), size: merge.size, height: merge.height });
+        }
+        self.head = Some(self.nodes.len() - 1);
     }
-    //println!("{}", partitions.len());
-    partitions
 }
 
 pub struct AgglomerativeCluster {
     pub centers: usize,
-    pub clusters: Vec<Vec<Array1<f32>>>
+    /// Row indices of the points in every cluster.
+    pub clusters: Vec<Vec<usize>>,
+    pub linkage: Linkage
 }
 
 impl AgglomerativeCluster {
 
     pub fn new(data: &Array2<f32>, centers: usize) -> AgglomerativeCluster {
-        
-        let mut clusters = vec![vec![data.row(0).to_owned()]];
-        for i in 1..data.shape()[0]{
-            clusters.append(&mut vec![vec![data.row(i).to_owned()]]);
-        }
         AgglomerativeCluster {
             centers,
-            clusters: clusters
+            clusters: (0..data.shape()[0]).map(|i| vec![i]).collect(),
+            linkage: Linkage::Centroid
         }
     }
 
-    fn initialize(&mut self, data: &Array2<f32>) {
-        self.clusters = vec![vec![data.row(0).to_owned()]];
-        for i in 1..data.shape()[0]{
-            self.clusters.append(&mut vec![vec![data.row(i).to_owned()]]);
-        }
+    pub fn set_linkage(&mut self, linkage: Linkage) {
+        self.linkage = linkage;
     }
 
     pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
-        self.initialize(data);
-        while self.clusters.len() > self.centers {
-            let best: &Vec<usize> = &self.update();
-            let mut vector = self.clusters[best[1]].clone();
-            self.clusters[best[0]].append(&mut vector); 
-            self.clusters.remove(best[1]);
-        }
-        let partitions = self.get_partition(data);
-        return partitions;
-    }
-
-    fn update(&self) -> Vec<usize> {
-        let mut minimum = f32::INFINITY;
-        let mut best: Vec<usize> = vec![0, 0];
-        for i in 0..self.clusters.len() {
-            for j in i.. self.clusters.len(){
-                if i == j {
-                    continue;
-                }
-                let cluster_1 = &self.clusters[i];
-                let cluster_2 = &self.clusters[j];
-                let dist = l2(&mean_of_vec_arr(cluster_1), &mean_of_vec_arr(cluster_2), false);
-                if dist < minimum {
-                    minimum = dist;
-                    best[0] = i;
-                    best[1] = j;
-                }
+        let merges = linkage_tree(data, self.linkage);
+        self.cut(&merges, data.shape()[0])
+    }
+
+    /// Clusters from precomputed distances, see `linkage_tree_precomputed`.
+    pub fn fit_predict_precomputed(&mut self, distances: &CondensedMatrix) -> Vec<i32> {
+        let merges = linkage_tree_precomputed(distances, self.linkage);
+        self.cut(&merges, distances.n_points())
+    }
+
+    /// Replays `merges` until `centers` clusters are left.
+    fn cut(&mut self, merges: &[Merge], n: usize) -> Vec<i32> {
+        let mut parents: Vec<usize> = (0..2 * n).collect();
+        for (i, merge) in merges.iter().take(n.saturating_sub(self.centers)).enumerate() {
+            parents[merge.left] = n + i;
+            parents[merge.right] = n + i;
+        }
+        let mut slots = vec![usize::MAX; 2 * n];
+        self.clusters = Vec::new();
+        for i in 0..n {
+            let root = find(&mut parents, i);
+            if slots[root] == usize::MAX {
+                slots[root] = self.clusters.len();
+                self.clusters.push(Vec::new());
             }
+            self.clusters[slots[root]].push(i);
         }
-        best
+        self.get_partition(n)
     }
 
-    fn get_partition(&self, data: &Array2<f32>) -> Vec<i32> {
-        let mut partitions = vec![0; data.shape()[0]];
-        for (i, center) in self.clusters.clone().into_iter().enumerate() {
-            for point in center.into_iter() {
-                for (j, datapoint) in data.rows().into_iter().enumerate() {
-                    if point == datapoint{
-                        partitions[j] = i.to_i32().unwrap();
-                    }
-                }
+    fn get_partition(&self, n: usize) -> Vec<i32> {
+        let mut partitions = vec![0; n];
+        for (i, members) in self.clusters.iter().enumerate() {
+            for &point in members.iter() {
+                partitions[point] = i.to_i32().unwrap();
             }
-        }  
+        }
+        partitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points 0, 1, 3 and 7 on a line. No two candidate merges tie under any linkage,
+    /// so every linkage has a unique SciPy result.
+    fn line() -> Array2<f32> {
+        array![[0.0], [1.0], [3.0], [7.0]]
+    }
+
+    /// Output of `scipy.cluster.hierarchy.linkage(line, method)`.
+    fn scipy_linkage(linkage: Linkage) -> Array2<f32> {
+        let (second, third) = match linkage {
+            Linkage::Single => (2.0, 4.0),
+            Linkage::Complete => (3.0, 7.0),
+            Linkage::Average => (2.5, 17.0 / 3.0),
+            Linkage::Weighted => (2.5, 5.25),
+            Linkage::Ward => (2.886_751, 6.940_221),
+            Linkage::Centroid => (2.5, 17.0 / 3.0),
+            Linkage::Median => (2.5, 5.25)
+        };
+        array![[0.0, 1.0, 1.0, 2.0], [2.0, 4.0, second, 3.0], [3.0, 5.0, third, 4.0]]
+    }
+
+    const LINKAGES: [Linkage; 7] = [
+        Linkage::Single, Linkage::Complete, Linkage::Average, Linkage::Weighted,
+        Linkage::Ward, Linkage::Centroid, Linkage::Median
+    ];
+
+    fn assert_close(actual: &Array2<f32>, expected: &Array2<f32>, linkage: Linkage) {
+        assert_eq!(actual.shape(), expected.shape(), "{:?}", linkage);
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-4, "{:?}: got {}, expected {}", linkage, actual, expected);
+        }
+    }
+
+    #[test]
+    fn linkage_matrix_matches_scipy() {
+        for linkage in LINKAGES {
+            let mut clusterer = AggloClusterer::new();
+            clusterer.set_linkage(linkage);
+            clusterer.fit(&line());
+            assert_close(&clusterer.linkage_matrix(), &scipy_linkage(linkage), linkage);
+        }
+    }
+
+    #[test]
+    fn precomputed_linkage_matrix_matches_scipy() {
+        let distances = CondensedMatrix::euclidean(&line());
+        for linkage in LINKAGES {
+            let mut clusterer = AggloClusterer::new();
+            clusterer.set_linkage(linkage);
+            clusterer.fit_precomputed(&distances).unwrap();
+            assert_close(&clusterer.linkage_matrix(), &scipy_linkage(linkage), linkage);
+        }
+    }
+
+    #[test]
+    fn linkage_matrix_round_trips() {
+        let mut clusterer = AggloClusterer::new();
+        clusterer.set_linkage(Linkage::Average);
+        clusterer.fit(&line());
+        let matrix = clusterer.linkage_matrix();
+        assert_eq!(AggloClusterer::from_linkage_matrix(&matrix).unwrap().linkage_matrix(), matrix);
+    }
+    #[test]
+    fn precomputed_rejects_connectivity() {
+        let mut clusterer = AggloClusterer::new();
+        clusterer.set_connectivity(vec![vec![1], vec![0, 2], vec![1, 3], vec![2]]);
+        let result = clusterer.fit_precomputed(&CondensedMatrix::euclidean(&line()));
+        assert!(matches!(result, Err(ClusterError::InvalidParameter(_))));
+    }
+    #[test]
+    fn rejects_malformed_linkage_matrices() {
+        let invalid = |matrix: Array2<f32>| match AggloClusterer::from_linkage_matrix(&matrix) {
+            Err(ClusterError::InvalidInput(message)) => message,
+            other => panic!("expected invalid input, got {:?}", other.map(|c| c.merges))
+        };
+        assert!(invalid(array![[0.0, 1.0, 1.0]]).contains("4 columns"));
+        assert!(invalid(array![[0.0, 1.0, 1.0, 2.0], [2.0, 4.0, 2.0, 3.0]]).contains("node 4"));
+        assert!(invalid(array![[0.0, 1.0, 1.0, 2.0], [0.0, 2.0, 2.0, 3.0]]).contains("node 0"));
+        assert!(invalid(array![[0.0, -1.0, 1.0, 2.0]]).contains("non-negative integers"));
+        assert!(invalid(array![[0.0, 1.5, 1.0, 2.0]]).contains("non-negative integers"));
+        assert!(invalid(array![[0.0, 1.0, -1.0, 2.0]]).contains("non-negative"));
+        assert!(invalid(array![[0.0, 1.0, 2.0, 2.0], [2.0, 3.0, 1.0, 3.0]]).contains("below"));
+        assert!(invalid(array![[0.0, 1.0, 1.0, 3.0]]).contains("size"));
+    }
+    /// Pairs {0, 1}, {2, 3} and {4, 5} merge at 1, 1.5 and 2, the first two pairs at 4,
+    /// and everything at 8.
+    fn pairs() -> AggloClusterer {
+        let matrix = array![
+            [0.0, 1.0, 1.0, 2.0], [2.0, 3.0, 1.5, 2.0], [4.0, 5.0, 2.0, 2.0],
+            [6.0, 7.0, 4.0, 4.0], [8.0, 9.0, 8.0, 6.0]
+        ];
+        AggloClusterer::from_linkage_matrix(&matrix).unwrap()
+    }
+
+    // The expected labels are the partitions of `scipy.cluster.hierarchy.fcluster` on the
+    // same matrix, numbered in order of their first point.
+
+    #[test]
+    fn cut_at_distance_matches_fcluster() {
+        let clusterer = pairs();
+        assert_eq!(clusterer.cut_at_distance(0.5), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(clusterer.cut_at_distance(1.5), vec![0, 0, 1, 1, 2, 3]);
+        assert_eq!(clusterer.cut_at_distance(2.0), vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!(clusterer.cut_at_distance(7.9), vec![0, 0, 0, 0, 1, 1]);
+        assert_eq!(clusterer.cut_at_distance(8.0), vec![0; 6]);
+    }
+
+    #[test]
+    fn cut_at_k_matches_maxclust() {
+        let clusterer = pairs();
+        assert_eq!(clusterer.cut_at_k(1), vec![0; 6]);
+        assert_eq!(clusterer.cut_at_k(2), vec![0, 0, 0, 0, 1, 1]);
+        assert_eq!(clusterer.cut_at_k(3), vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!(clusterer.cut_at_k(4), vec![0, 0, 1, 1, 2, 3]);
+        assert_eq!(clusterer.cut_at_k(6), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(clusterer.cut_at_k(10), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn inconsistency_matches_scipy() {
+        // scipy.cluster.hierarchy.inconsistent(Z, 2)
+        let expected = array![
+            [1.0, 0.0, 1.0, 0.0],
+            [1.5, 0.0, 1.0, 0.0],
+            [2.0, 0.0, 1.0, 0.0],
+            [2.166_667, 1.607_275, 3.0, 1.140_647],
+            [4.666_667, 3.055_05, 3.0, 1.091_089]
+        ];
+        assert_close(&pairs().inconsistency(2), &expected, Linkage::Centroid);
+        // at depth 1 every merge only sees itself
+        let shallow = pairs().inconsistency(1);
+        assert_eq!(shallow.column(1).to_vec(), vec![0.0; 5]);
+        assert_eq!(shallow.column(2).to_vec(), vec![1.0; 5]);
+    }
+
+    #[test]
+    fn cut_by_inconsistency_matches_fcluster() {
+        let clusterer = pairs();
+        assert_eq!(clusterer.cut_by_inconsistency(0.0, 2), vec![0, 0, 1, 1, 2, 2]);
+        // the root is consistent on its own, but lies above the inconsistent merge at 4
+        assert_eq!(clusterer.cut_by_inconsistency(1.1, 2), vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!(clusterer.cut_by_inconsistency(1.15, 2), vec![0; 6]);
+    }
+
+    #[test]
+    fn retrieve_clusters_splits_the_latest_merges() {
+        let clusterer = pairs();
+        assert_eq!(clusterer.retrieve_clusters(1), vec![10]);
+        assert_eq!(clusterer.retrieve_clusters(2), vec![9, 8]);
+        assert_eq!(clusterer.retrieve_clusters(3), vec![8, 7, 6]);
+        assert_eq!(clusterer.retrieve_clusters(4), vec![7, 6, 5, 4]);
+        assert_eq!(clusterer.retrieve_clusters(10), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(clusterer.get_partitions(&clusterer.retrieve_clusters(2)), vec![0, 0, 0, 0, 1, 1]);
+        assert_eq!(clusterer.get_partitions(&[6, 4]), vec![0, 0, NOISE, NOISE, 1, NOISE]);
+    }
+    #[test]
+    fn connectivity_keeps_components_apart() {
+        // 0 and 2 are closest, but only 0-1 and 2-3 are connected
+        let data = array![[0.0], [3.0], [1.0], [4.0]];
+        let connectivity = vec![vec![1], vec![0], vec![3], vec![2]];
+        for linkage in LINKAGES {
+            let mut clusterer = AggloClusterer::new();
+            clusterer.set_linkage(linkage);
+            clusterer.fit(&data);
+            assert_eq!(clusterer.cut_at_k(2), vec![0, 1, 0, 1], "{:?}", linkage);
+            clusterer.set_connectivity(connectivity.clone());
+            clusterer.fit(&data);
+            assert_eq!(clusterer.cut_at_k(2), vec![0, 0, 1, 1], "{:?}", linkage);
+            let merges = &clusterer.merges;
+            assert_eq!((merges[2].left, merges[2].right, merges[2].size), (4, 5, 4), "{:?}", linkage);
+            assert!(merges[2].height >= merges[1].height.max(merges[0].height), "{:?}", linkage);
+        }
+    }
+
+    #[test]
+    fn connectivity_only_merges_neighbours() {
+        // a chain 0 - 1 - 2 in which the ends are closest
+        let data = array![[0.0], [10.0], [1.0]];
+        let mut clusterer = AggloClusterer::new();
+        clusterer.set_linkage(Linkage::Single);
+        clusterer.set_connectivity(vec![vec![1], vec![0, 2], vec![1]]);
+        clusterer.fit(&data);
+        assert_eq!((clusterer.merges[0].left, clusterer.merges[0].right), (1, 2));
+        assert_eq!(clusterer.merges[0].height, 9.0);
+        assert_eq!(clusterer.merges[1].height, 10.0);
+    }
+
+    #[test]
+    fn grid_graph_links_the_four_neighbours() {
+        let graph = grid_graph(3, 4);
+        let counts: Vec<usize> = graph.iter().map(Vec::len).collect();
+        assert_eq!(counts, vec![2, 3, 3, 2, 3, 4, 4, 3, 2, 3, 3, 2]);
+        let mut centre = graph[5].clone();
+        centre.sort_unstable();
+        assert_eq!(centre, vec![1, 4, 6, 9]);
+        for (i, neighbours) in graph.iter().enumerate() {
+            assert!(neighbours.iter().all(|&j| graph[j].contains(&i)));
+        }
+    }
+
+    #[test]
+    fn knn_graph_is_symmetric() {
+        assert_eq!(knn_graph(&line(), 1), vec![vec![1], vec![0, 2], vec![1, 3], vec![2]]);
+        assert_eq!(knn_graph(&line(), 0), vec![Vec::<usize>::new(); 4]);
+        assert_eq!(knn_graph(&line(), 5)[0], vec![1, 2, 3]);
+    }
 
-        return partitions;
+    #[test]
+    fn radius_graph_links_close_points() {
+        assert_eq!(radius_graph(&line(), 2.0), vec![vec![1], vec![0, 2], vec![1], vec![]]);
+        assert_eq!(radius_graph(&line(), 0.5), vec![Vec::<usize>::new(); 4]);
     }
 }
\ No newline at end of file
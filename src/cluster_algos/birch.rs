@@ -0,0 +1,401 @@
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::lloyd::Kmeans;
+use crate::cluster_algos::agglomerative::AggloClusterer;
+use crate::utils::stream::ChunkReader;
+use std::io;
+
+/// Summary of a set of points from which centroid and radius can be derived.
+#[derive(Clone, Debug)]
+pub struct ClusteringFeature {
+    pub n: usize,
+    pub linear_sum: Array1<f32>,
+    pub squared_sum: f32
+}
+
+impl ClusteringFeature {
+    pub fn from_point(point: ArrayView1<f32>) -> Self {
+        Self {
+            n: 1,
+            linear_sum: point.to_owned(),
+            squared_sum: point.dot(&point)
+        }
+    }
+
+    pub fn add(&mut self, other: &ClusteringFeature) {
+        self.n += other.n;
+        self.linear_sum += &other.linear_sum;
+        self.squared_sum += other.squared_sum;
+    }
+
+    pub fn centroid(&self) -> Array1<f32> {
+        &self.linear_sum / self.n.to_f32().unwrap()
+    }
+
+    /// Root mean squared distance of the summarized points to their centroid.
+    pub fn radius(&self) -> f32 {
+        let centroid = self.centroid();
+        (self.squared_sum / self.n.to_f32().unwrap() - centroid.dot(&centroid)).max(0.0).sqrt()
+    }
+}
+
+struct Entry {
+    cf: ClusteringFeature,
+    child: Option<usize>
+}
+
+struct Node {
+    is_leaf: bool,
+    entries: Vec<Entry>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlobalClustering {
+    /// Every leaf subcluster is its own cluster.
+    Subclusters,
+    /// `Kmeans` with the given number of centers on the subcluster centroids, 0 lets
+    /// it search the number of centers by silhouette.
+    Kmeans(usize),
+    /// `AggloClusterer` cut at the given number of clusters on the subcluster centroids.
+    Agglomerative(usize)
+}
+
+pub struct Birch {
+    /// Largest radius a leaf subcluster may reach by absorbing a point.
+    pub threshold: f32,
+    /// Largest number of entries per node before it is split.
+    pub branching_factor: usize,
+    pub global_clustering: GlobalClustering,
+    pub subcluster_centers: Array2<f32>,
+    pub subcluster_sizes: Vec<usize>,
+    pub subcluster_labels: Vec<i32>,
+    /// Size weighted mean of the subcluster centers of every global cluster.
+    pub centroids: Array2<f32>,
+    pub partition: Vec<i32>,
+    nodes: Vec<Node>,
+    root: usize,
+    /// Points were inserted since the global clustering last ran.
+    stale: bool
+}
+
+impl Birch {
+
+    pub fn new(global_clustering: GlobalClustering) -> Birch {
+        Birch {
+            threshold: 0.5,
+            branching_factor: 50,
+            global_clustering,
+            subcluster_centers: Array2::zeros((0, 0)),
+            subcluster_sizes: Vec::new(),
+            subcluster_labels: Vec::new(),
+            centroids: Array2::zeros((0, 0)),
+            partition: Vec::new(),
+            nodes: vec![Node { is_leaf: true, entries: Vec::new() }],
+            root: 0,
+            stale: false
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn set_branching_factor(&mut self, branching_factor: usize) {
+        assert!(branching_factor >= 2, "branching factor must be at least 2");
+        self.branching_factor = branching_factor;
+    }
+
+    /// Builds a fresh CF-tree from `data` and labels it.
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        self.reset_tree();
+        self.partial_fit(data);
+        self.partition = self.predict(data);
+        self.partition.clone()
+    }
+
+    /// Inserts a batch into the existing CF-tree. The global clustering, a full
+    /// `Kmeans` or `AggloClusterer` fit on all leaf subclusters, is deferred to the
+    /// next `predict` or `update_global`, so many small batches only pay for it once.
+    pub fn partial_fit(&mut self, batch: &Array2<f32>) {
+        self.insert_batch(batch);
+        self.stale = true;
+    }
+
+    /// Reruns the global clustering if points were inserted since it last ran,
+    /// bringing `subcluster_centers`, `subcluster_labels` and `centroids` up to date.
+    pub fn update_global(&mut self) {
+        if !self.stale {
+            return;
+        }
+        let dim = self.nodes[self.root].entries.first().map_or(0, |entry| entry.cf.linear_sum.len());
+        self.extract_subclusters(dim);
+        self.global_cluster();
+        self.stale = false;
+    }
+
+    /// Builds a fresh CF-tree from a file larger than memory chunk by chunk and runs
+    /// the global clustering once at the end. Label the rows with a second pass, e.g.
+    /// `stream::write_labels`.
+    pub fn fit_chunks(&mut self, reader: &mut ChunkReader) -> io::Result<()> {
+        self.reset_tree();
+        reader.rewind()?;
+        while let Some(chunk) = reader.next_chunk()? {
+            self.partial_fit(&chunk);
+        }
+        self.update_global();
+        Ok(())
+    }
+
+    fn reset_tree(&mut self) {
+        self.nodes = vec![Node { is_leaf: true, entries: Vec::new() }];
+        self.root = 0;
+    }
+
+    fn insert_batch(&mut self, batch: &Array2<f32>) {
+        for point in batch.rows() {
+            let cf = ClusteringFeature::from_point(point);
+            if let Some(sibling) = self.insert(self.root, &cf) {
+                let old_root = self.root;
+                self.nodes.push(Node {
+                    is_leaf: false,
+                    entries: vec![
+                        Entry { cf: self.summarize(old_root), child: Some(old_root) },
+                        Entry { cf: self.summarize(sibling), child: Some(sibling) }
+                    ]
+                });
+                self.root = self.nodes.len() - 1;
+            }
+        }
+    }
+
+    /// Labels points with the global label of their nearest leaf subcluster, running
+    /// the global clustering first if `partial_fit` left it out of date.
+    pub fn predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        self.update_global();
+        data.rows()
+            .into_iter()
+            .map(|point| {
+                let mut min = f32::INFINITY;
+                let mut best = 0;
+                for (j, center) in self.subcluster_centers.rows().into_iter().enumerate() {
+                    let dist = l2_view(point, center);
+                    if dist < min {
+                        min = dist;
+                        best = j;
+                    }
+                }
+                self.subcluster_labels.get(best).copied().unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Adds `cf` below `node`, returning the index of a new sibling node if `node` had to split.
+    fn insert(&mut self, node: usize, cf: &ClusteringFeature) -> Option<usize> {
+        let closest = self.closest_entry(node, &cf.centroid());
+        if self.nodes[node].is_leaf {
+            let mut absorbed = false;
+            if let Some(i) = closest {
+                let mut merged = self.nodes[node].entries[i].cf.clone();
+                merged.add(cf);
+                if merged.radius() <= self.threshold {
+                    self.nodes[node].entries[i].cf = merged;
+                    absorbed = true;
+                }
+            }
+            if !absorbed {
+                self.nodes[node].entries.push(Entry { cf: cf.clone(), child: None });
+            }
+        }
+        else {
+            let i = closest.expect("inner nodes always have entries");
+            let child = self.nodes[node].entries[i].child.expect("inner entries always have a child");
+            match self.insert(child, cf) {
+                None => self.nodes[node].entries[i].cf.add(cf),
+                Some(sibling) => {
+                    self.nodes[node].entries[i].cf = self.summarize(child);
+                    let sibling_cf = self.summarize(sibling);
+                    self.nodes[node].entries.push(Entry { cf: sibling_cf, child: Some(sibling) });
+                }
+            }
+        }
+        if self.nodes[node].entries.len() > self.branching_factor {
+            Some(self.split(node))
+        }
+        else {
+            None
+        }
+    }
+
+    fn closest_entry(&self, node: usize, point: &Array1<f32>) -> Option<usize> {
+        let mut min = f32::INFINITY;
+        let mut best = None;
+        for (i, entry) in self.nodes[node].entries.iter().enumerate() {
+            let dist = l2(&entry.cf.centroid(), point, false);
+            if dist < min || best.is_none() {
+                min = dist;
+                best = Some(i);
+            }
+        }
+        best
+    }
+
+    /// Moves the entries of `node` closer to the second of its two farthest apart
+    /// entries into a new node, which is returned.
+    fn split(&mut self, node: usize) -> usize {
+        let entries = std::mem::take(&mut self.nodes[node].entries);
+        let centroids: Vec<Array1<f32>> = entries.iter().map(|entry| entry.cf.centroid()).collect();
+        let (mut seed_1, mut seed_2, mut max) = (0, 1, -1.0);
+        for i in 0..centroids.len() {
+            for j in i + 1..centroids.len() {
+                let dist = l2(&centroids[i], &centroids[j], false);
+                if dist > max {
+                    max = dist;
+                    seed_1 = i;
+                    seed_2 = j;
+                }
+            }
+        }
+        let mut kept = Vec::new();
+        let mut moved = Vec::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let to_first = l2(&centroids[i], &centroids[seed_1], false);
+            let to_second = l2(&centroids[i], &centroids[seed_2], false);
+            if i == seed_1 || (i != seed_2 && to_first <= to_second) {
+                kept.push(entry);
+            }
+            else {
+                moved.push(entry);
+            }
+        }
+        self.nodes[node].entries = kept;
+        let is_leaf = self.nodes[node].is_leaf;
+        self.nodes.push(Node { is_leaf, entries: moved });
+        self.nodes.len() - 1
+    }
+
+    fn summarize(&self, node: usize) -> ClusteringFeature {
+        let mut entries = self.nodes[node].entries.iter();
+        let mut cf = entries.next().expect("nodes are never empty").cf.clone();
+        for entry in entries {
+            cf.add(&entry.cf);
+        }
+        cf
+    }
+
+    fn extract_subclusters(&mut self, dim: usize) {
+        let mut leaves = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(node) = stack.pop() {
+            for entry in self.nodes[node].entries.iter() {
+                match entry.child {
+                    Some(child) => stack.push(child),
+                    None => leaves.push(&entry.cf)
+                }
+            }
+        }
+        self.subcluster_centers = Array2::zeros((leaves.len(), dim));
+        for (i, cf) in leaves.iter().enumerate() {
+            self.subcluster_centers.row_mut(i).assign(&cf.centroid());
+        }
+        self.subcluster_sizes = leaves.iter().map(|cf| cf.n).collect();
+    }
+
+    fn global_cluster(&mut self) {
+        let n_subclusters = self.subcluster_centers.shape()[0];
+        self.subcluster_labels = match self.global_clustering {
+            GlobalClustering::Kmeans(centers) if n_subclusters > centers => {
+                let mut kmeans = Kmeans::new(&self.subcluster_centers, centers.to_i32().unwrap());
+                kmeans.fit_predict(&self.subcluster_centers).expect("the default Kmeans settings are valid")
+            }
+            GlobalClustering::Agglomerative(n_clusters) if n_subclusters > n_clusters => {
+                let mut agglo = AggloClusterer::new();
+                agglo.fit(&self.subcluster_centers);
+                agglo.cut_at_k(n_clusters)
+            }
+            _ => (0..n_subclusters).map(|i| i.to_i32().unwrap()).collect()
+        };
+        let n_clusters = self.subcluster_labels.iter().max().map_or(0, |&max| max + 1).to_usize().unwrap();
+        let mut sums = Array2::<f32>::zeros((n_clusters, self.subcluster_centers.shape()[1]));
+        let mut counts = vec![0.0; n_clusters];
+        for (i, &label) in self.subcluster_labels.iter().enumerate() {
+            let label = label.to_usize().unwrap();
+            let size = self.subcluster_sizes[i].to_f32().unwrap();
+            sums.row_mut(label).scaled_add(size, &self.subcluster_centers.row(i));
+            counts[label] += size;
+        }
+        for (mut row, count) in sums.rows_mut().into_iter().zip(counts) {
+            if count > 0.0 {
+                row /= count;
+            }
+        }
+        self.centroids = sums;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::stream::ChunkFormat;
+
+    /// A 5 x 5 grid of spacing 0.1 around `(x, y)`.
+    fn blob(x: f32, y: f32) -> Array2<f32> {
+        Array2::from_shape_fn((25, 2), |(i, j)| if j == 0 { x + (i % 5) as f32 * 0.1 } else { y + (i / 5) as f32 * 0.1 })
+    }
+
+    fn assert_two_blobs(labels: &[i32]) {
+        assert!(labels[..25].iter().all(|&label| label == labels[0]));
+        assert!(labels[25..].iter().all(|&label| label == labels[25]));
+        assert_ne!(labels[0], labels[25]);
+    }
+
+    #[test]
+    fn separates_two_blobs() {
+        let data = ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap();
+        let mut model = Birch::new(GlobalClustering::Agglomerative(2));
+        model.set_threshold(0.2);
+        assert_two_blobs(&model.fit_predict(&data));
+        assert_eq!(model.centroids.shape(), &[2, 2]);
+    }
+
+    #[test]
+    fn partial_fit_defers_the_global_clustering() {
+        let first = blob(0.0, 0.0);
+        let second = blob(10.0, 10.0);
+        let mut model = Birch::new(GlobalClustering::Agglomerative(2));
+        model.set_threshold(0.2);
+        model.partial_fit(&first);
+        model.partial_fit(&second);
+        assert!(model.subcluster_labels.is_empty());
+        model.update_global();
+        assert_eq!(model.centroids.shape(), &[2, 2]);
+        let data = ndarray::concatenate(Axis(0), &[first.view(), second.view()]).unwrap();
+        assert_two_blobs(&model.predict(&data));
+    }
+
+    #[test]
+    fn more_centers_than_subclusters_keeps_the_subclusters() {
+        let data = blob(0.0, 0.0);
+        let mut model = Birch::new(GlobalClustering::Kmeans(1000));
+        let labels = model.fit_predict(&data);
+        assert_eq!(model.subcluster_labels.len(), model.subcluster_sizes.len());
+        assert_eq!(labels.len(), 25);
+    }
+    #[test]
+    fn fit_chunks_starts_a_fresh_tree() {
+        let path = std::env::temp_dir().join(format!("birch_refit_{}.csv", std::process::id()));
+        let data = ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap();
+        let rows: Vec<String> = data.rows().into_iter().map(|row| format!("{},{}", row[0], row[1])).collect();
+        std::fs::write(&path, rows.join("\n")).unwrap();
+        let mut reader = ChunkReader::open(&path, ChunkFormat::Csv { delimiter: ',', has_header: false }, 7).unwrap();
+        let mut model = Birch::new(GlobalClustering::Agglomerative(2));
+        model.set_threshold(0.2);
+        let first = model.fit_chunks(&mut reader).map(|_| model.subcluster_sizes.clone());
+        let second = model.fit_chunks(&mut reader).map(|_| model.subcluster_sizes.clone());
+        std::fs::remove_file(&path).unwrap();
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first.iter().sum::<usize>(), 50);
+        assert_eq!(second, first);
+        assert_two_blobs(&model.predict(&data));
+    }
+}
\ No newline at end of file
@@ -1,7 +1,30 @@
 use ndarray::prelude::*;
 use crate::utils::mathfuncs::*;
-use std::collections::HashSet;
+use crate::utils::distances::CondensedMatrix;
+use crate::utils::sparse::{CsrMatrix, SparseMetric};
+use std::collections::{HashSet, VecDeque};
 
+/// Label assigned to points that do not belong to any cluster.
+pub const NOISE: i32 = -1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointType {
+    /// Has at least `min_points` neighbours (itself included) within `epsilon`.
+    Core,
+    /// Not a core point, but within `epsilon` of one.
+    Border,
+    Noise
+}
+
+/// Suggested DBScan parameters along with the k-distance curve they were read from.
+pub struct ParameterEstimate {
+    pub min_points: usize,
+    pub epsilon: f32,
+    /// Distance of every point to its `min_points`-th neighbour, sorted ascending.
+    pub k_distances: Vec<f32>,
+    /// Position of the knee in `k_distances`.
+    pub knee: usize
+}
 
 pub struct DBScan {
     pub min_points: usize,
@@ -9,7 +32,11 @@ pub struct DBScan {
     pub is_visited: HashSet<usize>,
     pub is_in_cluster: HashSet<usize>,
     pub partitions: Vec<i32>,
-    pub current_clusters: i32
+    pub current_clusters: i32,
+    pub core_sample_indices: Vec<usize>,
+    pub point_types: Vec<PointType>,
+    pub core_samples: Array2<f32>,
+    pub core_labels: Vec<i32>
 }
 
 impl DBScan {
@@ -21,8 +48,12 @@ impl DBScan {
             epsilon: 3e-1,
             is_visited: HashSet::new(),
             is_in_cluster: HashSet::new(),
-            partitions: vec![0; data.shape()[0]],
-            current_clusters: 1
+            partitions: vec![NOISE; data.shape()[0]],
+            current_clusters: 0,
+            core_sample_indices: Vec::new(),
+            point_types: vec![PointType::Noise; data.shape()[0]],
+            core_samples: Array2::zeros((0, data.shape()[1])),
+            core_labels: Vec::new()
         }
     }
 
@@ -34,62 +65,313 @@ impl DBScan {
         self.epsilon = epsilon;
     }
 
-    fn initialize(&mut self, data: &Array2<f32>) {
-        self.partitions = vec![0; data.shape()[0]];
-        self.current_clusters = 1;
+    fn initialize(&mut self, n: usize) {
+        self.partitions = vec![NOISE; n];
+        self.point_types = vec![PointType::Noise; n];
+        self.core_sample_indices.clear();
+        self.core_labels.clear();
+        self.is_visited.clear();
+        self.is_in_cluster.clear();
+        self.current_clusters = 0;
     }
 
+    /// Labels every row of `data` with its cluster id, starting at 0, or `NOISE`.
     pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
-        self.initialize(data);
-        for i in 0..data.shape()[0]{
+        let epsilon = self.epsilon;
+        self.fit_neighbourhoods(data.shape()[0], |i| region_query(data, data.row(i), epsilon));
+        self.core_samples = data.select(Axis(0), &self.core_sample_indices);
+        self.partitions.clone()
+    }
+
+    /// Labels points given only their pairwise distances. Without feature vectors
+    /// no core samples are kept, so `predict` labels everything `NOISE` afterwards.
+    pub fn fit_predict_precomputed(&mut self, distances: &CondensedMatrix) -> Vec<i32> {
+        let (epsilon, n) = (self.epsilon, distances.n_points());
+        self.fit_neighbourhoods(n, |i| (0..n).filter(|&j| distances.get(i, j) <= epsilon).collect());
+        self.core_samples = Array2::zeros((0, 0));
+        self.partitions.clone()
+    }
+
+    /// Labels the rows of a sparse matrix under the Euclidean or cosine distance, using
+    /// precomputed row norms. Like `fit_predict_precomputed`, no core samples are kept.
+    pub fn fit_predict_sparse(&mut self, data: &CsrMatrix, metric: SparseMetric) -> Vec<i32> {
+        let (epsilon, n) = (self.epsilon, data.shape().0);
+        let norms = data.row_norms_squared();
+        self.fit_neighbourhoods(n, |i| (0..n).filter(|&j| data.distance(i, j, &norms, metric) <= epsilon).collect());
+        self.core_samples = Array2::zeros((0, 0));
+        self.partitions.clone()
+    }
+
+    /// Runs DBScan on `n` points, `neighbours(i)` being all points within `epsilon` of `i`.
+    fn fit_neighbourhoods(&mut self, n: usize, neighbours: impl Fn(usize) -> Vec<usize>) {
+        self.initialize(n);
+        for i in 0..n {
             if self.is_visited.contains(&i){
                 continue;
             }
             self.is_visited.insert(i);
-            let neighbours = self.get_neighbours(data, i);
-            if neighbours.len() <= self.min_points {
+            let point_neighbours = neighbours(i);
+            if point_neighbours.len() < self.min_points {
                 continue;
             }
-            else {
-                self.partitions[i] = self.current_clusters;
-                self.is_in_cluster.insert(i);
-                for point in neighbours.into_iter() {
-                    if !self.is_in_cluster.contains(&point){
-                        self.expand_cluster(data, point);
-                    }
-                }
-                self.current_clusters += 1;
-            }
+            self.expand_cluster(&neighbours, i, point_neighbours);
+            self.current_clusters += 1;
         }
-        return self.partitions.clone();
+        self.core_sample_indices = (0..n)
+            .filter(|&i| self.point_types[i] == PointType::Core)
+            .collect();
+        self.core_labels = self.core_sample_indices.iter().map(|&i| self.partitions[i]).collect();
     }
 
-    fn expand_cluster(&mut self, data: &Array2<f32>,  index: usize) {
-        self.partitions[index] = self.current_clusters;
-        if !self.is_visited.contains(&index){
-            self.is_visited.insert(index);
-            self.is_in_cluster.insert(index);
-            let neighbours = self.get_neighbours(data, index);
-            if neighbours.len() >= self.min_points {
-                for point in neighbours.into_iter() {
-                    if !self.is_in_cluster.contains(&point){
-                        self.expand_cluster(data, point);
+    /// Labels new points with the cluster of the nearest fitted core sample within
+    /// `epsilon`, or `NOISE` if there is none. Must be called after `fit_predict`.
+    pub fn predict(&self, data: &Array2<f32>) -> Vec<i32> {
+        data.rows()
+            .into_iter()
+            .map(|point| {
+                let mut best = NOISE;
+                let mut min = f32::INFINITY;
+                for (core, &label) in self.core_samples.rows().into_iter().zip(self.core_labels.iter()) {
+                    let dist = l2_view(point, core);
+                    if dist <= self.epsilon && dist < min {
+                        min = dist;
+                        best = label;
                     }
                 }
+                best
+            })
+            .collect()
+    }
+
+    /// Number of clusters found by the last call to `fit_predict`.
+    pub fn n_clusters(&self) -> usize {
+        self.current_clusters as usize
+    }
+
+    fn expand_cluster(&mut self, neighbours_of: &impl Fn(usize) -> Vec<usize>, index: usize, neighbours: Vec<usize>) {
+        self.partitions[index] = self.current_clusters;
+        self.point_types[index] = PointType::Core;
+        self.is_in_cluster.insert(index);
+        let mut queue: VecDeque<usize> = neighbours.into();
+        while let Some(point) = queue.pop_front() {
+            if !self.is_in_cluster.contains(&point) {
+                self.partitions[point] = self.current_clusters;
+                self.point_types[point] = PointType::Border;
+                self.is_in_cluster.insert(point);
+            }
+            if self.is_visited.contains(&point) {
+                continue;
+            }
+            self.is_visited.insert(point);
+            let point_neighbours = neighbours_of(point);
+            if point_neighbours.len() >= self.min_points {
+                self.point_types[point] = PointType::Core;
+                queue.extend(point_neighbours.into_iter().filter(|p| {
+                    !self.is_visited.contains(p) || !self.is_in_cluster.contains(p)
+                }));
             }
         }
+    }
 
+    /// Sets `min_points` and `epsilon` from `estimate_parameters` and returns the estimate.
+    pub fn auto_configure(&mut self, data: &Array2<f32>) -> ParameterEstimate {
+        let estimate = estimate_parameters(data);
+        self.min_points = estimate.min_points;
+        self.epsilon = estimate.epsilon;
+        estimate
     }
 
-    fn get_neighbours(&self, data: &Array2<f32>, index: usize) -> Vec<usize> {
-        let mut neighbours: Vec<usize> = vec![];
-        for (i, point) in data.rows().into_iter().enumerate() {
-            let dist = l2(&data.row(index).to_owned(), &point.to_owned(), false);
-            if dist <= self.epsilon {
-                neighbours.push(i);
+}
+
+/// Indices of all rows of `data` within `epsilon` of `point`.
+pub fn region_query(data: &Array2<f32>, point: ArrayView1<f32>, epsilon: f32) -> Vec<usize> {
+    data.rows()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, other)| l2_view(point, *other) <= epsilon)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Distance from every row of `data` to its `k`-th nearest neighbour, counting the
+/// row itself as the first neighbour. `k` is clamped to the number of rows.
+pub fn kth_neighbour_distances(data: &Array2<f32>, k: usize) -> Vec<f32> {
+    let n = data.shape()[0];
+    let k = k.clamp(1, n.max(1));
+    let mut dists = vec![0.0; n];
+    data.rows()
+        .into_iter()
+        .map(|point| {
+            for (j, other) in data.rows().into_iter().enumerate() {
+                dists[j] = l2_view(point, other);
             }
+            *dists.select_nth_unstable_by(k - 1, |a, b| a.total_cmp(b)).1
+        })
+        .collect()
+}
+
+/// Common rule of thumb of twice the dimensionality, but at least 3.
+pub fn suggest_min_points(dim: usize) -> usize {
+    (2 * dim).max(3)
+}
+
+/// Sorted distances to the `k`-th neighbour, the curve of a k-distance graph.
+pub fn k_distance_curve(data: &Array2<f32>, k: usize) -> Vec<f32> {
+    let mut distances = kth_neighbour_distances(data, k);
+    distances.sort_by(|a, b| a.total_cmp(b));
+    distances
+}
+
+/// Index of the knee of an ascending curve: the point farthest below the chord
+/// between its first and last value, after scaling both axes to [0, 1].
+pub fn knee_point(curve: &[f32]) -> usize {
+    if curve.len() < 3 {
+        return curve.len().saturating_sub(1);
+    }
+    let last = curve.len() - 1;
+    let range = curve[last] - curve[0];
+    if range <= 0.0 {
+        return last;
+    }
+    let mut knee = last;
+    let mut max_gap = 0.0;
+    for (i, value) in curve.iter().enumerate() {
+        let x = i as f32 / last as f32;
+        let y = (value - curve[0]) / range;
+        if x - y > max_gap {
+            max_gap = x - y;
+            knee = i;
         }
-        return neighbours;
+    }
+    knee
+}
+
+/// Suggests `min_points` from the dimensionality of `data` and `epsilon` from the
+/// knee of the resulting k-distance curve.
+pub fn estimate_parameters(data: &Array2<f32>) -> ParameterEstimate {
+    let min_points = suggest_min_points(data.shape()[1]);
+    let k_distances = k_distance_curve(data, min_points);
+    let knee = knee_point(&k_distances);
+    let epsilon = k_distances.get(knee).copied().unwrap_or(0.0);
+    ParameterEstimate {
+        min_points,
+        epsilon,
+        k_distances,
+        knee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two chains along the x-axis and an isolated point between them.
+    fn chains() -> Array2<f32> {
+        let xs = [0.0, 1.0, 2.0, 3.0, 10.0, 20.0, 20.6, 21.2];
+        Array2::from_shape_fn((xs.len(), 2), |(i, j)| if j == 0 { xs[i] } else { 0.0 })
+    }
+
+    fn fitted(data: &Array2<f32>) -> DBScan {
+        let mut model = DBScan::new(data);
+        model.set_epsilon(1.0);
+        model.set_min_points(3);
+        model
     }
 
+    #[test]
+    fn labels_core_border_and_noise() {
+        let data = chains();
+        let mut model = fitted(&data);
+        assert_eq!(model.fit_predict(&data), vec![0, 0, 0, 0, NOISE, 1, 1, 1]);
+        use PointType::*;
+        assert_eq!(model.point_types, vec![Border, Core, Core, Border, Noise, Border, Core, Border]);
+        assert_eq!(model.core_sample_indices, vec![1, 2, 6]);
+        assert_eq!(model.n_clusters(), 2);
+    }
+
+    #[test]
+    fn refitting_gives_the_same_labels() {
+        let data = chains();
+        let mut model = fitted(&data);
+        let first = model.fit_predict(&data);
+        assert_eq!(model.fit_predict(&data), first);
+    }
+
+    #[test]
+    fn precomputed_matches_feature_vectors() {
+        let data = chains();
+        let mut model = fitted(&data);
+        let labels = model.fit_predict(&data);
+        assert_eq!(model.fit_predict_precomputed(&CondensedMatrix::euclidean(&data)), labels);
+    }
+
+    #[test]
+    fn predict_uses_core_samples() {
+        let data = chains();
+        let mut model = fitted(&data);
+        model.fit_predict(&data);
+        let new = array![[1.5, 0.5], [20.4, 0.0], [10.0, 0.0]];
+        assert_eq!(model.predict(&new), vec![0, 1, NOISE]);
+    }
+    #[test]
+    fn sparse_euclidean_matches_dense() {
+        let data = chains();
+        let mut model = fitted(&data);
+        let labels = model.fit_predict(&data);
+        let sparse = CsrMatrix::from_dense(&data);
+        assert_eq!(model.fit_predict_sparse(&sparse, SparseMetric::Euclidean), labels);
+        assert_eq!(model.core_sample_indices, vec![1, 2, 6]);
+    }
+
+    #[test]
+    fn sparse_cosine_groups_directions() {
+        // three rows along the first axis, three along the diagonal of the others,
+        // one orthogonal to both; magnitudes do not matter under the cosine distance
+        let data = array![
+            [1.0, 0.0, 0.0], [5.0, 0.0, 0.0], [0.5, 0.01, 0.0],
+            [0.0, 1.0, 1.0], [0.0, 3.0, 3.1], [0.0, 0.2, 0.2],
+            [0.0, 1.0, -1.0]
+        ];
+        let mut model = DBScan::new(&data);
+        model.set_epsilon(0.01);
+        model.set_min_points(3);
+        let labels = model.fit_predict_sparse(&CsrMatrix::from_dense(&data), SparseMetric::Cosine);
+        assert_eq!(labels, vec![0, 0, 0, 1, 1, 1, NOISE]);
+    }
+    #[test]
+    fn knee_point_finds_the_elbow() {
+        let curve = [1.0, 1.1, 1.2, 1.3, 1.4, 1.5, 1.6, 1.7, 5.0, 10.0];
+        assert_eq!(knee_point(&curve), 7);
+        assert_eq!(knee_point(&[2.0; 5]), 4);
+        assert_eq!(knee_point(&[1.0, 3.0]), 1);
+        assert_eq!(knee_point(&[]), 0);
+    }
+
+    #[test]
+    fn suggests_twice_the_dimension() {
+        assert_eq!(suggest_min_points(1), 3);
+        assert_eq!(suggest_min_points(2), 4);
+        assert_eq!(suggest_min_points(5), 10);
+    }
+
+    #[test]
+    fn estimated_parameters_separate_the_outliers() {
+        // a 5 x 5 grid of spacing 1 and three far away points
+        let mut points: Vec<[f32; 2]> = (0..25).map(|i| [(i % 5) as f32, (i / 5) as f32]).collect();
+        points.extend([[50.0, 50.0], [-50.0, 0.0], [0.0, 80.0]]);
+        let data = Array2::from_shape_fn((points.len(), 2), |(i, j)| points[i][j]);
+        let estimate = estimate_parameters(&data);
+        assert_eq!(estimate.min_points, 4);
+        assert_eq!(estimate.k_distances.len(), 28);
+        assert_eq!(&estimate.k_distances[..21], &[1.0; 21]);
+        // the grid corners have their third neighbour on the diagonal
+        assert_eq!(estimate.knee, 24);
+        assert!((estimate.epsilon - 2.0f32.sqrt()).abs() < 1e-6);
+        let mut model = DBScan::new(&data);
+        model.set_epsilon(estimate.epsilon);
+        model.set_min_points(estimate.min_points);
+        let labels = model.fit_predict(&data);
+        assert_eq!(&labels[..25], &[0; 25]);
+        assert_eq!(&labels[25..], &[NOISE; 3]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// Why an algorithm refused its hyperparameters or input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClusterError {
+    /// A hyperparameter outside its valid range.
+    InvalidParameter(String),
+    /// Data the algorithm cannot work on, e.g. an asymmetric distance matrix.
+    InvalidInput(String)
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClusterError::InvalidParameter(message) | ClusterError::InvalidInput(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for ClusterError {}
\ No newline at end of file
@@ -0,0 +1,461 @@
+use ndarray::prelude::*;
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::dbscan::{kth_neighbour_distances, NOISE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClusterSelection {
+    /// Pick the set of non-overlapping clusters with the largest total stability.
+    ExcessOfMass,
+    /// Pick the leaves of the condensed tree.
+    Leaf
+}
+
+/// One merge of the single linkage tree. Nodes below `n` are data points,
+/// merge `i` creates node `n + i`.
+#[derive(Clone, Debug)]
+pub struct SingleLinkageStep {
+    pub left: usize,
+    pub right: usize,
+    pub distance: f32,
+    pub size: usize
+}
+
+/// An edge of the condensed tree. Clusters are numbered from `n` (the root)
+/// upwards, children below `n` are data points falling out of `parent`.
+#[derive(Clone, Debug)]
+pub struct CondensedEdge {
+    pub parent: usize,
+    pub child: usize,
+    pub lambda: f32,
+    pub child_size: usize
+}
+
+pub struct HDBScan {
+    pub min_cluster_size: usize,
+    pub min_samples: usize,
+    pub cluster_selection: ClusterSelection,
+    pub allow_single_cluster: bool,
+    pub core_distances: Vec<f32>,
+    pub single_linkage_tree: Vec<SingleLinkageStep>,
+    pub condensed_tree: Vec<CondensedEdge>,
+    pub stabilities: Vec<f32>,
+    pub selected_clusters: Vec<usize>,
+    pub partitions: Vec<i32>,
+    pub probabilities: Vec<f32>,
+    pub outlier_scores: Vec<f32>
+}
+
+impl HDBScan {
+
+    pub fn new(min_cluster_size: usize) -> HDBScan {
+        HDBScan {
+            min_cluster_size,
+            min_samples: min_cluster_size,
+            cluster_selection: ClusterSelection::ExcessOfMass,
+            allow_single_cluster: false,
+            core_distances: Vec::new(),
+            single_linkage_tree: Vec::new(),
+            condensed_tree: Vec::new(),
+            stabilities: Vec::new(),
+            selected_clusters: Vec::new(),
+            partitions: Vec::new(),
+            probabilities: Vec::new(),
+            outlier_scores: Vec::new()
+        }
+    }
+
+    /// Number of neighbours, the point itself included, used for the core distance.
+    pub fn set_min_samples(&mut self, min_samples: usize) {
+        self.min_samples = min_samples;
+    }
+
+    pub fn set_cluster_selection(&mut self, cluster_selection: ClusterSelection) {
+        self.cluster_selection = cluster_selection;
+    }
+
+    pub fn set_allow_single_cluster(&mut self, allow_single_cluster: bool) {
+        self.allow_single_cluster = allow_single_cluster;
+    }
+
+    /// Labels every row of `data` with its cluster id, starting at 0, or `NOISE`.
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        let n = data.shape()[0];
+        self.core_distances = kth_neighbour_distances(data, self.min_samples);
+        let mst = mutual_reachability_mst(data, &self.core_distances);
+        self.single_linkage_tree = single_linkage(mst, n);
+        self.condensed_tree = condense_tree(&self.single_linkage_tree, n, self.min_cluster_size.max(2));
+        self.stabilities = compute_stabilities(&self.condensed_tree, n);
+        self.selected_clusters = self.select_clusters(n);
+        self.assign_labels(n);
+        self.outlier_scores = glosh(&self.condensed_tree, n, self.stabilities.len());
+        self.partitions.clone()
+    }
+
+    fn select_clusters(&self, n: usize) -> Vec<usize> {
+        let n_clusters = self.stabilities.len();
+        let children = cluster_children(&self.condensed_tree, n, n_clusters);
+        let mut is_cluster = vec![false; n_clusters];
+        match self.cluster_selection {
+            ClusterSelection::ExcessOfMass => {
+                let mut stability = self.stabilities.clone();
+                is_cluster = vec![true; n_clusters];
+                if !self.allow_single_cluster && n_clusters > 0 {
+                    is_cluster[0] = false;
+                }
+                // children always carry a higher label than their parent
+                for c in (0..n_clusters).rev() {
+                    if c == 0 && !self.allow_single_cluster {
+                        continue;
+                    }
+                    let subtree: f32 = children[c].iter().map(|&child| stability[child]).sum();
+                    if subtree > stability[c] {
+                        is_cluster[c] = false;
+                        stability[c] = subtree;
+                    }
+                    else {
+                        let mut stack = children[c].clone();
+                        while let Some(d) = stack.pop() {
+                            is_cluster[d] = false;
+                            stack.extend(children[d].iter());
+                        }
+                    }
+                }
+            }
+            ClusterSelection::Leaf => {
+                for c in 0..n_clusters {
+                    is_cluster[c] = children[c].is_empty();
+                }
+                if n_clusters == 1 && !self.allow_single_cluster {
+                    is_cluster[0] = false;
+                }
+            }
+        }
+        (0..n_clusters).filter(|&c| is_cluster[c]).map(|c| c + n).collect()
+    }
+
+    fn assign_labels(&mut self, n: usize) {
+        let n_clusters = self.stabilities.len();
+        let mut label_of: Vec<Option<i32>> = vec![None; n_clusters];
+        for (label, &c) in self.selected_clusters.iter().enumerate() {
+            label_of[c - n] = Some(label as i32);
+        }
+        // walk the tree top down so every cluster inherits its selected ancestor
+        let mut selected = vec![None; n_clusters];
+        if n_clusters > 0 {
+            selected[0] = label_of[0].map(|label| (label, n));
+        }
+        self.partitions = vec![NOISE; n];
+        self.probabilities = vec![0.0; n];
+        // duplicates never leave their cluster, so scale by the densest point that does
+        let deaths = subtree_max_lambdas(&self.condensed_tree, n, n_clusters, true);
+        for edge in self.condensed_tree.iter() {
+            let inherited = selected[edge.parent - n];
+            if edge.child >= n {
+                selected[edge.child - n] = label_of[edge.child - n].map(|label| (label, edge.child)).or(inherited);
+            }
+            else if let Some((label, cluster)) = inherited {
+                self.partitions[edge.child] = label;
+                let max_lambda = deaths[cluster - n];
+                self.probabilities[edge.child] = if max_lambda == 0.0 || !edge.lambda.is_finite() {
+                    1.0
+                }
+                else {
+                    edge.lambda.min(max_lambda) / max_lambda
+                };
+            }
+        }
+    }
+}
+
+/// Prim's algorithm on the dense mutual reachability graph, returning `n - 1` edges.
+fn mutual_reachability_mst(data: &Array2<f32>, core_distances: &[f32]) -> Vec<(usize, usize, f32)> {
+    let n = data.shape()[0];
+    let mut edges = Vec::with_capacity(n.saturating_sub(1));
+    if n == 0 {
+        return edges;
+    }
+    let mut in_tree = vec![false; n];
+    let mut best = vec![f32::INFINITY; n];
+    let mut from = vec![0; n];
+    let mut current = 0;
+    in_tree[0] = true;
+    for _ in 1..n {
+        let point = data.row(current);
+        let mut next = usize::MAX;
+        let mut min = f32::INFINITY;
+        for j in 0..n {
+            if in_tree[j] {
+                continue;
+            }
+            let dist = l2_view(point, data.row(j))
+                .max(core_distances[current])
+                .max(core_distances[j]);
+            if dist < best[j] {
+                best[j] = dist;
+                from[j] = current;
+            }
+            if best[j] < min || next == usize::MAX {
+                min = best[j];
+                next = j;
+            }
+        }
+        in_tree[next] = true;
+        edges.push((from[next], next, best[next]));
+        current = next;
+    }
+    edges
+}
+
+fn find(parents: &mut [usize], mut x: usize) -> usize {
+    while parents[x] != x {
+        parents[x] = parents[parents[x]];
+        x = parents[x];
+    }
+    x
+}
+
+fn single_linkage(mut mst: Vec<(usize, usize, f32)>, n: usize) -> Vec<SingleLinkageStep> {
+    mst.sort_by(|a, b| a.2.total_cmp(&b.2));
+    let mut parents: Vec<usize> = (0..2 * n).collect();
+    let mut sizes = vec![1; 2 * n];
+    let mut tree = Vec::with_capacity(mst.len());
+    for (i, (a, b, distance)) in mst.into_iter().enumerate() {
+        let left = find(&mut parents, a);
+        let right = find(&mut parents, b);
+        let node = n + i;
+        sizes[node] = sizes[left] + sizes[right];
+        parents[left] = node;
+        parents[right] = node;
+        tree.push(SingleLinkageStep { left, right, distance, size: sizes[node] });
+    }
+    tree
+}
+
+fn node_size(tree: &[SingleLinkageStep], node: usize, n: usize) -> usize {
+    if node < n { 1 } else { tree[node - n].size }
+}
+
+fn descendant_points(tree: &[SingleLinkageStep], node: usize, n: usize) -> Vec<usize> {
+    let mut points = Vec::new();
+    let mut stack = vec![node];
+    while let Some(current) = stack.pop() {
+        if current < n {
+            points.push(current);
+        }
+        else {
+            stack.push(tree[current - n].left);
+            stack.push(tree[current - n].right);
+        }
+    }
+    points
+}
+
+/// Collapses the single linkage tree so that only splits into two parts of at
+/// least `min_cluster_size` points create new clusters.
+fn condense_tree(tree: &[SingleLinkageStep], n: usize, min_cluster_size: usize) -> Vec<CondensedEdge> {
+    let mut condensed = Vec::new();
+    if tree.is_empty() {
+        return condensed;
+    }
+    let root = n + tree.len() - 1;
+    let mut relabel = vec![0; root + 1];
+    relabel[root] = n;
+    let mut next_label = n + 1;
+    let mut queue = std::collections::VecDeque::from([root]);
+    while let Some(node) = queue.pop_front() {
+        if node < n {
+            continue;
+        }
+        let step = &tree[node - n];
+        let lambda = if step.distance > 0.0 { 1.0 / step.distance } else { f32::INFINITY };
+        let parent = relabel[node];
+        let left_size = node_size(tree, step.left, n);
+        let right_size = node_size(tree, step.right, n);
+        let left_big = left_size >= min_cluster_size;
+        let right_big = right_size >= min_cluster_size;
+        for (child, child_size, big, other_big) in [(step.left, left_size, left_big, right_big), (step.right, right_size, right_big, left_big)] {
+            if big && other_big {
+                relabel[child] = next_label;
+                next_label += 1;
+                condensed.push(CondensedEdge { parent, child: relabel[child], lambda, child_size });
+                queue.push_back(child);
+            }
+            else if big {
+                relabel[child] = parent;
+                queue.push_back(child);
+            }
+            else {
+                for point in descendant_points(tree, child, n) {
+                    condensed.push(CondensedEdge { parent, child: point, lambda, child_size: 1 });
+                }
+            }
+        }
+    }
+    condensed
+}
+
+fn compute_stabilities(condensed: &[CondensedEdge], n: usize) -> Vec<f32> {
+    let n_clusters = condensed.iter().map(|e| e.parent.max(e.child) + 1).max().unwrap_or(n + 1) - n;
+    let mut births = vec![0.0; n_clusters];
+    for edge in condensed.iter().filter(|e| e.child >= n) {
+        births[edge.child - n] = edge.lambda;
+    }
+    let mut stabilities = vec![0.0; n_clusters];
+    for edge in condensed.iter() {
+        stabilities[edge.parent - n] += (edge.lambda - births[edge.parent - n]) * edge.child_size as f32;
+    }
+    stabilities
+}
+
+fn cluster_children(condensed: &[CondensedEdge], n: usize, n_clusters: usize) -> Vec<Vec<usize>> {
+    let mut children = vec![Vec::new(); n_clusters];
+    for edge in condensed.iter().filter(|e| e.child >= n) {
+        children[edge.parent - n].push(edge.child - n);
+    }
+    children
+}
+
+/// Largest lambda at which any point leaves every cluster or one of its descendants,
+/// ignoring the infinite lambdas of duplicate points if `finite_only` is set.
+fn subtree_max_lambdas(condensed: &[CondensedEdge], n: usize, n_clusters: usize, finite_only: bool) -> Vec<f32> {
+    let mut deaths = vec![0.0f32; n_clusters];
+    for edge in condensed.iter().filter(|e| !finite_only || e.lambda.is_finite()) {
+        deaths[edge.parent - n] = deaths[edge.parent - n].max(edge.lambda);
+    }
+    // children are condensed after their parent, so walking backwards sees them first
+    for edge in condensed.iter().rev().filter(|e| e.child >= n) {
+        deaths[edge.parent - n] = deaths[edge.parent - n].max(deaths[edge.child - n]);
+    }
+    deaths
+}
+
+/// GLOSH outlier scores: how early a point leaves its cluster relative to the
+/// densest point of that cluster's subtree, 0 for inliers and towards 1 for outliers.
+/// Duplicate points make the densest lambda infinite, every point of such a subtree
+/// scores 0.
+fn glosh(condensed: &[CondensedEdge], n: usize, n_clusters: usize) -> Vec<f32> {
+    let deaths = subtree_max_lambdas(condensed, n, n_clusters, false);
+    let mut scores = vec![0.0; n];
+    for edge in condensed.iter().filter(|e| e.child < n) {
+        let lambda_max = deaths[edge.parent - n];
+        if lambda_max != 0.0 && lambda_max.is_finite() && edge.lambda.is_finite() {
+            scores[edge.child] = (lambda_max - edge.lambda) / lambda_max;
+        }
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5 x 5 grid of spacing 0.1 around `(x, y)`.
+    fn blob(x: f32, y: f32) -> Array2<f32> {
+        Array2::from_shape_fn((25, 2), |(i, j)| if j == 0 { x + (i % 5) as f32 * 0.1 } else { y + (i / 5) as f32 * 0.1 })
+    }
+
+    fn two_blobs() -> Array2<f32> {
+        ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap()
+    }
+
+    #[test]
+    fn separates_two_blobs() {
+        let data = two_blobs();
+        let mut model = HDBScan::new(5);
+        let labels = model.fit_predict(&data);
+        assert_ne!(labels[0], NOISE);
+        assert_ne!(labels[0], labels[25]);
+        assert!(labels[..25].iter().all(|&label| label == labels[0]));
+        assert!(labels[25..].iter().all(|&label| label == labels[25]));
+    }
+
+    #[test]
+    fn scores_are_probabilities() {
+        let data = two_blobs();
+        let mut model = HDBScan::new(5);
+        model.fit_predict(&data);
+        assert!(model.probabilities.iter().all(|p| (0.0..=1.0).contains(p)));
+        assert!(model.outlier_scores.iter().all(|s| (0.0..=1.0).contains(s)));
+    }
+
+    /// Root 4 splits at lambda 1 into cluster 5 with points 0 and 1, leaving at
+    /// lambda 3, and cluster 6 with points 2 and 3, leaving at lambda 2.
+    fn condensed_example() -> Vec<CondensedEdge> {
+        let edge = |parent, child, lambda, child_size| CondensedEdge { parent, child, lambda, child_size };
+        vec![
+            edge(4, 5, 1.0, 2), edge(4, 6, 1.0, 2),
+            edge(5, 0, 3.0, 1), edge(5, 1, 3.0, 1),
+            edge(6, 2, 2.0, 1), edge(6, 3, 2.0, 1)
+        ]
+    }
+
+    #[test]
+    fn max_lambdas_cover_the_whole_subtree() {
+        assert_eq!(subtree_max_lambdas(&condensed_example(), 4, 3, false), vec![3.0, 3.0, 2.0]);
+        let mut duplicates = condensed_example();
+        duplicates[2].lambda = f32::INFINITY;
+        assert_eq!(subtree_max_lambdas(&duplicates, 4, 3, false), vec![f32::INFINITY, f32::INFINITY, 2.0]);
+        assert_eq!(subtree_max_lambdas(&duplicates, 4, 3, true), vec![3.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn probabilities_use_the_whole_subtree() {
+        let mut model = HDBScan::new(2);
+        model.condensed_tree = condensed_example();
+        model.stabilities = vec![0.0; 3];
+        model.selected_clusters = vec![4];
+        model.assign_labels(4);
+        assert_eq!(model.partitions, vec![0; 4]);
+        assert_eq!(model.probabilities, vec![1.0, 1.0, 2.0 / 3.0, 2.0 / 3.0]);
+    }
+
+    #[test]
+    fn glosh_scores_against_the_densest_descendant() {
+        // as in condensed_example, plus point 4 leaving the root 5 at lambda 0.5
+        let edge = |parent, child, lambda, child_size| CondensedEdge { parent, child, lambda, child_size };
+        let mut condensed = vec![
+            edge(5, 4, 0.5, 1), edge(5, 6, 1.0, 2), edge(5, 7, 1.0, 2),
+            edge(6, 0, 3.0, 1), edge(6, 1, 3.0, 1),
+            edge(7, 2, 2.0, 1), edge(7, 3, 2.0, 1)
+        ];
+        let scores = glosh(&condensed, 5, 3);
+        assert_eq!(scores[..4], [0.0; 4]);
+        assert!((scores[4] - 2.5 / 3.0).abs() < 1e-6);
+        // duplicates of point 0 leave at an infinite lambda
+        condensed[3].lambda = f32::INFINITY;
+        assert_eq!(glosh(&condensed, 5, 3), vec![0.0; 5]);
+    }
+
+    #[test]
+    fn duplicates_give_no_nan_outlier_scores() {
+        let mut data = two_blobs();
+        for i in 0..5 {
+            data.row_mut(i).assign(&array![0.0, 0.0]);
+        }
+        let mut model = HDBScan::new(5);
+        model.fit_predict(&data);
+        assert!(model.outlier_scores.iter().all(|s| s.is_finite()));
+        assert!(model.probabilities.iter().all(|p| p.is_finite()));
+        // the other members of the duplicates' cluster keep a non-zero probability
+        for (i, &p) in model.probabilities.iter().enumerate() {
+            assert_eq!(p > 0.0, model.partitions[i] != NOISE, "{}: {:?} {:?}", i, model.probabilities, model.partitions);
+        }
+        assert!(model.partitions[5..].contains(&model.partitions[0]), "{:?}", model.partitions);
+    }
+
+    #[test]
+    fn probabilities_ignore_the_lambda_of_duplicates() {
+        // as in glosh_scores_against_the_densest_descendant, with points 0 and 1 duplicates
+        let edge = |parent, child, lambda, child_size| CondensedEdge { parent, child, lambda, child_size };
+        let mut model = HDBScan::new(2);
+        model.condensed_tree = vec![
+            edge(5, 4, 0.5, 1), edge(5, 6, 1.0, 2), edge(5, 7, 1.0, 2),
+            edge(6, 0, f32::INFINITY, 1), edge(6, 1, f32::INFINITY, 1),
+            edge(7, 2, 2.0, 1), edge(7, 3, 2.0, 1)
+        ];
+        model.stabilities = vec![0.0; 3];
+        model.selected_clusters = vec![5];
+        model.assign_labels(5);
+        assert_eq!(model.probabilities, vec![1.0, 1.0, 1.0, 1.0, 0.25]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,182 @@
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use crate::utils::distances::CondensedMatrix;
+
+/// Like `Kmeans`, but every cluster is represented by one of its members, so only
+/// pairwise distances are needed.
+pub struct KMedoids {
+    pub centers: usize,
+    pub max_iter: i32,
+    /// Row indices of the medoids, cluster `i` is represented by `medoids[i]`.
+    pub medoids: Vec<usize>,
+    /// The medoid rows, only filled when fitted on feature vectors.
+    pub centroids: Array2<f32>,
+    pub partition: Vec<i32>,
+    /// Sum of the distances of all points to their medoid.
+    pub inertia: f32
+}
+
+impl KMedoids {
+
+    pub fn new(centers: usize) -> KMedoids {
+        KMedoids {
+            centers,
+            max_iter: 100,
+            medoids: Vec::new(),
+            centroids: Array2::zeros((0, 0)),
+            partition: Vec::new(),
+            inertia: 0.0
+        }
+    }
+
+    pub fn set_max_iter(&mut self, max_iter: i32) {
+        self.max_iter = max_iter;
+    }
+
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        self.fit_predict_precomputed(&CondensedMatrix::euclidean(data));
+        self.centroids = data.select(Axis(0), &self.medoids);
+        self.partition.clone()
+    }
+
+    /// Greedy BUILD initialisation followed by alternating between assigning points to
+    /// their nearest medoid and moving every medoid to the member closest to the others.
+    pub fn fit_predict_precomputed(&mut self, distances: &CondensedMatrix) -> Vec<i32> {
+        let n = distances.n_points();
+        self.centroids = Array2::zeros((0, 0));
+        if n == 0 || self.centers == 0 {
+            self.medoids = Vec::new();
+            self.partition = vec![0; n];
+            self.inertia = 0.0;
+            return self.partition.clone();
+        }
+        self.medoids = build(distances, self.centers.min(n));
+        let mut labels = assign(distances, &self.medoids);
+        for _ in 0..self.max_iter {
+            let mut changed = false;
+            for (k, medoid) in self.medoids.iter_mut().enumerate() {
+                let members: Vec<usize> = (0..n).filter(|&i| labels[i] == k).collect();
+                let cost = |candidate: usize| members.iter().map(|&i| distances.get(i, candidate)).sum::<f32>();
+                let mut best = (*medoid, cost(*medoid));
+                for &candidate in members.iter() {
+                    let total = cost(candidate);
+                    if total < best.1 {
+                        best = (candidate, total);
+                    }
+                }
+                if best.0 != *medoid {
+                    *medoid = best.0;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            labels = assign(distances, &self.medoids);
+        }
+        self.inertia = (0..n).map(|i| distances.get(i, self.medoids[labels[i]])).sum();
+        self.partition = labels.into_iter().map(|k| k.to_i32().unwrap()).collect();
+        self.partition.clone()
+    }
+}
+
+/// Picks the most central point first, then repeatedly the point lowering the
+/// total distance to the nearest medoid the most.
+fn build(distances: &CondensedMatrix, k: usize) -> Vec<usize> {
+    let n = distances.n_points();
+    let mut nearest = vec![f32::INFINITY; n];
+    let mut medoids = Vec::with_capacity(k);
+    for _ in 0..k {
+        let mut best = (usize::MAX, f32::INFINITY);
+        for candidate in (0..n).filter(|c| !medoids.contains(c)) {
+            let total: f32 = (0..n).map(|i| nearest[i].min(distances.get(i, candidate))).sum();
+            if total < best.1 || best.0 == usize::MAX {
+                best = (candidate, total);
+            }
+        }
+        medoids.push(best.0);
+        for (i, dist) in nearest.iter_mut().enumerate() {
+            *dist = dist.min(distances.get(i, best.0));
+        }
+    }
+    medoids
+}
+
+/// Index into `medoids` of the nearest medoid for every point.
+fn assign(distances: &CondensedMatrix, medoids: &[usize]) -> Vec<usize> {
+    (0..distances.n_points())
+        .map(|i| {
+            let mut best = 0;
+            for (k, &medoid) in medoids.iter().enumerate() {
+                if distances.get(i, medoid) < distances.get(i, medoids[best]) {
+                    best = k;
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_algos::error::ClusterError;
+
+    /// Two plus-shaped blobs of five points, centred on rows 0 and 5.
+    fn blobs() -> Array2<f32> {
+        let plus = array![[0.0, 0.0], [1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]];
+        ndarray::concatenate![Axis(0), plus, &plus + &array![10.0, 10.0]]
+    }
+
+    fn square_distances(data: &Array2<f32>) -> Array2<f32> {
+        let n = data.shape()[0];
+        Array2::from_shape_fn((n, n), |(i, j)| (&data.row(i) - &data.row(j)).mapv(|x| x * x).sum().sqrt())
+    }
+
+    #[test]
+    fn finds_the_blob_centres() {
+        let mut model = KMedoids::new(2);
+        let labels = model.fit_predict(&blobs());
+        assert_eq!(model.medoids, vec![0, 5]);
+        assert_eq!(labels, vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(model.centroids, array![[0.0, 0.0], [10.0, 10.0]]);
+        assert_eq!(model.inertia, 8.0);
+    }
+
+    #[test]
+    fn precomputed_matches_euclidean() {
+        let data = blobs();
+        let mut euclidean = KMedoids::new(2);
+        let labels = euclidean.fit_predict(&data);
+        let distances = CondensedMatrix::from_square(&square_distances(&data)).unwrap();
+        let mut precomputed = KMedoids::new(2);
+        assert_eq!(precomputed.fit_predict_precomputed(&distances), labels);
+        assert_eq!(precomputed.medoids, euclidean.medoids);
+        assert!((precomputed.inertia - euclidean.inertia).abs() < 1e-5);
+        assert_eq!(precomputed.centroids.len(), 0);
+    }
+
+    #[test]
+    fn alternating_improves_on_build() {
+        // BUILD starts from 4 and 11, moving the first medoid to 2 lowers the cost from 8 to 6
+        let data = array![[1.0], [2.0], [4.0], [9.0], [11.0], [12.0]];
+        let distances = CondensedMatrix::from_square(&square_distances(&data)).unwrap();
+        let mut model = KMedoids::new(2);
+        model.set_max_iter(0);
+        model.fit_predict_precomputed(&distances);
+        assert_eq!(model.medoids, vec![2, 4]);
+        assert_eq!(model.inertia, 8.0);
+        model.set_max_iter(100);
+        assert_eq!(model.fit_predict_precomputed(&distances), vec![0, 0, 0, 1, 1, 1]);
+        assert_eq!(model.medoids, vec![1, 4]);
+        assert_eq!(model.inertia, 6.0);
+    }
+
+    #[test]
+    fn malformed_precomputed_matrices_are_rejected() {
+        let not_square = array![[0.0, 1.0, 2.0], [1.0, 0.0, 3.0]];
+        assert!(matches!(CondensedMatrix::from_square(&not_square), Err(ClusterError::InvalidInput(_))));
+        let asymmetric = array![[0.0, 1.0, 2.0], [1.0, 0.0, 3.0], [2.0, 4.0, 0.0]];
+        assert!(matches!(CondensedMatrix::from_square(&asymmetric), Err(ClusterError::InvalidInput(_))));
+    }
+}
\ No newline at end of file
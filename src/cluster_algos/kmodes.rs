@@ -0,0 +1,443 @@
+use rand::prelude::*;
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use std::collections::HashMap;
+
+/// Seeding strategy of `KModes` and `KPrototypes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeInit {
+    /// Huang (1998): draws every attribute of a seed from its overall frequencies, then
+    /// replaces the seed by the nearest row not chosen yet. Random, so restarted `retries` times.
+    Huang,
+    /// Cao et al. (2009): starts from the densest row, then repeatedly takes the row
+    /// maximising density times dissimilarity to the nearest seed. Deterministic.
+    Cao
+}
+
+/// Kmeans for categorical data: clusters are represented by their mode, the most
+/// frequent category of every column, and compared by the number of mismatching columns.
+/// Categories are integer codes, see `encode_categorical`.
+pub struct KModes {
+    pub centers: usize,
+    pub max_iter: i32,
+    pub retries: i32,
+    pub init: ModeInit,
+    pub modes: Array2<u32>,
+    pub partition: Vec<i32>,
+    /// Total number of mismatches between the points and their mode.
+    pub cost: f32
+}
+
+impl KModes {
+
+    pub fn new(centers: usize) -> KModes {
+        KModes {
+            centers,
+            max_iter: 100,
+            retries: 10,
+            init: ModeInit::Cao,
+            modes: Array2::zeros((0, 0)),
+            partition: Vec::new(),
+            cost: 0.0
+        }
+    }
+
+    pub fn set_init(&mut self, init: ModeInit) {
+        self.init = init;
+    }
+
+    pub fn set_fitting_time(&mut self, max_iter: i32, retries: i32) {
+        self.max_iter = max_iter;
+        self.retries = retries;
+    }
+
+    pub fn fit_predict(&mut self, data: &Array2<u32>) -> Vec<i32> {
+        let numeric = Array2::zeros((data.shape()[0], 0));
+        let fit = fit_mixed(&numeric, data, self.centers, 1.0, self.init, self.max_iter, self.retries);
+        self.modes = fit.modes;
+        self.cost = fit.cost;
+        self.partition = fit.partition;
+        self.partition.clone()
+    }
+
+    /// Labels new rows with their nearest mode.
+    pub fn predict(&self, data: &Array2<u32>) -> Vec<i32> {
+        data.rows()
+            .into_iter()
+            .map(|row| nearest_mode(row, &self.modes).0.to_i32().unwrap())
+            .collect()
+    }
+}
+
+/// Combines `Kmeans` on the numeric columns with `KModes` on the categorical ones:
+/// the dissimilarity is the squared Euclidean distance plus `gamma` times the number
+/// of mismatching categories.
+pub struct KPrototypes {
+    pub centers: usize,
+    pub max_iter: i32,
+    pub retries: i32,
+    pub init: ModeInit,
+    /// Weight of a categorical mismatch. `None` uses half the mean standard deviation
+    /// of the numeric columns, as suggested by Huang.
+    pub gamma: Option<f32>,
+    pub centroids: Array2<f32>,
+    pub modes: Array2<u32>,
+    pub partition: Vec<i32>,
+    pub cost: f32
+}
+
+impl KPrototypes {
+
+    pub fn new(centers: usize) -> KPrototypes {
+        KPrototypes {
+            centers,
+            max_iter: 100,
+            retries: 10,
+            init: ModeInit::Cao,
+            gamma: None,
+            centroids: Array2::zeros((0, 0)),
+            modes: Array2::zeros((0, 0)),
+            partition: Vec::new(),
+            cost: 0.0
+        }
+    }
+
+    pub fn set_init(&mut self, init: ModeInit) {
+        self.init = init;
+    }
+
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = Some(gamma);
+    }
+
+    pub fn set_fitting_time(&mut self, max_iter: i32, retries: i32) {
+        self.max_iter = max_iter;
+        self.retries = retries;
+    }
+
+    /// Clusters rows given as their numeric and categorical columns, which must have
+    /// the same number of rows.
+    pub fn fit_predict(&mut self, numeric: &Array2<f32>, categorical: &Array2<u32>) -> Vec<i32> {
+        assert_eq!(numeric.shape()[0], categorical.shape()[0], "numeric and categorical columns need the same number of rows");
+        let gamma = self.gamma.unwrap_or_else(|| default_gamma(numeric));
+        let fit = fit_mixed(numeric, categorical, self.centers, gamma, self.init, self.max_iter, self.retries);
+        self.gamma = Some(gamma);
+        self.centroids = fit.centroids;
+        self.modes = fit.modes;
+        self.cost = fit.cost;
+        self.partition = fit.partition;
+        self.partition.clone()
+    }
+
+    /// Labels new rows with their nearest prototype. Must be called after `fit_predict`.
+    pub fn predict(&self, numeric: &Array2<f32>, categorical: &Array2<u32>) -> Vec<i32> {
+        let gamma = self.gamma.expect("predict called before fit_predict");
+        (0..numeric.shape()[0])
+            .map(|i| nearest_prototype(numeric.row(i), categorical.row(i), &self.centroids, &self.modes, gamma).0.to_i32().unwrap())
+            .collect()
+    }
+}
+
+/// Number of columns in which `a` and `b` differ.
+pub fn mismatches(a: ArrayView1<u32>, b: ArrayView1<u32>) -> usize {
+    a.iter().zip(b.iter()).filter(|(x, y)| x != y).count()
+}
+
+/// Encodes string records column by column as integer codes, in order of first
+/// appearance. Returns the codes and the levels of every column to decode them.
+pub fn encode_categorical(records: &[Vec<&str>]) -> (Array2<u32>, Vec<Vec<String>>) {
+    let width = records.first().map_or(0, |record| record.len());
+    let mut codes = Array2::<u32>::zeros((records.len(), width));
+    let mut levels: Vec<Vec<String>> = vec![Vec::new(); width];
+    let mut lookup: Vec<HashMap<&str, u32>> = vec![HashMap::new(); width];
+    for (i, record) in records.iter().enumerate() {
+        assert_eq!(record.len(), width, "record {} has {} columns, expected {}", i, record.len(), width);
+        for (j, &value) in record.iter().enumerate() {
+            codes[[i, j]] = *lookup[j].entry(value).or_insert_with(|| {
+                levels[j].push(value.to_string());
+                (levels[j].len() - 1) as u32
+            });
+        }
+    }
+    (codes, levels)
+}
+
+struct MixedFit {
+    centroids: Array2<f32>,
+    modes: Array2<u32>,
+    partition: Vec<i32>,
+    cost: f32
+}
+
+/// Alternates between assigning rows to their nearest prototype and moving every
+/// prototype to the mean and per-column mode of its members. Keeps the cheapest restart.
+fn fit_mixed(numeric: &Array2<f32>, categorical: &Array2<u32>, centers: usize, gamma: f32, init: ModeInit, max_iter: i32, retries: i32) -> MixedFit {
+    let n = categorical.shape()[0];
+    let k = centers.min(n);
+    let runs = if init == ModeInit::Cao { 1 } else { retries.max(1) };
+    let mut best = MixedFit {
+        centroids: Array2::zeros((k, numeric.shape()[1])),
+        modes: Array2::zeros((k, categorical.shape()[1])),
+        partition: vec![0; n],
+        cost: f32::INFINITY
+    };
+    if k == 0 {
+        best.cost = 0.0;
+        return best;
+    }
+    for _ in 0..runs {
+        let seeds = match init {
+            ModeInit::Huang => huang_seeds(numeric, categorical, k, gamma),
+            ModeInit::Cao => cao_seeds(numeric, categorical, k, gamma)
+        };
+        let mut centroids = numeric.select(Axis(0), &seeds);
+        let mut modes = categorical.select(Axis(0), &seeds);
+        let mut labels = vec![usize::MAX; n];
+        let mut cost = 0.0;
+        for _ in 0..max_iter.max(1) {
+            let mut changed = false;
+            cost = 0.0;
+            for (i, label) in labels.iter_mut().enumerate() {
+                let (nearest, dist) = nearest_prototype(numeric.row(i), categorical.row(i), &centroids, &modes, gamma);
+                changed |= *label != nearest;
+                *label = nearest;
+                cost += dist;
+            }
+            if !changed {
+                break;
+            }
+            update_prototypes(numeric, categorical, &labels, &mut centroids, &mut modes);
+        }
+        if cost < best.cost {
+            best = MixedFit {
+                centroids,
+                modes,
+                partition: labels.iter().map(|&label| label.to_i32().unwrap()).collect(),
+                cost
+            };
+        }
+    }
+    best
+}
+
+/// Moves every prototype to the mean and most frequent categories of its members,
+/// ties going to the smallest code. Empty clusters keep their prototype.
+fn update_prototypes(numeric: &Array2<f32>, categorical: &Array2<u32>, labels: &[usize], centroids: &mut Array2<f32>, modes: &mut Array2<u32>) {
+    for c in 0..modes.shape()[0] {
+        let members: Vec<usize> = (0..labels.len()).filter(|&i| labels[i] == c).collect();
+        if members.is_empty() {
+            continue;
+        }
+        if numeric.shape()[1] > 0 {
+            centroids.row_mut(c).assign(&numeric.select(Axis(0), &members).mean_axis(Axis(0)).unwrap());
+        }
+        for j in 0..categorical.shape()[1] {
+            let mut frequencies: HashMap<u32, usize> = HashMap::new();
+            for &i in members.iter() {
+                *frequencies.entry(categorical[[i, j]]).or_insert(0) += 1;
+            }
+            modes[[c, j]] = frequencies
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+fn nearest_mode(row: ArrayView1<u32>, modes: &Array2<u32>) -> (usize, usize) {
+    let mut best = (0, usize::MAX);
+    for (c, mode) in modes.rows().into_iter().enumerate() {
+        let dist = mismatches(row, mode);
+        if dist < best.1 {
+            best = (c, dist);
+        }
+    }
+    best
+}
+
+fn nearest_prototype(numeric: ArrayView1<f32>, categorical: ArrayView1<u32>, centroids: &Array2<f32>, modes: &Array2<u32>, gamma: f32) -> (usize, f32) {
+    let mut best = (0, f32::INFINITY);
+    for c in 0..modes.shape()[0] {
+        let dist = dissimilarity(numeric, categorical, centroids.row(c), modes.row(c), gamma);
+        if dist < best.1 {
+            best = (c, dist);
+        }
+    }
+    best
+}
+
+/// Huang's mixed dissimilarity: squared Euclidean distance of the numeric parts plus
+/// `gamma` times the number of mismatching categories.
+fn dissimilarity(numeric: ArrayView1<f32>, categorical: ArrayView1<u32>, centroid: ArrayView1<f32>, mode: ArrayView1<u32>, gamma: f32) -> f32 {
+    let diff = &numeric - &centroid;
+    diff.dot(&diff) + gamma * mismatches(categorical, mode) as f32
+}
+
+/// Half the mean standard deviation of the numeric columns, 1 without any.
+fn default_gamma(numeric: &Array2<f32>) -> f32 {
+    if numeric.shape()[0] == 0 || numeric.shape()[1] == 0 {
+        return 1.0;
+    }
+    0.5 * numeric.std_axis(Axis(0), 0.0).mean().unwrap()
+}
+
+/// Per column, how often every category occurs.
+fn column_frequencies(data: &Array2<u32>) -> Vec<HashMap<u32, usize>> {
+    data.columns()
+        .into_iter()
+        .map(|column| {
+            let mut frequencies = HashMap::new();
+            for &value in column.iter() {
+                *frequencies.entry(value).or_insert(0) += 1;
+            }
+            frequencies
+        })
+        .collect()
+}
+
+/// Draws every numeric attribute of a seed from a normal distribution with the
+/// column's mean and standard deviation, every categorical one from the column's
+/// frequencies, then takes the nearest row not chosen yet under `dissimilarity`.
+fn huang_seeds(numeric: &Array2<f32>, categorical: &Array2<u32>, k: usize, gamma: f32) -> Vec<usize> {
+    let mut rng = thread_rng();
+    let frequencies = column_frequencies(categorical);
+    let means = numeric.mean_axis(Axis(0)).unwrap_or_else(|| Array1::zeros(numeric.shape()[1]));
+    let stds = numeric.std_axis(Axis(0), 0.0);
+    let mut seeds: Vec<usize> = Vec::with_capacity(k);
+    for _ in 0..k {
+        let centroid: Array1<f32> = means
+            .iter()
+            .zip(stds.iter())
+            .map(|(mean, std)| mean + std * standard_normal(&mut rng))
+            .collect();
+        let mode: Array1<u32> = frequencies
+            .iter()
+            .map(|column| {
+                let mut categories: Vec<(u32, usize)> = column.iter().map(|(&value, &count)| (value, count)).collect();
+                categories.sort_unstable();
+                categories.choose_weighted(&mut rng, |category| category.1).unwrap().0
+            })
+            .collect();
+        let distances: Vec<f32> = (0..categorical.shape()[0])
+            .map(|i| dissimilarity(numeric.row(i), categorical.row(i), centroid.view(), mode.view(), gamma))
+            .collect();
+        let nearest = (0..categorical.shape()[0])
+            .filter(|i| !seeds.contains(i))
+            .min_by(|&a, &b| distances[a].total_cmp(&distances[b]))
+            .unwrap();
+        seeds.push(nearest);
+    }
+    seeds
+}
+
+/// Box-Muller transform of two uniform draws.
+fn standard_normal(rng: &mut impl Rng) -> f32 {
+    let u: f32 = 1.0 - rng.gen::<f32>();
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
+
+/// Density is measured on the categories alone, as in Cao et al., while the distance
+/// to the nearest seed is the mixed `dissimilarity`, so numeric columns spread the
+/// seeds as well.
+fn cao_seeds(numeric: &Array2<f32>, categorical: &Array2<u32>, k: usize, gamma: f32) -> Vec<usize> {
+    let frequencies = column_frequencies(categorical);
+    let density: Vec<f32> = categorical.rows()
+        .into_iter()
+        .map(|row| row.iter().enumerate().map(|(j, value)| frequencies[j][value] as f32).sum())
+        .collect();
+    let distance = |i: usize, seed: usize| dissimilarity(numeric.row(i), categorical.row(i), numeric.row(seed), categorical.row(seed), gamma);
+    let first = (0..density.len()).max_by(|&a, &b| density[a].total_cmp(&density[b]).then(b.cmp(&a))).unwrap();
+    let mut seeds = vec![first];
+    let mut nearest: Vec<f32> = (0..density.len()).map(|i| distance(i, first)).collect();
+    while seeds.len() < k {
+        let next = (0..density.len())
+            .filter(|i| !seeds.contains(i))
+            .max_by(|&a, &b| (density[a] * nearest[a]).total_cmp(&(density[b] * nearest[b])).then(b.cmp(&a)))
+            .unwrap();
+        seeds.push(next);
+        for (i, dist) in nearest.iter_mut().enumerate() {
+            *dist = dist.min(distance(i, next));
+        }
+    }
+    seeds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two numeric groups far apart that share all their categories.
+    fn numeric_groups() -> (Array2<f32>, Array2<u32>) {
+        let numeric = array![[0.0], [0.1], [0.2], [100.0], [100.1], [100.2]];
+        (numeric, Array2::zeros((6, 2)))
+    }
+
+    #[test]
+    fn cao_seeds_spread_over_numeric_columns() {
+        let (numeric, categorical) = numeric_groups();
+        let seeds = cao_seeds(&numeric, &categorical, 2, 1.0);
+        assert!(seeds[0] < 3 && seeds[1] >= 3, "seeds {:?} lie in one group", seeds);
+    }
+
+    #[test]
+    fn huang_seeds_spread_over_numeric_columns() {
+        let (numeric, categorical) = numeric_groups();
+        // both draws fall on the same side of the mean half the time
+        let spread = (0..50).any(|_| {
+            let seeds = huang_seeds(&numeric, &categorical, 2, 1.0);
+            (seeds[0] < 3) != (seeds[1] < 3)
+        });
+        assert!(spread);
+    }
+
+    #[test]
+    fn kprototypes_separates_numeric_groups() {
+        let (numeric, categorical) = numeric_groups();
+        let mut model = KPrototypes::new(2);
+        assert_eq!(model.fit_predict(&numeric, &categorical), vec![0, 0, 0, 1, 1, 1]);
+    }
+    /// Two groups around the modes (0, 0, 0) and (1, 1, 1) with one deviating column
+    /// in half of the rows.
+    fn categories() -> Array2<u32> {
+        array![
+            [0, 0, 0], [0, 0, 1], [0, 2, 0], [0, 0, 0],
+            [1, 1, 1], [1, 1, 2], [2, 1, 1], [1, 1, 1]
+        ]
+    }
+
+    #[test]
+    fn cao_seeds_weigh_density_by_mismatches() {
+        // densities are 10, 11, 8, 10, 11, 8, 9, 11: row 1 comes first, then row 5 with
+        // 8 times 3 mismatches beats rows 4 and 7 with 11 times 2
+        let data = categories();
+        assert_eq!(cao_seeds(&Array2::zeros((8, 0)), &data, 2, 1.0), vec![1, 5]);
+    }
+
+    #[test]
+    fn kmodes_finds_the_modes() {
+        let data = categories();
+        let mut model = KModes::new(2);
+        assert_eq!(model.fit_predict(&data), vec![0, 0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(model.modes, array![[0, 0, 0], [1, 1, 1]]);
+        assert_eq!(model.cost, 4.0);
+    }
+
+    #[test]
+    fn kmodes_predicts_the_nearest_mode() {
+        let data = categories();
+        let mut model = KModes::new(2);
+        model.fit_predict(&data);
+        assert_eq!(model.predict(&array![[0, 0, 2], [2, 1, 2], [1, 0, 1], [3, 3, 0]]), vec![0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn encodes_categories_in_order_of_appearance() {
+        let records = vec![vec!["red", "small"], vec!["blue", "medium"], vec!["red", "medium"], vec!["green", "small"]];
+        let (codes, levels) = encode_categorical(&records);
+        assert_eq!(codes, array![[0, 0], [1, 1], [0, 1], [2, 0]]);
+        assert_eq!(levels, vec![vec!["red", "blue", "green"], vec!["small", "medium"]]);
+        assert_eq!(mismatches(codes.row(0), codes.row(2)), 1);
+    }
+}
\ No newline at end of file
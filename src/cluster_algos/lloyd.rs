@@ -1,8 +1,12 @@
 use std::vec;
 use rand::prelude::*;
-use ndarray::{Array, Array1, Array2};
+use ndarray::{s, Array, Array1, Array2, ArrayView1, Axis};
 use num::ToPrimitive;
-use crate::utils::mathfuncs::{silhouette_score, l2, cumsum};
+use crate::utils::mathfuncs::{silhouette_score, l2, l2_view, cumsum, square};
+use crate::utils::sparse::CsrMatrix;
+use crate::utils::stream::ChunkReader;
+use crate::cluster_algos::error::ClusterError;
+use std::io;
 
 pub struct Kmeans {
     pub centers: i32 ,
@@ -12,7 +16,14 @@ pub struct Kmeans {
     pub partition: Vec<i32>,
     pub initializer: &'static str,
     pub max_iter: i32,
-    pub retries: i32
+    pub retries: i32,
+    /// Weight of the points seen by every center, continued by `partial_fit`.
+    pub counts: Vec<f32>,
+    /// Factor the counts decay by before every `partial_fit` batch, 1 keeps all history.
+    pub forgetting: f32,
+    /// Rows `partial_fit` buffers before seeding the centers.
+    pub init_size: usize,
+    pending: Vec<Array1<f32>>
 }
 
 impl Kmeans {
@@ -26,7 +37,11 @@ impl Kmeans {
             partition: vec![0; data.shape()[0]],
             initializer: "kmeans++",
             max_iter: 100,
-            retries: 10
+            retries: 10,
+            counts: Vec::new(),
+            forgetting: 1.0,
+            init_size: 3 * centers.max(1).to_usize().unwrap(),
+            pending: Vec::new()
         }
     }
 
@@ -47,6 +62,17 @@ impl Kmeans {
         self.max_centers = max_centers;
     }
 
+    /// Lower values adapt faster to drifting data, a batch seen `t` batches ago
+    /// weighs `forgetting^t` times as much as the current one.
+    pub fn set_forgetting_factor(&mut self, forgetting: f32) {
+        assert!(forgetting > 0.0 && forgetting <= 1.0, "forgetting factor must be in (0, 1]");
+        self.forgetting = forgetting;
+    }
+
+    pub fn set_init_size(&mut self, init_size: usize) {
+        self.init_size = init_size;
+    }
+
     fn initialize(&mut self, data: &Array2<f32>, n_centers: i32){
         self.centroids = Array::<f32,_>::zeros((n_centers.to_usize().unwrap(), data.raw_dim()[1]));
         if self.initializer == "random_choice" {
@@ -60,11 +86,13 @@ impl Kmeans {
         }
     }
 
-    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+    /// Fits `centers` clusters, or searches 2 up to `max_centers - 1` clusters if `centers`
+    /// is 0, keeping the run with the best silhouette score.
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Result<Vec<i32>, ClusterError> {
+        self.check_parameters()?;
+        let mut best: Option<(Array2<f32>, Vec<i32>)> = None;
+        let mut minimum = -f32::INFINITY;
         if self.centers > 0 {
-            let mut best_centroids = Array2::<f32>::zeros((self.centroids.shape()[0], self.centroids.shape()[1]));
-            let mut best_partition: Vec<i32> = vec![0; self.partition.len()];
-            let mut minimum = -f32::INFINITY;
             for _ in 0..self.retries{
                 self.initialize(data, self.centers);
                 let mut count = 0;
@@ -76,19 +104,13 @@ impl Kmeans {
                     self.update_centroids(data);
                 }
                 let score = silhouette_score(data.clone(), self.partition.clone(), self.centroids.clone());
-                if score > minimum {
+                if best.is_none() || score > minimum {
                     minimum = score;
-                    best_centroids = self.centroids.clone();
-                    best_partition = self.partition.clone();
+                    best = Some((self.centroids.clone(), self.partition.clone()));
                 }
             }
-            self.partition = best_partition;
-            self.centroids = best_centroids;
         }
         else {
-            let mut best_centroids = Array2::<f32>::zeros((self.centroids.shape()[0], self.centroids.shape()[1]));
-            let mut best_partition: Vec<i32> = vec![0; self.partition.len()];
-            let mut minimum = -f32::INFINITY;
             for _ in 0..self.retries {
                 for i in 2..self.max_centers{
                     self.initialize(data, i);
@@ -102,20 +124,200 @@ impl Kmeans {
                         self.update_centroids(data);
                     }
                     let score = silhouette_score(data.clone(), self.partition.clone(), self.centroids.clone());
-                    if score > minimum {
+                    if best.is_none() || score > minimum {
                         minimum = score;
-                        best_centroids = self.centroids.clone();
-                        best_partition = self.partition.clone();
+                        best = Some((self.centroids.clone(), self.partition.clone()));
                     }
                     if score > self.accept{
                         break;
                     }
                 }
             }
-            self.partition = best_partition;
-            self.centroids = best_centroids;
         }
-        return self.partition.clone();
+        let Some((centroids, partition)) = best else {
+            return Err(ClusterError::InvalidParameter("no run produced centroids".to_string()));
+        };
+        self.centroids = centroids;
+        self.partition = partition;
+        self.count_members();
+        Ok(self.partition.clone())
+    }
+
+    /// Rejects settings under which `fit_predict` would not run a single iteration.
+    fn check_parameters(&self) -> Result<(), ClusterError> {
+        if self.retries < 1 {
+            return Err(ClusterError::InvalidParameter(format!("retries must be at least 1, got {}", self.retries)));
+        }
+        if self.max_iter < 1 {
+            return Err(ClusterError::InvalidParameter(format!("max_iter must be at least 1, got {}", self.max_iter)));
+        }
+        if self.centers < 0 {
+            return Err(ClusterError::InvalidParameter(format!("centers must not be negative, got {}", self.centers)));
+        }
+        if self.centers == 0 && self.max_centers <= 2 {
+            return Err(ClusterError::InvalidParameter(format!("max_centers must be above 2 to search the number of centers, got {}", self.max_centers)));
+        }
+        Ok(())
+    }
+
+    /// Updates the centers with a batch of new points, moving the nearest center of every
+    /// point towards it by one over its count. The first `init_size` rows are buffered and
+    /// clustered with `fit_predict`'s iterations to seed the centers.
+    pub fn partial_fit(&mut self, batch: &Array2<f32>) {
+        assert!(self.centers > 0, "partial_fit needs a fixed number of centers");
+        if self.counts.is_empty() {
+            self.pending.extend(batch.rows().into_iter().map(|row| row.to_owned()));
+            if self.pending.len() < self.init_size.max(self.centers.to_usize().unwrap()) {
+                return;
+            }
+            let rows: Vec<ArrayView1<f32>> = self.pending.iter().map(|row| row.view()).collect();
+            let data = ndarray::stack(Axis(0), &rows).unwrap();
+            self.pending.clear();
+            self.partition = vec![0; data.shape()[0]];
+            self.initialize(&data, self.centers);
+            let mut count = 0;
+            let mut last_centroids = Array2::zeros(self.centroids.raw_dim());
+            while check_if_finished(&self.centroids - last_centroids.clone()) && count < self.max_iter {
+                count += 1;
+                last_centroids = self.centroids.clone();
+                self.update_partitions(&data);
+                self.update_centroids(&data);
+            }
+            self.count_members();
+            return;
+        }
+        for count in self.counts.iter_mut() {
+            *count *= self.forgetting;
+        }
+        for point in batch.rows() {
+            let nearest = self.nearest(point);
+            self.counts[nearest] += 1.0;
+            let rate = 1.0 / self.counts[nearest];
+            let mut centroid = self.centroids.row_mut(nearest);
+            centroid.zip_mut_with(&point, |c, &x| *c += rate * (x - *c));
+        }
+    }
+
+    /// Runs `partial_fit` over every chunk of `reader`, `passes` times, for files larger
+    /// than memory. Files shorter than `init_size` rows are seeded from all their rows.
+    pub fn fit_chunks(&mut self, reader: &mut ChunkReader, passes: usize) -> io::Result<()> {
+        for _ in 0..passes {
+            reader.rewind()?;
+            while let Some(chunk) = reader.next_chunk()? {
+                self.partial_fit(&chunk);
+            }
+            if self.counts.is_empty() {
+                // seed from the buffered rows without changing the configured init size
+                let init_size = std::mem::replace(&mut self.init_size, 0);
+                self.partial_fit(&Array2::zeros((0, reader.dim().unwrap_or(0))));
+                self.init_size = init_size;
+            }
+            if self.counts.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} has fewer rows than centers", reader.path.display())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Labels every row with its nearest center.
+    pub fn predict(&self, data: &Array2<f32>) -> Vec<i32> {
+        data.rows().into_iter().map(|point| self.nearest(point).to_i32().unwrap()).collect()
+    }
+
+    /// Distance of every row to every center, one column per center.
+    pub fn transform(&self, data: &Array2<f32>) -> Array2<f32> {
+        let mut distances = Array2::<f32>::zeros((data.shape()[0], self.centroids.shape()[0]));
+        for (i, point) in data.rows().into_iter().enumerate() {
+            for (j, centroid) in self.centroids.rows().into_iter().enumerate() {
+                distances[[i, j]] = l2_view(point, centroid);
+            }
+        }
+        distances
+    }
+
+    fn nearest(&self, point: ArrayView1<f32>) -> usize {
+        let mut best = (0, f32::INFINITY);
+        for (j, centroid) in self.centroids.rows().into_iter().enumerate() {
+            let dist = l2_view(point, centroid);
+            if dist < best.1 {
+                best = (j, dist);
+            }
+        }
+        best.0
+    }
+
+    fn count_members(&mut self) {
+        self.counts = vec![0.0; self.centroids.shape()[0]];
+        for &label in self.partition.iter() {
+            self.counts[label.to_usize().unwrap()] += 1.0;
+        }
+    }
+
+    /// Clusters the rows of a sparse matrix into `centers` clusters with dense centroids,
+    /// using `|x - c|^2 = |x|^2 - 2 x.c + |c|^2` so rows are never densified. Always seeds
+    /// with kmeans++ and keeps the restart with the lowest inertia.
+    pub fn fit_predict_sparse(&mut self, data: &CsrMatrix) -> Result<Vec<i32>, ClusterError> {
+        self.check_parameters()?;
+        if self.centers == 0 {
+            return Err(ClusterError::InvalidParameter("sparse input needs a fixed number of centers".to_string()));
+        }
+        let (n, dim) = data.shape();
+        let k = self.centers.to_usize().unwrap().min(n);
+        let norms = data.row_norms_squared();
+        let mut best_inertia = f32::INFINITY;
+        let mut best_centroids = Array2::<f32>::zeros((k, dim));
+        let mut best_partition = vec![0; n];
+        for _ in 0..self.retries {
+            let mut centroids = sparse_kmeanspp(data, &norms, k);
+            let mut partition = vec![usize::MAX; n];
+            let mut inertia = 0.0;
+            for _ in 0..self.max_iter {
+                let centroid_norms: Vec<f32> = centroids.rows().into_iter().map(|c| c.dot(&c)).collect();
+                let mut changed = false;
+                inertia = 0.0;
+                for (i, label) in partition.iter_mut().enumerate() {
+                    let mut min = f32::INFINITY;
+                    let mut best = 0;
+                    for (j, centroid) in centroids.rows().into_iter().enumerate() {
+                        let dist = norms[i] - 2.0 * data.dot_dense(i, centroid) + centroid_norms[j];
+                        if dist < min {
+                            min = dist;
+                            best = j;
+                        }
+                    }
+                    changed |= *label != best;
+                    *label = best;
+                    inertia += min.max(0.0);
+                }
+                if !changed {
+                    break;
+                }
+                // empty clusters keep their previous centroid
+                let mut sums = Array2::<f32>::zeros((k, dim));
+                let mut counts = vec![0.0f32; k];
+                for (i, &label) in partition.iter().enumerate() {
+                    let (columns, values) = data.row(i);
+                    for (&column, &value) in columns.iter().zip(values) {
+                        sums[[label, column]] += value;
+                    }
+                    counts[label] += 1.0;
+                }
+                for (j, &count) in counts.iter().enumerate() {
+                    if count > 0.0 {
+                        centroids.row_mut(j).assign(&(&sums.row(j) / count));
+                    }
+                }
+            }
+            if inertia < best_inertia {
+                best_inertia = inertia;
+                best_centroids = centroids;
+                best_partition = partition;
+            }
+        }
+        self.centroids = best_centroids;
+        self.partition = best_partition.into_iter().map(|label| label.to_i32().unwrap()).collect();
+        self.count_members();
+        Ok(self.partition.clone())
     }
 
     fn update_centroids(&mut self, data: &Array2<f32>) {
@@ -175,27 +377,64 @@ fn random_choice(data: &Array2<f32>, centroids: &mut Array2<f32>) {
 fn kmeanspp(data: &Array2<f32>, centroids: &mut Array2<f32>) {
     let mut rng = thread_rng();
     let mut points = vec![rng.gen_range(0..data.shape()[0])];
-    let clone_centroid = centroids.clone();
-    replace_values(centroids, &data, 0, points[0]);
+    replace_values(centroids, data, 0, points[0]);
     for i in 1..centroids.shape()[0] {
+        let chosen = centroids.slice(s![..i, ..]).to_owned();
         let mut probs = Array::<f32, _>::zeros(data.shape()[0]);
         for (j, point) in data.rows().into_iter().enumerate() {
             if is_in_vec(&points, &j){
                 continue;
             }
-            probs[j] = get_smallest_dist(point.to_owned(), clone_centroid.clone());
+            probs[j] = square(get_smallest_dist(point.to_owned(), chosen.clone()));
         }
-        probs /= probs.len().to_f32().unwrap();
+        let total = probs.sum();
+        if total <= 0.0 {
+            // every remaining point coincides with a chosen centroid
+            let j = (0..data.shape()[0]).find(|j| !is_in_vec(&points, j)).unwrap_or(points[0]);
+            points.push(j);
+            replace_values(centroids, data, i, j);
+            continue;
+        }
+        probs /= total;
         cumsum(&mut probs);
         let random_num = rng.gen_range(0.0..1.0);
-        for (j, prob) in probs.into_iter().enumerate() {
-            if random_num < prob {
-                points.push(j);
-                replace_values(centroids, data, i, j);
-                break;
-            }
+        let j = probs.iter().position(|&prob| random_num < prob).unwrap_or(data.shape()[0] - 1);
+        points.push(j);
+        replace_values(centroids, data, i, j);
+    }
+}
+
+/// kmeans++ seeding on sparse rows, returning the chosen rows as dense centroids.
+fn sparse_kmeanspp(data: &CsrMatrix, norms: &[f32], k: usize) -> Array2<f32> {
+    let mut rng = thread_rng();
+    let (n, dim) = data.shape();
+    let mut centroids = Array2::<f32>::zeros((k, dim));
+    if n == 0 {
+        return centroids;
+    }
+    let mut closest = vec![f32::INFINITY; n];
+    let mut chosen = rng.gen_range(0..n);
+    for i in 0..k {
+        let (columns, values) = data.row(chosen);
+        for (&column, &value) in columns.iter().zip(values) {
+            centroids[[i, column]] = value;
+        }
+        for (j, dist) in closest.iter_mut().enumerate() {
+            *dist = dist.min((norms[j] + norms[chosen] - 2.0 * data.dot_rows(j, chosen)).max(0.0));
         }
+        let total: f32 = closest.iter().sum();
+        if total <= 0.0 {
+            // every point coincides with a chosen centroid
+            chosen = (chosen + 1) % n;
+            continue;
+        }
+        let mut target = rng.gen_range(0.0..total);
+        chosen = closest.iter().position(|&dist| {
+            target -= dist;
+            target < 0.0
+        }).unwrap_or(n - 1);
     }
+    centroids
 }
 
 fn check_if_finished(x: Array2<f32>) -> bool {
@@ -206,7 +445,7 @@ fn check_if_finished(x: Array2<f32>) -> bool {
             }
         }
     }
-    return false;
+    false
 }
 
 fn replace_values (arr1: &mut Array2<f32>, arr2: &Array2<f32>, row: usize, row2: usize) {
@@ -215,26 +454,145 @@ fn replace_values (arr1: &mut Array2<f32>, arr2: &Array2<f32>, row: usize, row2:
     }
 }
 
-fn is_in_vec(vector: &Vec<usize>, value: &usize) -> bool {
-    for val in vector.into_iter() {
+fn is_in_vec(vector: &[usize], value: &usize) -> bool {
+    for val in vector.iter() {
         if val == value {
             return true;
         }
     }
-    return false;
+    false
 }
 
 fn get_smallest_dist (point: Array1<f32>, data: Array2<f32>) -> f32 {
     let mut minimum = f32::INFINITY;
-    let zeros = Array1::zeros(point.shape()[0]);
     for point2 in data.rows().into_iter() {
-        if point2 == zeros  {
-            continue;
-        }
         let dist: f32 = l2(&point, &point2.to_owned(), false);
         if dist < minimum {
             minimum = dist;
         }
     }
-    return minimum;
+    minimum
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::stream::ChunkFormat;
+    use ndarray::array;
+
+    #[test]
+    fn fit_chunks_keeps_init_size_on_short_files() {
+        let path = std::env::temp_dir().join(format!("lloyd_short_{}.csv", std::process::id()));
+        std::fs::write(&path, "0,0\n0,1\n10,0\n10,1\n").unwrap();
+        let mut reader = ChunkReader::open(&path, ChunkFormat::Csv { delimiter: ',', has_header: false }, 2).unwrap();
+        let data = Array2::<f32>::zeros((4, 2));
+        let mut model = Kmeans::new(&data, 2);
+        model.set_init_size(100);
+        let result = model.fit_chunks(&mut reader, 1);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(model.init_size, 100);
+        assert_eq!(model.centroids.shape(), &[2, 2]);
+    }
+    /// A model that has already seen two points near the first center and one near the second.
+    fn seeded() -> Kmeans {
+        let mut model = Kmeans::new(&Array2::zeros((0, 2)), 2);
+        model.centroids = array![[0.0, 0.0], [10.0, 0.0]];
+        model.counts = vec![2.0, 1.0];
+        model
+    }
+
+    #[test]
+    fn partial_fit_seeds_once_init_size_rows_are_seen() {
+        let mut model = Kmeans::new(&Array2::zeros((0, 2)), 2);
+        model.set_init_size(4);
+        model.partial_fit(&array![[0.0, 0.0], [10.0, 0.0]]);
+        assert!(model.counts.is_empty());
+        assert_eq!(model.pending.len(), 2);
+        model.partial_fit(&array![[0.2, 0.0], [10.2, 0.0]]);
+        assert!(model.pending.is_empty());
+        assert_eq!(model.counts, vec![2.0, 2.0]);
+        let mut centers: Vec<f32> = model.centroids.column(0).to_vec();
+        centers.sort_by(f32::total_cmp);
+        assert!((centers[0] - 0.1).abs() < 1e-6 && (centers[1] - 10.1).abs() < 1e-6, "{:?}", centers);
+    }
+
+    #[test]
+    fn partial_fit_moves_centers_by_one_over_their_count() {
+        let mut model = seeded();
+        model.partial_fit(&array![[1.0, 0.0], [11.0, 3.0]]);
+        assert_eq!(model.counts, vec![3.0, 2.0]);
+        // 0 + (1 - 0) / 3 and 10 + (11 - 10) / 2, 0 + (3 - 0) / 2
+        assert!((model.centroids[[0, 0]] - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(model.centroids.row(1), array![10.5, 1.5]);
+    }
+
+    #[test]
+    fn forgetting_factor_decays_old_counts() {
+        let mut model = seeded();
+        model.set_forgetting_factor(0.5);
+        model.partial_fit(&array![[1.0, 0.0]]);
+        // counts decay to [1, 0.5] before the point is added
+        assert_eq!(model.counts, vec![2.0, 0.5]);
+        assert_eq!(model.centroids.row(0), array![0.5, 0.0]);
+        model.partial_fit(&Array2::zeros((0, 2)));
+        assert_eq!(model.counts, vec![1.0, 0.25]);
+    }
+
+    #[test]
+    fn predict_and_transform_shapes() {
+        let model = seeded();
+        let data = array![[1.0, 0.0], [9.0, 0.0], [4.0, 3.0], [20.0, 0.0], [-1.0, 0.0]];
+        assert_eq!(model.predict(&data), vec![0, 1, 0, 1, 0]);
+        let distances = model.transform(&data);
+        assert_eq!(distances.shape(), &[5, 2]);
+        assert_eq!(distances.row(2), array![5.0, 45.0f32.sqrt()]);
+    }
+    /// Whether two labellings group the points the same way, whatever the label values.
+    fn same_partition(a: &[i32], b: &[i32]) -> bool {
+        a.len() == b.len() && (0..a.len()).all(|i| (0..a.len()).all(|j| (a[i] == a[j]) == (b[i] == b[j])))
+    }
+
+    /// Three separated groups of three points, mostly zeros as sparse data would be.
+    fn groups() -> Array2<f32> {
+        array![
+            [1.0, 0.0, 0.0, 0.0], [1.2, 0.0, 0.0, 0.0], [1.1, 0.1, 0.0, 0.0],
+            [0.0, 0.0, 5.0, 0.0], [0.0, 0.0, 5.1, 0.0], [0.0, 0.2, 5.0, 0.0],
+            [0.0, 0.0, 0.0, 9.0], [0.0, 0.0, 0.3, 9.0], [0.0, 0.0, 0.0, 9.2]
+        ]
+    }
+
+    #[test]
+    fn sparse_and_dense_fits_agree() {
+        let data = groups();
+        let mut dense = Kmeans::new(&data, 3);
+        let dense_labels = dense.fit_predict(&data).unwrap();
+        let mut sparse = Kmeans::new(&data, 3);
+        let sparse_labels = sparse.fit_predict_sparse(&CsrMatrix::from_dense(&data)).unwrap();
+        assert!(same_partition(&dense_labels, &sparse_labels), "{:?} vs {:?}", dense_labels, sparse_labels);
+        assert!(same_partition(&sparse_labels, &[0, 0, 0, 1, 1, 1, 2, 2, 2]), "{:?}", sparse_labels);
+        assert_eq!(sparse.counts, vec![3.0; 3]);
+    }
+
+    #[test]
+    fn sparse_fit_needs_a_number_of_centers() {
+        let data = groups();
+        let mut model = Kmeans::new(&data, 0);
+        assert!(matches!(model.fit_predict_sparse(&CsrMatrix::from_dense(&data)), Err(ClusterError::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn sparse_kmeanspp_seeds_distinct_rows() {
+        let data = groups();
+        let sparse = CsrMatrix::from_dense(&data);
+        for _ in 0..20 {
+            let seeds = sparse_kmeanspp(&sparse, &sparse.row_norms_squared(), 3);
+            let rows: Vec<usize> = seeds.rows()
+                .into_iter()
+                .map(|seed| data.rows().into_iter().position(|row| row == seed).expect("every seed is a data row"))
+                .collect();
+            // a chosen row is at distance 0 from its centroid and cannot be drawn again
+            assert!(rows[0] != rows[1] && rows[1] != rows[2] && rows[0] != rows[2], "{:?}", rows);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::dbscan::{kth_neighbour_distances, NOISE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    /// Every point within `bandwidth` has the same weight.
+    Flat,
+    /// Points are weighted by a gaussian with standard deviation `bandwidth`.
+    Gaussian
+}
+
+pub struct MeanShift {
+    /// Estimated with `estimate_bandwidth` on every fit if `None`.
+    pub bandwidth: Option<f32>,
+    /// Bandwidth the centers were found with, given or estimated, used by `predict`.
+    pub fitted_bandwidth: f32,
+    pub kernel: Kernel,
+    pub bin_seeding: bool,
+    pub min_bin_freq: usize,
+    pub cluster_all: bool,
+    pub max_iter: i32,
+    pub centroids: Array2<f32>,
+    pub partition: Vec<i32>
+}
+
+impl MeanShift {
+
+    pub fn new(data: &Array2<f32>) -> MeanShift {
+        MeanShift {
+            bandwidth: None,
+            fitted_bandwidth: f32::INFINITY,
+            kernel: Kernel::Flat,
+            bin_seeding: false,
+            min_bin_freq: 1,
+            cluster_all: true,
+            max_iter: 300,
+            centroids: Array2::zeros((0, data.shape()[1])),
+            partition: vec![0; data.shape()[0]]
+        }
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: f32) {
+        self.bandwidth = Some(bandwidth);
+    }
+
+    pub fn set_kernel(&mut self, kernel: Kernel) {
+        self.kernel = kernel;
+    }
+
+    /// Seeds from a grid of cell size `bandwidth` instead of from every point,
+    /// skipping cells with fewer than `min_bin_freq` points.
+    pub fn set_bin_seeding(&mut self, bin_seeding: bool, min_bin_freq: usize) {
+        self.bin_seeding = bin_seeding;
+        self.min_bin_freq = min_bin_freq;
+    }
+
+    /// If false, points farther than `bandwidth` from every center are labelled `NOISE`.
+    pub fn set_cluster_all(&mut self, cluster_all: bool) {
+        self.cluster_all = cluster_all;
+    }
+
+    pub fn set_max_iter(&mut self, max_iter: i32) {
+        self.max_iter = max_iter;
+    }
+
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        let bandwidth = self.bandwidth.unwrap_or_else(|| estimate_bandwidth(data, 0.3));
+        self.fitted_bandwidth = bandwidth;
+        let seeds = if self.bin_seeding { bin_seeds(data, bandwidth, self.min_bin_freq) } else { data.clone() };
+
+        let mut modes: Vec<(Array1<f32>, usize)> = seeds.rows()
+            .into_iter()
+            .map(|seed| self.shift_seed(data, seed.to_owned(), bandwidth))
+            .collect();
+        // keep the densest mode of every group of modes closer than the bandwidth
+        modes.sort_by_key(|mode| std::cmp::Reverse(mode.1));
+        // modes without points within the bandwidth are dropped, unless that leaves
+        // none, in which case the first seed's mode stands for a single cluster
+        let keep = modes.iter().filter(|mode| mode.1 > 0).count().max(1);
+        modes.truncate(keep);
+        let mut centers: Vec<Array1<f32>> = Vec::new();
+        for (mode, _) in modes.into_iter() {
+            if centers.iter().all(|center| l2(center, &mode, false) >= bandwidth) {
+                centers.push(mode);
+            }
+        }
+        self.centroids = Array2::zeros((centers.len(), data.shape()[1]));
+        for (i, center) in centers.iter().enumerate() {
+            self.centroids.row_mut(i).assign(center);
+        }
+        self.partition = self.predict(data);
+        self.partition.clone()
+    }
+
+    /// Labels points by their nearest center, or `NOISE` for orphans if `cluster_all` is false.
+    pub fn predict(&self, data: &Array2<f32>) -> Vec<i32> {
+        let bandwidth = self.fitted_bandwidth;
+        data.rows()
+            .into_iter()
+            .map(|point| {
+                let mut min = f32::INFINITY;
+                let mut best = NOISE;
+                for (j, center) in self.centroids.rows().into_iter().enumerate() {
+                    let dist = l2_view(point, center);
+                    if dist < min {
+                        min = dist;
+                        best = j.to_i32().unwrap();
+                    }
+                }
+                if !self.cluster_all && min > bandwidth {
+                    NOISE
+                }
+                else {
+                    best
+                }
+            })
+            .collect()
+    }
+
+    /// Moves `seed` uphill until it converges, returning the mode and the number
+    /// of points within `bandwidth` of it.
+    fn shift_seed(&self, data: &Array2<f32>, mut seed: Array1<f32>, bandwidth: f32) -> (Array1<f32>, usize) {
+        let stop_threshold = 1e-3 * bandwidth;
+        let mut intensity = 0;
+        for _ in 0..self.max_iter {
+            let mut weighted_sum = Array1::<f32>::zeros(seed.len());
+            let mut total_weight = 0.0;
+            intensity = 0;
+            for point in data.rows() {
+                let dist = l2_view(seed.view(), point);
+                if dist <= bandwidth {
+                    intensity += 1;
+                }
+                let weight = match self.kernel {
+                    Kernel::Flat => if dist <= bandwidth { 1.0 } else { 0.0 },
+                    Kernel::Gaussian => (-square(dist) / (2.0 * square(bandwidth))).exp()
+                };
+                if weight > 0.0 {
+                    weighted_sum.scaled_add(weight, &point);
+                    total_weight += weight;
+                }
+            }
+            if total_weight == 0.0 {
+                break;
+            }
+            let new_seed = weighted_sum / total_weight;
+            let shift = l2(&new_seed, &seed, false);
+            seed = new_seed;
+            if shift <= stop_threshold {
+                break;
+            }
+        }
+        (seed, intensity)
+    }
+}
+
+/// Mean distance of every point to its nearest `quantile` share of the data.
+pub fn estimate_bandwidth(data: &Array2<f32>, quantile: f32) -> f32 {
+    let n = data.shape()[0];
+    if n == 0 {
+        return 0.0;
+    }
+    let k = (n.to_f32().unwrap() * quantile).to_usize().unwrap().max(1);
+    let distances = kth_neighbour_distances(data, k);
+    distances.iter().sum::<f32>() / n.to_f32().unwrap()
+}
+
+/// Centers of the grid cells of size `bandwidth` holding at least `min_bin_freq` points.
+/// Falls back to the data itself if binning does not reduce the number of seeds.
+pub fn bin_seeds(data: &Array2<f32>, bandwidth: f32, min_bin_freq: usize) -> Array2<f32> {
+    if bandwidth <= 0.0 {
+        return data.clone();
+    }
+    let mut bins: HashMap<Vec<i64>, usize> = HashMap::new();
+    for point in data.rows() {
+        let bin: Vec<i64> = point.iter().map(|x| (x / bandwidth).round() as i64).collect();
+        *bins.entry(bin).or_insert(0) += 1;
+    }
+    let mut seeds: Vec<Vec<i64>> = bins.into_iter().filter(|(_, freq)| *freq >= min_bin_freq).map(|(bin, _)| bin).collect();
+    if seeds.len() == data.shape()[0] || seeds.is_empty() {
+        return data.clone();
+    }
+    seeds.sort();
+    let mut array = Array2::zeros((seeds.len(), data.shape()[1]));
+    for (i, bin) in seeds.iter().enumerate() {
+        for (j, coord) in bin.iter().enumerate() {
+            array[[i, j]] = *coord as f32 * bandwidth;
+        }
+    }
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5 x 5 grid of spacing 0.1 around `(x, y)`.
+    fn blob(x: f32, y: f32) -> Array2<f32> {
+        Array2::from_shape_fn((25, 2), |(i, j)| if j == 0 { x + (i % 5) as f32 * 0.1 } else { y + (i / 5) as f32 * 0.1 })
+    }
+
+    #[test]
+    fn estimates_the_bandwidth_on_every_fit() {
+        let small = blob(0.0, 0.0);
+        let large = blob(0.0, 0.0).mapv(|v| v * 100.0);
+        let mut model = MeanShift::new(&small);
+        model.fit_predict(&small);
+        assert_eq!(model.bandwidth, None);
+        model.fit_predict(&large);
+        assert_eq!(model.fitted_bandwidth, estimate_bandwidth(&large, 0.3));
+    }
+
+    #[test]
+    fn separates_two_blobs() {
+        let data = ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap();
+        let mut model = MeanShift::new(&data);
+        model.set_bandwidth(1.0);
+        let labels = model.fit_predict(&data);
+        assert_eq!(model.centroids.shape()[0], 2);
+        assert!(labels[..25].iter().all(|&label| label == labels[0]));
+        assert!(labels[25..].iter().all(|&label| label == labels[25]));
+        assert_ne!(labels[0], labels[25]);
+    }
+
+    #[test]
+    fn keeps_one_mode_when_all_are_empty() {
+        let data = blob(0.0, 0.0);
+        let mut model = MeanShift::new(&data);
+        model.set_bandwidth(1.0);
+        // no shift is made, so no mode counts any points
+        model.set_max_iter(0);
+        assert_eq!(model.fit_predict(&data), vec![0; 25]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,325 @@
+use ndarray::prelude::*;
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::dbscan::{kth_neighbour_distances, NOISE};
+use crate::cluster_algos::error::ClusterError;
+
+pub struct Optics {
+    pub min_points: usize,
+    pub max_epsilon: f32,
+    pub xi: f32,
+    pub min_cluster_size: usize,
+    pub ordering: Vec<usize>,
+    pub core_distances: Vec<f32>,
+    pub reachability: Vec<f32>,
+    pub predecessor: Vec<Option<usize>>,
+    /// `(start, end)` positions in `ordering` of the clusters found by `extract_xi`,
+    /// inner clusters before the clusters containing them.
+    pub xi_clusters: Vec<(usize, usize)>,
+    pub partitions: Vec<i32>
+}
+
+impl Optics {
+
+    pub fn new(min_points: usize) -> Optics {
+        Optics {
+            min_points,
+            max_epsilon: f32::INFINITY,
+            xi: 0.05,
+            min_cluster_size: min_points,
+            ordering: Vec::new(),
+            core_distances: Vec::new(),
+            reachability: Vec::new(),
+            predecessor: Vec::new(),
+            xi_clusters: Vec::new(),
+            partitions: Vec::new()
+        }
+    }
+
+    pub fn set_max_epsilon(&mut self, max_epsilon: f32) {
+        self.max_epsilon = max_epsilon;
+    }
+
+    pub fn set_xi(&mut self, xi: f32) {
+        self.xi = xi;
+    }
+
+    pub fn set_min_cluster_size(&mut self, min_cluster_size: usize) {
+        self.min_cluster_size = min_cluster_size;
+    }
+
+    /// Computes the cluster ordering together with core and reachability distances.
+    /// Both are `f32::INFINITY` where undefined within `max_epsilon`.
+    pub fn fit(&mut self, data: &Array2<f32>) {
+        let n = data.shape()[0];
+        self.core_distances = kth_neighbour_distances(data, self.min_points)
+            .into_iter()
+            .map(|dist| if dist <= self.max_epsilon { dist } else { f32::INFINITY })
+            .collect();
+        self.reachability = vec![f32::INFINITY; n];
+        self.predecessor = vec![None; n];
+        self.ordering = Vec::with_capacity(n);
+        let mut processed = vec![false; n];
+        for _ in 0..n {
+            // unprocessed point with the smallest reachability, ties broken by index
+            let mut point = usize::MAX;
+            for (j, &done) in processed.iter().enumerate() {
+                if !done && (point == usize::MAX || self.reachability[j] < self.reachability[point]) {
+                    point = j;
+                }
+            }
+            processed[point] = true;
+            self.ordering.push(point);
+            if self.core_distances[point].is_infinite() {
+                continue;
+            }
+            for (j, &done) in processed.iter().enumerate() {
+                if done {
+                    continue;
+                }
+                let dist = l2_view(data.row(point), data.row(j));
+                if dist > self.max_epsilon {
+                    continue;
+                }
+                let reach = dist.max(self.core_distances[point]);
+                if reach < self.reachability[j] {
+                    self.reachability[j] = reach;
+                    self.predecessor[j] = Some(point);
+                }
+            }
+        }
+    }
+
+    /// Fits the ordering and labels the data with `extract_xi`.
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Vec<i32> {
+        self.fit(data);
+        self.extract_xi()
+    }
+
+    /// Labels equivalent to running `DBScan` with `min_points` and `epsilon`, up to
+    /// the labelling of some border points. Distances beyond `max_epsilon` were never
+    /// computed, so a larger `epsilon` is rejected rather than clamped: the labels
+    /// would silently be those of `max_epsilon`.
+    pub fn extract_dbscan(&mut self, epsilon: f32) -> Result<Vec<i32>, ClusterError> {
+        if epsilon > self.max_epsilon {
+            return Err(ClusterError::InvalidParameter(format!("epsilon {} exceeds max_epsilon {} of the fitted ordering", epsilon, self.max_epsilon)));
+        }
+        let mut partitions = vec![NOISE; self.ordering.len()];
+        let mut label = NOISE;
+        for &point in self.ordering.iter() {
+            let near_core = self.core_distances[point] <= epsilon;
+            if self.reachability[point] > epsilon {
+                if near_core {
+                    label += 1;
+                    partitions[point] = label;
+                }
+            }
+            else {
+                partitions[point] = label;
+            }
+        }
+        self.partitions = partitions;
+        Ok(self.partitions.clone())
+    }
+
+    /// Labels points by the steep areas of the reachability plot. Points belong to
+    /// the innermost cluster containing them, points outside every cluster are `NOISE`.
+    pub fn extract_xi(&mut self) -> Vec<i32> {
+        self.xi_clusters = self.xi_cluster();
+        let mut ordered = vec![NOISE; self.ordering.len()];
+        let mut label = 0;
+        for &(start, end) in self.xi_clusters.iter() {
+            if ordered[start..=end].iter().all(|&l| l == NOISE) {
+                ordered[start..=end].iter_mut().for_each(|l| *l = label);
+                label += 1;
+            }
+        }
+        self.partitions = vec![NOISE; self.ordering.len()];
+        for (position, &point) in self.ordering.iter().enumerate() {
+            self.partitions[point] = ordered[position];
+        }
+        self.partitions.clone()
+    }
+
+    /// Reachability distances in cluster order, as shown in a reachability plot.
+    pub fn ordered_reachability(&self) -> Vec<f32> {
+        self.ordering.iter().map(|&point| self.reachability[point]).collect()
+    }
+
+    fn xi_cluster(&self) -> Vec<(usize, usize)> {
+        let n = self.ordering.len();
+        let mut plot = self.ordered_reachability();
+        plot.push(f32::INFINITY);
+        let predecessors: Vec<Option<usize>> = self.ordering.iter().map(|&point| self.predecessor[point]).collect();
+        let xi_complement = 1.0 - self.xi;
+        let ratio: Vec<f32> = (0..n).map(|i| plot[i] / plot[i + 1]).collect();
+        let steep_up: Vec<bool> = ratio.iter().map(|&r| r <= xi_complement).collect();
+        let steep_down: Vec<bool> = ratio.iter().map(|&r| r >= 1.0 / xi_complement).collect();
+        let up: Vec<bool> = ratio.iter().map(|&r| r < 1.0).collect();
+        let down: Vec<bool> = ratio.iter().map(|&r| r > 1.0).collect();
+
+        let mut steep_down_areas: Vec<SteepDownArea> = Vec::new();
+        let mut clusters = Vec::new();
+        let mut index = 0;
+        let mut mib = 0.0f32;
+        for steep_index in 0..n {
+            if !(steep_up[steep_index] || steep_down[steep_index]) || steep_index < index {
+                continue;
+            }
+            mib = plot[index..=steep_index].iter().fold(mib, |acc, &r| acc.max(r));
+            filter_steep_down_areas(&mut steep_down_areas, mib, xi_complement, &plot);
+            if steep_down[steep_index] {
+                let end = extend_region(&steep_down, &up, steep_index, self.min_points);
+                steep_down_areas.push(SteepDownArea { start: steep_index, end, mib: 0.0 });
+                index = end + 1;
+                mib = plot[index];
+                continue;
+            }
+            let up_start = steep_index;
+            let up_end = extend_region(&steep_up, &down, up_start, self.min_points);
+            index = up_end + 1;
+            mib = plot[index];
+            let mut up_clusters = Vec::new();
+            for area in steep_down_areas.iter() {
+                let mut start = area.start;
+                let mut end = up_end;
+                if plot[end + 1] * xi_complement < area.mib {
+                    continue;
+                }
+                let down_max = plot[area.start];
+                if down_max * xi_complement >= plot[end + 1] {
+                    while plot[start + 1] > plot[end + 1] && start < area.end {
+                        start += 1;
+                    }
+                }
+                else if plot[end + 1] * xi_complement >= down_max {
+                    while plot[end - 1] > down_max && end > up_start {
+                        end -= 1;
+                    }
+                }
+                let Some((start, end)) = correct_predecessor(&plot, &predecessors, &self.ordering, start, end) else {
+                    continue;
+                };
+                if end - start + 1 < self.min_cluster_size || start > area.end || end < up_start {
+                    continue;
+                }
+                up_clusters.push((start, end));
+            }
+            up_clusters.reverse();
+            clusters.extend(up_clusters);
+        }
+        clusters
+    }
+}
+
+struct SteepDownArea {
+    start: usize,
+    end: usize,
+    mib: f32
+}
+
+fn filter_steep_down_areas(areas: &mut Vec<SteepDownArea>, mib: f32, xi_complement: f32, plot: &[f32]) {
+    if mib.is_infinite() {
+        areas.clear();
+        return;
+    }
+    areas.retain(|area| mib <= plot[area.start] * xi_complement);
+    for area in areas.iter_mut() {
+        area.mib = area.mib.max(mib);
+    }
+}
+
+/// Extends a steep area from `start`, allowing at most `min_points` consecutive
+/// points that are neither steep nor going the other way.
+fn extend_region(steep: &[bool], other_way: &[bool], start: usize, min_points: usize) -> usize {
+    let mut non_steep = 0;
+    let mut end = start;
+    for index in start..steep.len() {
+        if steep[index] {
+            non_steep = 0;
+            end = index;
+        }
+        else if !other_way[index] {
+            non_steep += 1;
+            if non_steep > min_points {
+                break;
+            }
+        }
+        else {
+            return end;
+        }
+    }
+    end
+}
+
+/// Shrinks the cluster end until the end point's predecessor lies inside the cluster.
+fn correct_predecessor(plot: &[f32], predecessors: &[Option<usize>], ordering: &[usize], start: usize, mut end: usize) -> Option<(usize, usize)> {
+    while start < end {
+        if plot[start] > plot[end] {
+            return Some((start, end));
+        }
+        if let Some(pred) = predecessors[end] {
+            if ordering[start..end].contains(&pred) {
+                return Some((start, end));
+            }
+        }
+        end -= 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5 x 5 grid of spacing 0.1 around `(x, y)`.
+    fn blob(x: f32, y: f32) -> Array2<f32> {
+        Array2::from_shape_fn((25, 2), |(i, j)| if j == 0 { x + (i % 5) as f32 * 0.1 } else { y + (i / 5) as f32 * 0.1 })
+    }
+
+    fn two_blobs() -> Array2<f32> {
+        ndarray::concatenate(Axis(0), &[blob(0.0, 0.0).view(), blob(10.0, 10.0).view()]).unwrap()
+    }
+
+    fn assert_two_blobs(labels: &[i32]) {
+        assert_ne!(labels[0], NOISE);
+        assert_ne!(labels[0], labels[25]);
+        assert!(labels[..25].iter().all(|&label| label == labels[0]));
+        assert!(labels[25..].iter().all(|&label| label == labels[25]));
+    }
+
+    #[test]
+    fn xi_separates_two_blobs() {
+        let data = two_blobs();
+        let mut model = Optics::new(5);
+        assert_two_blobs(&model.fit_predict(&data));
+    }
+
+    #[test]
+    fn epsilon_cut_separates_two_blobs() {
+        let data = two_blobs();
+        let mut model = Optics::new(5);
+        model.fit(&data);
+        assert_two_blobs(&model.extract_dbscan(0.5).unwrap());
+    }
+
+    #[test]
+    fn ordering_visits_every_point_once() {
+        let data = two_blobs();
+        let mut model = Optics::new(5);
+        model.fit(&data);
+        let mut ordering = model.ordering.clone();
+        ordering.sort();
+        assert_eq!(ordering, (0..50).collect::<Vec<_>>());
+        assert_eq!(model.ordered_reachability().iter().filter(|r| r.is_infinite()).count(), 1);
+    }
+
+    #[test]
+    fn rejects_epsilon_above_max_epsilon() {
+        let data = two_blobs();
+        let mut model = Optics::new(5);
+        model.set_max_epsilon(1.0);
+        model.fit(&data);
+        assert!(model.extract_dbscan(2.0).is_err());
+        assert_two_blobs(&model.extract_dbscan(1.0).unwrap());
+    }
+}
\ No newline at end of file
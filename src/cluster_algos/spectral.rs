@@ -0,0 +1,335 @@
+use ndarray::prelude::*;
+use num::ToPrimitive;
+use rand::{thread_rng, Rng};
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::dbscan::kth_neighbour_distances;
+use crate::cluster_algos::lloyd::Kmeans;
+use crate::cluster_algos::error::ClusterError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Affinity {
+    /// `exp(-gamma * d^2)` between every pair of points.
+    Rbf { gamma: f32 },
+    /// 1 between points where either is among the other's `k` nearest neighbours.
+    NearestNeighbours { k: usize }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssignLabels {
+    /// Run `Kmeans` on the row-normalized embedding.
+    Kmeans,
+    /// Search the discrete partition closest to the embedding (Yu and Shi).
+    Discretize
+}
+
+pub struct SpectralClustering {
+    /// Number of clusters, estimated with the eigengap heuristic if 0 or less.
+    pub centers: i32,
+    pub max_centers: i32,
+    pub affinity: Affinity,
+    pub assign_labels: AssignLabels,
+    pub max_iter: i32,
+    pub affinity_matrix: Array2<f32>,
+    /// Smallest eigenvalues of the normalized laplacian, ascending.
+    pub eigenvalues: Vec<f32>,
+    pub embedding: Array2<f32>,
+    pub partition: Vec<i32>
+}
+
+impl SpectralClustering {
+
+    pub fn new(data: &Array2<f32>, centers: i32) -> SpectralClustering {
+        SpectralClustering {
+            centers,
+            max_centers: 10,
+            affinity: Affinity::Rbf { gamma: 1.0 },
+            assign_labels: AssignLabels::Kmeans,
+            max_iter: 1000,
+            affinity_matrix: Array2::zeros((0, 0)),
+            eigenvalues: Vec::new(),
+            embedding: Array2::zeros((0, 0)),
+            partition: vec![0; data.shape()[0]]
+        }
+    }
+
+    pub fn set_affinity(&mut self, affinity: Affinity) {
+        self.affinity = affinity;
+    }
+
+    pub fn set_assign_labels(&mut self, assign_labels: AssignLabels) {
+        self.assign_labels = assign_labels;
+    }
+
+    pub fn set_max_centers(&mut self, max_centers: i32) {
+        self.max_centers = max_centers;
+    }
+
+    pub fn fit_predict(&mut self, data: &Array2<f32>) -> Result<Vec<i32>, ClusterError> {
+        self.affinity_matrix = match self.affinity {
+            Affinity::Rbf { gamma } => rbf_affinity(data, gamma),
+            Affinity::NearestNeighbours { k } => knn_affinity(data, k)
+        };
+        self.fit_predict_affinity()
+    }
+
+    /// Clusters from a precomputed symmetric, non-negative affinity matrix.
+    pub fn fit_predict_precomputed(&mut self, affinity: &Array2<f32>) -> Result<Vec<i32>, ClusterError> {
+        if affinity.shape()[0] != affinity.shape()[1] {
+            return Err(ClusterError::InvalidInput(format!("affinity matrix must be square, got {} x {}", affinity.shape()[0], affinity.shape()[1])));
+        }
+        self.affinity_matrix = affinity.to_owned();
+        self.fit_predict_affinity()
+    }
+
+    fn fit_predict_affinity(&mut self) -> Result<Vec<i32>, ClusterError> {
+        if self.centers <= 0 && self.max_centers < 1 {
+            return Err(ClusterError::InvalidParameter(format!("max_centers must be at least 1 to estimate the number of clusters, got {}", self.max_centers)));
+        }
+        let n = self.affinity_matrix.shape()[0];
+        if n == 0 {
+            self.partition = Vec::new();
+            return Ok(Vec::new());
+        }
+        let n_eigen = if self.centers > 0 { self.centers } else { self.max_centers + 1 }
+            .to_usize()
+            .unwrap()
+            .clamp(1, n);
+        // the largest eigenvalues of 2I - L are the smallest of L, and 2I - L is positive semi-definite
+        let laplacian = normalized_laplacian(&self.affinity_matrix).mapv(|x| x as f64);
+        let shifted = Array2::<f64>::eye(n) * 2.0 - laplacian;
+        let (values, vectors) = leading_eigenvectors(&shifted, n_eigen, self.max_iter);
+        self.eigenvalues = values.iter().map(|v| (2.0 - v).max(0.0) as f32).collect();
+        let k = if self.centers > 0 {
+            self.centers.to_usize().unwrap().min(n)
+        }
+        else {
+            eigengap_heuristic(&self.eigenvalues)
+        };
+        self.embedding = vectors.slice(s![.., ..k]).mapv(|x| x as f32);
+        self.partition = match self.assign_labels {
+            AssignLabels::Kmeans => {
+                let normalized = normalize_rows(&self.embedding);
+                let mut kmeans = Kmeans::new(&normalized, k.to_i32().unwrap());
+                kmeans.fit_predict(&normalized)?
+            }
+            AssignLabels::Discretize => discretize(&self.embedding, 30, self.max_iter)
+        };
+        Ok(self.partition.clone())
+    }
+}
+
+pub fn rbf_affinity(data: &Array2<f32>, gamma: f32) -> Array2<f32> {
+    let n = data.shape()[0];
+    let mut affinity = Array2::<f32>::zeros((n, n));
+    for i in 0..n {
+        for j in i + 1..n {
+            let value = (-gamma * square(l2_view(data.row(i), data.row(j)))).exp();
+            affinity[[i, j]] = value;
+            affinity[[j, i]] = value;
+        }
+    }
+    affinity
+}
+
+/// Symmetric k-nearest-neighbour connectivity, the point itself not counted.
+pub fn knn_affinity(data: &Array2<f32>, k: usize) -> Array2<f32> {
+    let n = data.shape()[0];
+    let mut affinity = Array2::<f32>::zeros((n, n));
+    let thresholds = kth_neighbour_distances(data, k + 1);
+    for i in 0..n {
+        let mut candidates: Vec<(f32, usize)> = (0..n)
+            .filter(|&j| j != i)
+            .map(|j| (l2_view(data.row(i), data.row(j)), j))
+            .filter(|(dist, _)| *dist <= thresholds[i])
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for &(_, j) in candidates.iter().take(k) {
+            affinity[[i, j]] = 1.0;
+            affinity[[j, i]] = 1.0;
+        }
+    }
+    affinity
+}
+
+/// `I - D^-1/2 W D^-1/2` with `D` the degrees of `affinity`, self loops ignored.
+/// Isolated points keep a 1 on the diagonal.
+pub fn normalized_laplacian(affinity: &Array2<f32>) -> Array2<f32> {
+    let n = affinity.shape()[0];
+    let degrees: Vec<f32> = (0..n)
+        .map(|i| (0..n).filter(|&j| j != i).map(|j| affinity[[i, j]]).sum())
+        .collect();
+    let inv_sqrt: Vec<f32> = degrees.iter().map(|&d| if d > 0.0 { 1.0 / d.sqrt() } else { 0.0 }).collect();
+    let mut laplacian = Array2::<f32>::eye(n);
+    for i in 0..n {
+        for j in 0..n {
+            if i != j {
+                laplacian[[i, j]] = -affinity[[i, j]] * inv_sqrt[i] * inv_sqrt[j];
+            }
+        }
+    }
+    laplacian
+}
+
+/// Number of clusters at the largest gap between consecutive ascending laplacian eigenvalues.
+pub fn eigengap_heuristic(eigenvalues: &[f32]) -> usize {
+    let mut best = 1;
+    let mut max_gap = f32::NEG_INFINITY;
+    for i in 1..eigenvalues.len() {
+        let gap = eigenvalues[i] - eigenvalues[i - 1];
+        if gap > max_gap {
+            max_gap = gap;
+            best = i;
+        }
+    }
+    best
+}
+
+fn normalize_rows(matrix: &Array2<f32>) -> Array2<f32> {
+    let mut normalized = matrix.clone();
+    for mut row in normalized.rows_mut() {
+        let norm = row.dot(&row).sqrt();
+        if norm > 0.0 {
+            row.mapv_inplace(|x| x / norm);
+        }
+    }
+    normalized
+}
+
+/// Finds the partition whose indicator matrix is closest to a rotation of the embedding.
+fn discretize(embedding: &Array2<f32>, max_restarts: i32, max_iter: i32) -> Vec<i32> {
+    let (n, k) = (embedding.shape()[0], embedding.shape()[1]);
+    let mut vectors = embedding.mapv(|x| x as f64);
+    for mut column in vectors.columns_mut() {
+        let norm = column.dot(&column).sqrt();
+        if norm > 0.0 {
+            let sign = if column[0] > 0.0 { -1.0 } else { 1.0 };
+            column.mapv_inplace(|x| sign * x / norm * (n as f64).sqrt());
+        }
+    }
+    for mut row in vectors.rows_mut() {
+        let norm = row.dot(&row).sqrt();
+        if norm > 0.0 {
+            row.mapv_inplace(|x| x / norm);
+        }
+    }
+    let mut rng = thread_rng();
+    let mut labels = vec![0; n];
+    for _ in 0..max_restarts.max(1) {
+        // start from k rows that are as orthogonal to each other as possible
+        let mut rotation = Array2::<f64>::zeros((k, k));
+        rotation.column_mut(0).assign(&vectors.row(rng.gen_range(0..n)));
+        let mut c = Array1::<f64>::zeros(n);
+        for j in 1..k {
+            c += &vectors.dot(&rotation.column(j - 1)).mapv(f64::abs);
+            let next = (0..n).min_by(|&a, &b| c[a].total_cmp(&c[b])).unwrap();
+            rotation.column_mut(j).assign(&vectors.row(next));
+        }
+        let mut last_objective = 0.0;
+        let mut degenerate = false;
+        for _ in 0..max_iter.max(1) {
+            let projected = vectors.dot(&rotation);
+            for (i, row) in projected.rows().into_iter().enumerate() {
+                labels[i] = (0..k).max_by(|&a, &b| row[a].total_cmp(&row[b])).unwrap();
+            }
+            let mut t = Array2::<f64>::zeros((k, k));
+            for (i, &label) in labels.iter().enumerate() {
+                let mut target = t.row_mut(label);
+                target += &vectors.row(i);
+            }
+            // rotation = V U^T from the SVD t = U S V^T, via the eigen decomposition of t^T t
+            let (squared, v) = symmetric_eigen(&t.t().dot(&t));
+            if squared.iter().any(|&s| s <= 1e-12) {
+                degenerate = true;
+                break;
+            }
+            let singular = squared.mapv(f64::sqrt);
+            let u = t.dot(&v) / &singular;
+            let objective = 2.0 * (n as f64 - singular.sum());
+            if (objective - last_objective).abs() < f64::EPSILON {
+                break;
+            }
+            last_objective = objective;
+            rotation = v.dot(&u.t());
+        }
+        if !degenerate {
+            break;
+        }
+    }
+    labels.into_iter().map(|l| l.to_i32().unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20 points on a circle of radius 1 inside 40 points on a circle of radius 5.
+    fn rings() -> Array2<f32> {
+        let ring = |count: usize, radius: f32| {
+            Array2::from_shape_fn((count, 2), move |(i, j)| {
+                let angle = 2.0 * std::f32::consts::PI * i as f32 / count as f32;
+                radius * if j == 0 { angle.cos() } else { angle.sin() }
+            })
+        };
+        ndarray::concatenate(Axis(0), &[ring(20, 1.0).view(), ring(40, 5.0).view()]).unwrap()
+    }
+
+    fn assert_rings_separated(labels: &[i32]) {
+        assert!(labels[..20].iter().all(|&label| label == labels[0]), "{:?}", labels);
+        assert!(labels[20..].iter().all(|&label| label == labels[20]), "{:?}", labels);
+        assert_ne!(labels[0], labels[20]);
+    }
+
+    /// All-ones blocks of 3, 4 and 5 points without links between them.
+    fn blocks() -> Array2<f32> {
+        let block = |i: usize| if i < 3 { 0 } else if i < 7 { 1 } else { 2 };
+        Array2::from_shape_fn((12, 12), |(i, j)| if i != j && block(i) == block(j) { 1.0 } else { 0.0 })
+    }
+
+    #[test]
+    fn separates_rings() {
+        let data = rings();
+        let mut model = SpectralClustering::new(&data, 2);
+        model.set_affinity(Affinity::NearestNeighbours { k: 5 });
+        assert_rings_separated(&model.fit_predict(&data).unwrap());
+        assert_eq!(model.embedding.shape(), &[60, 2]);
+        model.set_assign_labels(AssignLabels::Discretize);
+        assert_rings_separated(&model.fit_predict(&data).unwrap());
+    }
+
+    #[test]
+    fn eigengap_finds_the_blocks() {
+        assert_eq!(eigengap_heuristic(&[0.0, 0.0, 0.0, 1.2, 1.5]), 3);
+        assert_eq!(eigengap_heuristic(&[0.0, 0.9, 1.0]), 1);
+        let mut model = SpectralClustering::new(&Array2::zeros((12, 1)), 0);
+        model.set_max_centers(5);
+        let labels = model.fit_predict_precomputed(&blocks()).unwrap();
+        assert_eq!(model.eigenvalues.len(), 6);
+        assert!(model.eigenvalues[..3].iter().all(|&value| value.abs() < 1e-4), "{:?}", model.eigenvalues);
+        // the complete graph on m points has laplacian eigenvalue m / (m - 1)
+        assert!((model.eigenvalues[3] - 1.25).abs() < 1e-4, "{:?}", model.eigenvalues);
+        for block in [&labels[..3], &labels[3..7], &labels[7..]] {
+            assert!(block.iter().all(|&label| label == block[0]), "{:?}", labels);
+        }
+        assert!(labels[0] != labels[3] && labels[3] != labels[7] && labels[0] != labels[7]);
+    }
+
+    #[test]
+    fn affinities_are_symmetric() {
+        let data = array![[0.0, 0.0], [1.0, 0.0], [3.0, 0.0]];
+        let rbf = rbf_affinity(&data, 0.5);
+        assert_eq!(rbf, rbf.t());
+        assert_eq!(rbf[[0, 0]], 0.0);
+        assert!((rbf[[0, 2]] - (-4.5f32).exp()).abs() < 1e-7);
+        // point 2 has point 1 as its only neighbour, which links them both ways
+        assert_eq!(knn_affinity(&data, 1), array![[0.0, 1.0, 0.0], [1.0, 0.0, 1.0], [0.0, 1.0, 0.0]]);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let mut model = SpectralClustering::new(&Array2::zeros((12, 1)), 0);
+        model.set_max_centers(-2);
+        assert!(matches!(model.fit_predict_precomputed(&blocks()), Err(ClusterError::InvalidParameter(_))));
+        model.set_max_centers(5);
+        assert!(matches!(model.fit_predict_precomputed(&Array2::zeros((2, 3))), Err(ClusterError::InvalidInput(_))));
+    }
+}
\ No newline at end of file
@@ -1,36 +1,64 @@
 use std::vec;
 use crate::cluster_algos::agglomerative::AgglomerativeCluster;
-use crate::cluster_algos::dbscan::DBScan;
+use crate::cluster_algos::dbscan::{DBScan, estimate_parameters};
+use crate::cluster_algos::hdbscan::HDBScan;
+use crate::cluster_algos::optics::Optics;
+use crate::cluster_algos::affinity_propagation::AffinityPropagation;
+use crate::cluster_algos::spectral::{SpectralClustering, Affinity};
+use crate::cluster_algos::birch::{Birch, GlobalClustering};
+use crate::cluster_algos::mean_shift::{MeanShift, estimate_bandwidth};
 use crate::cluster_algos::lloyd::Kmeans;
 use crate::utils::mathfuncs::{create_square, center_scale};
-use cluster_algos::agglomerative::{AggloClusterer, get_partitions};
-use plots::scatter_plot;
+use cluster_algos::agglomerative::AggloClusterer;
+use cli::{Algo, Cli};
+use clap::Parser;
+use plots::{scatter_plot, reachability_plot, k_distance_plot, dendrogram_plot};
 use ndarray::{array, Array2, Axis};
 use std::time::Instant;
 pub mod cluster_algos;
 pub mod utils;
 pub mod plots;
+pub mod cli;
 
 fn main() {
+    if let Err(error) = cli::run(Cli::parse()) {
+        eprintln!("error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+/// Runs every algorithm in `algos` that has a demo on the noisy squares and plots the
+/// results, plus the older `AgglomerativeCluster` if `legacy_agglomerative` is set.
+fn demo(algos: &[Algo], legacy_agglomerative: bool) {
     let cluster_size = 100;
     let noise_intensity = 20;
     let num_clusters = 4;
-    let bounds = vec![vec![vec![1.0, 5.0], vec![1.0, 5.0]], vec![vec![1.0, 3.0], vec![5.0, 6.0]], vec![vec![5.0, 6.0], vec![1.0, 3.0]], vec![vec![5.0, 6.0], vec![5.0, 6.0]]];
 
-    let mut data = get_data(noise_intensity, num_clusters, cluster_size, bounds);
+    let mut data = get_data(noise_intensity, cluster_size);
 
 
     center_scale(&mut data);
 
-    let agglo = true;
-    let kmeans = true;
-    let dbscan = true;
-    let agglo_old = true;
+    let agglo = algos.contains(&Algo::Agglomerative);
+    let kmeans = algos.contains(&Algo::Kmeans);
+    let dbscan = algos.contains(&Algo::Dbscan);
+    let hdbscan = algos.contains(&Algo::Hdbscan);
+    let optics = algos.contains(&Algo::Optics);
+    let mean_shift = algos.contains(&Algo::MeanShift);
+    let affinity_propagation = algos.contains(&Algo::AffinityPropagation);
+    let spectral = algos.contains(&Algo::Spectral);
+    let birch = algos.contains(&Algo::Birch);
+    for algo in algos.iter().filter(|algo| matches!(algo, Algo::Kmedoids | Algo::Kmodes | Algo::Kprototypes)) {
+        eprintln!("no demo for {:?}", algo);
+    }
 
     if dbscan {
         let mut dbscan_model = DBScan::new(&data);
         //model_4.set_epsilon(10e-2);
         //model_4.set_min_points(20);
+        let estimate = estimate_parameters(&data);
+        println!("DBScan suggests epsilon {} with min_points {}", estimate.epsilon, estimate.min_points);
+        let _ = k_distance_plot("DBScan_k_distance", &estimate.k_distances, estimate.knee, estimate.epsilon);
         let now = Instant::now();
         let partitions_dbscan = dbscan_model.fit_predict(&data);
         println!("DBScan fitted after {:?}", now.elapsed());
@@ -40,7 +68,7 @@ fn main() {
     if kmeans {
         let mut kmeans_model = Kmeans::new(&data, num_clusters);
         let now = Instant::now();
-        let partitions_kmeans = kmeans_model.fit_predict(&data);
+        let partitions_kmeans = kmeans_model.fit_predict(&data).expect("the demo settings are valid");
         println!("Kmeans fitted after {:?}", now.elapsed());
         let centroids_kmeans = kmeans_model.centroids;
         let _ = scatter_plot("kmeans_fitted", &data, &partitions_kmeans, &centroids_kmeans, true);
@@ -50,11 +78,16 @@ fn main() {
         let now = Instant::now();
         agglo_model.fit(&data);
         println!("Agglo clustereer fitted after {:?}", now.elapsed());
-        let partitions_agglo = get_partitions(&agglo_model.retrieve_clusters(num_clusters as usize), &data);
+        let partitions_agglo = agglo_model.cut_at_k(num_clusters as usize);
         let centroids = array![[0.0, 0.0]];
         let _ = scatter_plot("AggloScan_fitted", &data, &partitions_agglo, &centroids, false);
+        // cut halfway between the last kept and the first undone merge
+        let merges = &agglo_model.merges;
+        let first_undone = merges.len() + 1 - num_clusters as usize;
+        let cut = (merges[first_undone - 1].height + merges[first_undone].height) / 2.0;
+        let _ = dendrogram_plot("Agglo_dendrogram", merges, Some(30), Some(cut), Some(cut));
     }
-    if agglo_old {
+    if legacy_agglomerative {
         let mut agglo_model_old = AgglomerativeCluster::new(&data, num_clusters as usize);
         let now = Instant::now();
         let partitions_agglo_old = agglo_model_old.fit_predict(&data);
@@ -62,34 +95,68 @@ fn main() {
         let centroids = array![[0.0, 0.0]];
         let _ = scatter_plot("AgglomerativeScan_fitted", &data, &partitions_agglo_old, &centroids, false);
     }
+    if hdbscan {
+        let mut hdbscan_model = HDBScan::new(15);
+        let now = Instant::now();
+        let partitions_hdbscan = hdbscan_model.fit_predict(&data);
+        println!("HDBScan fitted after {:?}", now.elapsed());
+        let centroids = array![[0.0, 0.0]];
+        let _ = scatter_plot("HDBScan_fitted", &data, &partitions_hdbscan, &centroids, false);
+    }
+    if optics {
+        let mut optics_model = Optics::new(10);
+        optics_model.set_xi(0.1);
+        let now = Instant::now();
+        let partitions_optics = optics_model.fit_predict(&data);
+        println!("OPTICS fitted after {:?}", now.elapsed());
+        let centroids = array![[0.0, 0.0]];
+        let _ = scatter_plot("OPTICS_fitted", &data, &partitions_optics, &centroids, false);
+        let _ = reachability_plot("OPTICS_reachability", &optics_model.ordering, &optics_model.reachability, &partitions_optics);
+    }
+    if mean_shift {
+        let mut mean_shift_model = MeanShift::new(&data);
+        mean_shift_model.set_bandwidth(estimate_bandwidth(&data, 0.15));
+        mean_shift_model.set_bin_seeding(true, 5);
+        let now = Instant::now();
+        let partitions_mean_shift = mean_shift_model.fit_predict(&data);
+        println!("Mean shift fitted after {:?}", now.elapsed());
+        let _ = scatter_plot("MeanShift_fitted", &data, &partitions_mean_shift, &mean_shift_model.centroids, true);
+    }
+    if affinity_propagation {
+        let mut affinity_model = AffinityPropagation::new();
+        let now = Instant::now();
+        let partitions_affinity = affinity_model.fit_predict(&data);
+        println!("Affinity propagation fitted after {:?} with {} exemplars", now.elapsed(), affinity_model.exemplars.len());
+        let _ = scatter_plot("AffinityPropagation_fitted", &data, &partitions_affinity, &affinity_model.centroids, true);
+    }
+    if spectral {
+        let mut spectral_model = SpectralClustering::new(&data, num_clusters);
+        spectral_model.set_affinity(Affinity::NearestNeighbours { k: 10 });
+        let now = Instant::now();
+        let partitions_spectral = spectral_model.fit_predict(&data).expect("the demo settings are valid");
+        println!("Spectral clustering fitted after {:?}", now.elapsed());
+        let centroids = array![[0.0, 0.0]];
+        let _ = scatter_plot("Spectral_fitted", &data, &partitions_spectral, &centroids, false);
+    }
+    if birch {
+        let mut birch_model = Birch::new(GlobalClustering::Kmeans(num_clusters as usize));
+        birch_model.set_threshold(0.2);
+        let now = Instant::now();
+        let partitions_birch = birch_model.fit_predict(&data);
+        println!("BIRCH fitted after {:?} with {} subclusters", now.elapsed(), birch_model.subcluster_sizes.len());
+        let _ = scatter_plot("Birch_fitted", &data, &partitions_birch, &birch_model.centroids, true);
+    }
     
     println!("all plots generated");
 }
 
 
-fn get_data(noise_intensity: usize, num_clusters: i32, cluster_size: usize, _bounds: Vec<Vec<Vec<f32>>>) -> Array2<f32> {
-    //let mut squares: Vec<Array2<ViewRepr<f32>>> = vec![];
-
-    for _i in 0..num_clusters {
-        //let x_bound = &bounds[i.to_usize().unwrap()][0];
-        //let y_bound = &bounds[i.to_usize().unwrap()][1];
-        //let square = create_square(y_bound, x_bound, cluster_size, 2);
-        //squares.push(&square.view());
-    }
-
-    let square_1: Array2<f32> = create_square(&vec![1.0, 3.0], &vec![2.0, 4.0], cluster_size, 2); // Cluster 1
-    let square_2: Array2<f32> = create_square(&vec![5.0, 7.0], &vec![1.0, 3.0], cluster_size, 2); // Cluster 2
-    let square_3: Array2<f32> = create_square(&vec![5.0, 7.0], &vec![6.0, 7.0], cluster_size, 2); // Cluster 3
-    let square_4: Array2<f32> = create_square(&vec![10.0, 12.0], &vec![6.0, 7.0], cluster_size, 2);
-    let square_5: Array2<f32> = create_square(&vec![1.0, 8.0], &vec![1.0, 7.0], cluster_size / 10 + noise_intensity, 2); // A bunch of noise across them all
-    	
-    /*
-    let data: Array2<f32> = ndarray::concatenate(
-        Axis(0),
-        &squares[0..4],
-    )
-    .expect("An error occurred while stacking the dataset");
-    */
+fn get_data(noise_intensity: usize, cluster_size: usize) -> Array2<f32> {
+    let square_1: Array2<f32> = create_square(&[1.0, 3.0], &[2.0, 4.0], cluster_size, 2); // Cluster 1
+    let square_2: Array2<f32> = create_square(&[5.0, 7.0], &[1.0, 3.0], cluster_size, 2); // Cluster 2
+    let square_3: Array2<f32> = create_square(&[5.0, 7.0], &[6.0, 7.0], cluster_size, 2); // Cluster 3
+    let square_4: Array2<f32> = create_square(&[10.0, 12.0], &[6.0, 7.0], cluster_size, 2);
+    let square_5: Array2<f32> = create_square(&[1.0, 8.0], &[1.0, 7.0], cluster_size / 10 + noise_intensity, 2); // A bunch of noise across them all
 
     let data: Array2<f32> = ndarray::concatenate(
         Axis(0),
@@ -103,5 +170,5 @@ fn get_data(noise_intensity: usize, num_clusters: i32, cluster_size: usize, _bou
     )
     .expect("An error occurred while stacking the dataset");
 
-    return data;
+    data
 }
\ No newline at end of file
@@ -1,8 +1,9 @@
 use std::vec;
 use num::ToPrimitive;
-use plotters::{prelude::*, style::full_palette::CYAN_A700};
+use plotters::{prelude::*, style::full_palette::{CYAN_A700, GREY}};
 use ndarray::{Array, Array1, Array2};
 use crate::utils::utility::*;
+use crate::cluster_algos::agglomerative::Merge;
 
 pub fn line_plot(x: &Array2<f64>, y: &Array1<f64>, pred: &Array1<f64>, name: String) -> Result<(), Box<dyn std::error::Error>> {
 
@@ -48,7 +49,7 @@ pub fn scatter_plot(name: &str, data: &Array2<f32>, partitions: &Vec<i32>, centr
     let max = max_int(partitions.to_owned());
 
     // for max 7 clusters
-    let styles = vec![&BLACK, &RED, &MAGENTA, &GREEN, &BLUE, &CYAN, &CYAN_A700];
+    let styles = [&BLACK, &RED, &MAGENTA, &GREEN, &BLUE, &CYAN, &CYAN_A700];
 
     path.push_str(name);
     path.push_str(".png");
@@ -65,6 +66,17 @@ pub fn scatter_plot(name: &str, data: &Array2<f32>, partitions: &Vec<i32>, centr
 
     chart.configure_mesh().x_labels(10).y_labels(10).draw()?;
 
+    // negative labels mark noise, e.g. from DBScan
+    let noise_data: Vec<(f64, f64)> = (0..partitions.len())
+        .filter(|&j| partitions[j] < 0)
+        .map(|j| (data[[j, 0]].to_f64().unwrap(), data[[j, 1]].to_f64().unwrap()))
+        .collect();
+    chart.draw_series(
+        noise_data
+            .iter()
+            .map(|(x, y)| Cross::new((*x, *y), 2, GREY.stroke_width(1))),
+    )?;
+
     for i in 0..max + 1{
         let mut series_data: Vec<(f64, f64)> = vec![(0.0, 0.0)];
         for j in 0..partitions.len() {
@@ -77,11 +89,11 @@ pub fn scatter_plot(name: &str, data: &Array2<f32>, partitions: &Vec<i32>, centr
         if series_data.is_empty(){
             continue;
         }
-        //let series: PointSeries<(f64, f64), vec::IntoIter<(f64, f64)>, _, i32> = PointSeries::new(series_data.into_iter(), 2, styles[i.to_usize().unwrap()].filled());
+        //let series: PointSeries<(f64, f64), vec::IntoIter<(f64, f64)>, _, i32> = PointSeries::new(series_data.into_iter(), 2, styles[i.to_usize().unwrap() % styles.len()].filled());
         chart.draw_series(
             series_data
                 .iter()
-                .map(|(x, y)| Circle::new((*x, *y), 2, styles[i.to_usize().unwrap()].filled())),
+                .map(|(x, y)| Circle::new((*x, *y), 2, styles[i.to_usize().unwrap() % styles.len()].filled())),
         )?;
     }
     if plot_centroids {
@@ -90,7 +102,7 @@ pub fn scatter_plot(name: &str, data: &Array2<f32>, partitions: &Vec<i32>, centr
             chart.draw_series(
                 series_data
                     .iter()
-                    .map(|(x, y)| Circle::new((*x, *y), 2, (&YELLOW).filled())),
+                    .map(|(x, y)| Circle::new((*x, *y), 2, YELLOW.filled())),
             )?;
         }
     }
@@ -110,5 +122,207 @@ fn create_vec(data: &Array1<f64>, y: &Array1<f64>) -> Vec<(f64, f64)> {
         predictions[i].1 = y[i];
     }
 
-    return predictions;
-}
\ No newline at end of file
+    predictions
+}
+
+/// Bar plot of the reachability distances in cluster order, coloured by cluster.
+/// Infinite reachabilities are drawn at the height of the largest finite one.
+pub fn reachability_plot(name: &str, ordering: &[usize], reachability: &[f32], partitions: &[i32]) -> Result<(), Box<dyn std::error::Error>> {
+
+    let mut path = String::from("./images/");
+
+    let styles = [&BLACK, &RED, &MAGENTA, &GREEN, &BLUE, &CYAN, &CYAN_A700];
+
+    path.push_str(name);
+    path.push_str(".png");
+
+    let finite_max = reachability
+        .iter()
+        .filter(|r| r.is_finite())
+        .fold(0.0f32, |acc, &r| acc.max(r))
+        .to_f64()
+        .unwrap();
+    let y_max = if finite_max > 0.0 { finite_max * 1.05 } else { 1.0 };
+
+    let root = BitMapBackend::new(&path, (640, 480)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let root = root.margin(10, 10, 10, 10);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("reachability", ("sans-serif", 40).into_font())
+        .x_label_area_size(20)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0.0..ordering.len().max(1).to_f64().unwrap(), 0.0..y_max)?;
+
+    chart.configure_mesh().x_labels(10).y_labels(10).draw()?;
+
+    chart.draw_series(ordering.iter().enumerate().map(|(i, &point)| {
+        let height = if reachability[point].is_finite() { reachability[point].to_f64().unwrap() } else { finite_max };
+        let label = partitions[point];
+        let style = if label < 0 { GREY.filled() } else { styles[label.to_usize().unwrap() % styles.len()].filled() };
+        let x = i.to_f64().unwrap();
+        Rectangle::new([(x, 0.0), (x + 1.0, height)], style)
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+
+/// Sorted k-distance graph with the knee marked and `epsilon` as a horizontal line.
+pub fn k_distance_plot(name: &str, k_distances: &[f32], knee: usize, epsilon: f32) -> Result<(), Box<dyn std::error::Error>> {
+
+    let mut path = String::from("./images/");
+
+    path.push_str(name);
+    path.push_str(".png");
+
+    let n = k_distances.len().max(1).to_f64().unwrap();
+    let y_max = k_distances.iter().fold(epsilon, |acc, &d| acc.max(d)).to_f64().unwrap() * 1.05;
+    let y_max = if y_max > 0.0 { y_max } else { 1.0 };
+
+    let root = BitMapBackend::new(&path, (640, 480)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let root = root.margin(10, 10, 10, 10);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("k-distance", ("sans-serif", 40).into_font())
+        .x_label_area_size(20)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0.0..n, 0.0..y_max)?;
+
+    chart.configure_mesh().x_labels(10).y_labels(10).draw()?;
+
+    chart.draw_series(LineSeries::new(
+        k_distances.iter().enumerate().map(|(i, d)| (i.to_f64().unwrap(), d.to_f64().unwrap())),
+        &BLACK,
+    ))?;
+    let eps = epsilon.to_f64().unwrap();
+    chart.draw_series(LineSeries::new(vec![(0.0, eps), (n, eps)], &RED))?;
+    if let Some(d) = k_distances.get(knee) {
+        chart.draw_series(vec![Circle::new((knee.to_f64().unwrap(), d.to_f64().unwrap()), 4, RED.filled())])?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+
+/// Dendrogram of the `merges` of an `AggloClusterer` with merge heights on the y-axis.
+/// With `last_p` only the last `p - 1` merges are drawn and their subtrees collapse
+/// into leaves labelled with their size. Links below `color_threshold` are coloured by
+/// the cluster they belong to at that height, `cut` is drawn as a horizontal line.
+pub fn dendrogram_plot(name: &str, merges: &[Merge], last_p: Option<usize>, color_threshold: Option<f32>, cut: Option<f32>) -> Result<(), Box<dyn std::error::Error>> {
+
+    let mut path = String::from("./images/");
+
+    let styles = [&RED, &MAGENTA, &GREEN, &BLUE, &CYAN, &CYAN_A700];
+
+    path.push_str(name);
+    path.push_str(".png");
+
+    let n = merges.len() + 1;
+    let root_node = 2 * n - 2;
+    // merges below `first_shown` are collapsed into leaves
+    let first_shown = n - last_p.unwrap_or(n).clamp(1, n);
+    let is_leaf = |node: usize| node < n + first_shown;
+    let height = |node: usize| if node < n { 0.0 } else { merges[node - n].height.to_f64().unwrap() };
+
+    // left to right leaf order, taken iteratively as chained trees get deep
+    let mut leaves = Vec::new();
+    let mut stack = vec![root_node];
+    while let Some(node) = stack.pop() {
+        if is_leaf(node) {
+            leaves.push(node);
+        }
+        else {
+            stack.push(merges[node - n].right);
+            stack.push(merges[node - n].left);
+        }
+    }
+    let mut x = vec![0.0; 2 * n - 1];
+    for (i, &leaf) in leaves.iter().enumerate() {
+        x[leaf] = i.to_f64().unwrap();
+    }
+    for (i, merge) in merges.iter().enumerate().skip(first_shown) {
+        x[n + i] = (x[merge.left] + x[merge.right]) / 2.0;
+    }
+
+    // colour of every link, walking down from the root so children inherit it
+    let mut colors: Vec<Option<usize>> = vec![None; 2 * n - 1];
+    let mut next_color = 0;
+    if let Some(threshold) = color_threshold {
+        for (i, merge) in merges.iter().enumerate().skip(first_shown).rev() {
+            if colors[n + i].is_none() && merge.height < threshold {
+                colors[n + i] = Some(next_color);
+                next_color += 1;
+            }
+            if colors[n + i].is_some() {
+                colors[merge.left] = colors[n + i];
+                colors[merge.right] = colors[n + i];
+            }
+        }
+    }
+
+    let leaf_labels: Vec<String> = leaves
+        .iter()
+        .map(|&leaf| if leaf < n { leaf.to_string() } else { format!("({})", merges[leaf - n].size) })
+        .collect();
+    let y_max = merges
+        .iter()
+        .map(|merge| merge.height)
+        .chain(cut)
+        .fold(0.0f32, f32::max)
+        .to_f64()
+        .unwrap();
+    let y_max = if y_max > 0.0 { y_max * 1.05 } else { 1.0 };
+    let x_max = leaves.len().to_f64().unwrap() - 0.5;
+
+    let root = BitMapBackend::new(&path, (640, 480)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let root = root.margin(10, 10, 10, 10);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("dendrogram", ("sans-serif", 40).into_font())
+        .x_label_area_size(20)
+        .y_label_area_size(40)
+        .build_cartesian_2d(-0.5..x_max, 0.0..y_max)?;
+
+    // leaf names only fit below the axis for small trees
+    let x_labels = if leaves.len() <= 40 { leaves.len() } else { 0 };
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(x_labels)
+        .x_label_formatter(&|x| {
+            let i = x.round();
+            if (x - i).abs() < 1e-6 && i >= 0.0 { leaf_labels.get(i as usize).cloned().unwrap_or_default() } else { String::new() }
+        })
+        .y_labels(10)
+        .draw()?;
+
+    for (i, merge) in merges.iter().enumerate().skip(first_shown) {
+        let top = height(n + i);
+        let style = match colors[n + i] {
+            Some(color) => styles[color % styles.len()].stroke_width(1),
+            None => BLACK.stroke_width(1)
+        };
+        let (left, right) = (merge.left, merge.right);
+        chart.draw_series(LineSeries::new(
+            vec![
+                (x[left], if is_leaf(left) { 0.0 } else { height(left) }),
+                (x[left], top),
+                (x[right], top),
+                (x[right], if is_leaf(right) { 0.0 } else { height(right) })
+            ],
+            style,
+        ))?;
+    }
+    if let Some(cut) = cut {
+        let cut = cut.to_f64().unwrap();
+        chart.draw_series(LineSeries::new(vec![(-0.5, cut), (x_max, cut)], GREY.stroke_width(2)))?;
+    }
+
+    root.present()?;
+    Ok(())
+}
@@ -1,2 +1,7 @@
 pub mod mathfuncs;
-pub mod utility;
\ No newline at end of file
+pub mod utility;
+pub mod distances;
+pub mod sparse;
+pub mod stream;
+pub mod io;
+pub mod npy;
\ No newline at end of file
@@ -0,0 +1,156 @@
+use ndarray::prelude::*;
+use crate::utils::mathfuncs::*;
+use crate::cluster_algos::error::ClusterError;
+
+/// Upper triangle of a symmetric distance matrix without the diagonal, stored row by row
+/// in `n * (n - 1) / 2` values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CondensedMatrix {
+    n: usize,
+    pub values: Vec<f32>
+}
+
+impl CondensedMatrix {
+    /// Euclidean distances between all rows of `data`.
+    pub fn euclidean(data: &Array2<f32>) -> Self {
+        let n = data.shape()[0];
+        let mut values = Vec::with_capacity(n * n.saturating_sub(1) / 2);
+        for i in 0..n {
+            let row = data.row(i);
+            for j in i + 1..n {
+                values.push(l2_view(row, data.row(j)));
+            }
+        }
+        Self { n, values }
+    }
+
+    /// Wraps precomputed condensed distances, e.g. from SciPy's `pdist`. No values
+    /// are read as a single point.
+    pub fn from_condensed(values: Vec<f32>) -> Result<Self, ClusterError> {
+        // n (n - 1) / 2 = len  =>  n = (1 + sqrt(1 + 8 len)) / 2
+        let n = ((1.0 + (1.0 + 8.0 * values.len() as f64).sqrt()) / 2.0).round() as usize;
+        if n * n.saturating_sub(1) / 2 != values.len() {
+            return Err(ClusterError::InvalidInput(format!("{} values do not form a condensed distance matrix", values.len())));
+        }
+        if let Some(position) = values.iter().position(|&d| d.is_nan() || d < 0.0) {
+            return Err(ClusterError::InvalidInput(format!("distances must be non-negative numbers, found {} at {}", values[position], position)));
+        }
+        Ok(Self { n, values })
+    }
+
+    /// Condenses a square distance matrix, which must be symmetric with a zero diagonal.
+    pub fn from_square(square: &Array2<f32>) -> Result<Self, ClusterError> {
+        let n = square.shape()[0];
+        if n != square.shape()[1] {
+            return Err(ClusterError::InvalidInput(format!("distance matrix must be square, got {} x {}", n, square.shape()[1])));
+        }
+        let mut values = Vec::with_capacity(n * n.saturating_sub(1) / 2);
+        for i in 0..n {
+            if square[[i, i]] != 0.0 {
+                return Err(ClusterError::InvalidInput(format!("distance matrix must have a zero diagonal, found {} at {}", square[[i, i]], i)));
+            }
+            for j in i + 1..n {
+                let (upper, lower) = (square[[i, j]], square[[j, i]]);
+                if upper.is_nan() || upper < 0.0 {
+                    return Err(ClusterError::InvalidInput(format!("distances must be non-negative numbers, found {} at ({}, {})", upper, i, j)));
+                }
+                if lower.is_nan() || (upper - lower).abs() > 1e-5 * upper.max(1.0) {
+                    return Err(ClusterError::InvalidInput(format!("distance matrix is not symmetric at ({}, {}): {} and {}", i, j, upper, lower)));
+                }
+                values.push(upper);
+            }
+        }
+        Ok(Self { n, values })
+    }
+
+    pub fn to_square(&self) -> Array2<f32> {
+        let mut square = Array2::<f32>::zeros((self.n, self.n));
+        for i in 0..self.n {
+            for j in i + 1..self.n {
+                square[[i, j]] = self.get(i, j);
+                square[[j, i]] = self.get(i, j);
+            }
+        }
+        square
+    }
+
+    pub fn n_points(&self) -> usize {
+        self.n
+    }
+
+    pub(crate) fn index(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i < j { (i, j) } else { (j, i) };
+        self.row_offset(i) + j - 1
+    }
+
+    /// Position of `(i, j)` minus `j - 1` for any `j > i`.
+    fn row_offset(&self, i: usize) -> usize {
+        (2 * self.n - i - 3) * i / 2
+    }
+
+    /// Calls `f(j, distance)` for every `j` of the ascending `slots` except `i`,
+    /// reading the contiguous part of row `i` in one pass.
+    pub(crate) fn for_each_in_row(&self, i: usize, slots: &[usize], mut f: impl FnMut(usize, f32)) {
+        let split = slots.partition_point(|&j| j < i);
+        for &j in slots[..split].iter() {
+            f(j, self.values[self.row_offset(j) + i - 1]);
+        }
+        let offset = self.row_offset(i);
+        for &j in slots[split..].iter() {
+            if j != i {
+                f(j, self.values[offset + j - 1]);
+            }
+        }
+    }
+
+    /// Distance between points `i` and `j`, 0 if they are the same.
+    pub fn get(&self, i: usize, j: usize) -> f32 {
+        if i == j { 0.0 } else { self.values[self.index(i, j)] }
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, value: f32) {
+        let index = self.index(i, j);
+        self.values[index] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(result: Result<CondensedMatrix, ClusterError>) -> String {
+        match result {
+            Err(ClusterError::InvalidInput(message)) => message,
+            other => panic!("expected invalid input, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn square_and_condensed_agree() {
+        let square = array![[0.0, 1.0, 2.0], [1.0, 0.0, 3.0], [2.0, 3.0, 0.0]];
+        let condensed = CondensedMatrix::from_condensed(vec![1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(CondensedMatrix::from_square(&square).unwrap(), condensed);
+        assert_eq!(condensed.to_square(), square);
+        assert_eq!(condensed.get(2, 1), 3.0);
+    }
+
+    #[test]
+    fn rejects_wrong_condensed_length() {
+        assert!(invalid(CondensedMatrix::from_condensed(vec![1.0, 2.0])).contains("2 values"));
+        assert_eq!(CondensedMatrix::from_condensed(Vec::new()).unwrap().n_points(), 1);
+    }
+
+    #[test]
+    fn rejects_negative_and_nan_distances() {
+        invalid(CondensedMatrix::from_condensed(vec![1.0, -2.0, 3.0]));
+        invalid(CondensedMatrix::from_condensed(vec![1.0, f32::NAN, 3.0]));
+        invalid(CondensedMatrix::from_square(&array![[0.0, -1.0], [-1.0, 0.0]]));
+    }
+
+    #[test]
+    fn rejects_malformed_square_matrices() {
+        assert!(invalid(CondensedMatrix::from_square(&array![[0.0, 1.0, 2.0], [1.0, 0.0, 3.0]])).contains("square"));
+        assert!(invalid(CondensedMatrix::from_square(&array![[0.0, 1.0], [1.0, 0.5]])).contains("diagonal"));
+        assert!(invalid(CondensedMatrix::from_square(&array![[0.0, 1.0], [2.0, 0.0]])).contains("symmetric"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,439 @@
+use ndarray::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::cluster_algos::dbscan::NOISE;
+
+/// A column of a delimited file, by position counted from 0 or by header name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String)
+}
+
+impl Column {
+    /// Reads a number as an index and anything else as a name.
+    pub fn parse(text: &str) -> Column {
+        match text.trim().parse::<usize>() {
+            Ok(index) => Column::Index(index),
+            Err(_) => Column::Name(text.trim().to_string())
+        }
+    }
+}
+
+/// What to do with rows holding an empty field or `NA`, `NaN`, `null`, `none` or `?`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingValues {
+    /// Fail with the position of the first missing value.
+    Error,
+    /// Leave the row out of the dataset, see `Dataset::rows`.
+    DropRows,
+    /// Replace it by the mean of the present values of its column.
+    Mean,
+    Fill(f32)
+}
+
+/// Reads delimited text files into an `Array2`. Delimiter and header are detected
+/// from the first lines unless set.
+pub struct CsvReader {
+    pub delimiter: Option<char>,
+    pub has_header: Option<bool>,
+    /// Columns to read in this order, every column but the label column if empty.
+    pub columns: Vec<Column>,
+    /// Column holding ground truth labels, kept out of the data.
+    pub label_column: Option<Column>,
+    pub missing: MissingValues
+}
+
+/// Points read by `CsvReader` together with what is needed to write them back.
+pub struct Dataset {
+    pub data: Array2<f32>,
+    /// Names of the columns of `data`, `x0, x1, ..` without header.
+    pub column_names: Vec<String>,
+    /// Label column as integers, or as codes into `label_names` if it is not numeric.
+    /// Missing labels are `NOISE`.
+    pub labels: Option<Vec<i32>>,
+    pub label_names: Vec<String>,
+    /// Position among the data lines of the file of every row of `data`, which skips
+    /// the rows dropped for missing values.
+    pub rows: Vec<usize>,
+    pub source: PathBuf,
+    pub delimiter: char,
+    pub has_header: bool
+}
+
+impl Default for CsvReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsvReader {
+
+    pub fn new() -> CsvReader {
+        CsvReader {
+            delimiter: None,
+            has_header: None,
+            columns: Vec::new(),
+            label_column: None,
+            missing: MissingValues::Error
+        }
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = Some(delimiter);
+    }
+
+    pub fn set_header(&mut self, has_header: bool) {
+        self.has_header = Some(has_header);
+    }
+
+    pub fn set_columns(&mut self, columns: Vec<Column>) {
+        self.columns = columns;
+    }
+
+    pub fn set_label_column(&mut self, label_column: Column) {
+        self.label_column = Some(label_column);
+    }
+
+    pub fn set_missing(&mut self, missing: MissingValues) {
+        self.missing = missing;
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Dataset> {
+        let path = path.as_ref();
+        let lines = read_lines(path)?;
+        if lines.is_empty() {
+            return Err(invalid_data(format!("{}: empty file", path.display())));
+        }
+        let delimiter = self.delimiter.unwrap_or_else(|| sniff_delimiter(&lines));
+        let first = split_fields(&lines[0].1, delimiter);
+        let has_header = self.has_header.unwrap_or_else(|| {
+            detect_header(&first, lines.get(1).map(|(_, line)| split_fields(line, delimiter)).as_deref())
+        });
+        let width = first.len();
+        let names: Vec<String> = if has_header {
+            first.iter().map(|name| name.trim().to_string()).collect()
+        }
+        else {
+            (0..width).map(|j| format!("x{}", j)).collect()
+        };
+        let resolve = |column: &Column| -> io::Result<usize> {
+            match column {
+                Column::Index(index) if *index < width => Ok(*index),
+                Column::Index(index) => Err(invalid_data(format!("{}: column {} out of range for {} columns", path.display(), index, width))),
+                Column::Name(name) => names.iter().position(|n| n == name)
+                    .ok_or_else(|| invalid_data(format!("{}: no column named {:?}", path.display(), name)))
+            }
+        };
+        let label_index = self.label_column.as_ref().map(&resolve).transpose()?;
+        let selected: Vec<usize> = if self.columns.is_empty() {
+            (0..width).filter(|&j| Some(j) != label_index).collect()
+        }
+        else {
+            self.columns.iter().map(&resolve).collect::<io::Result<_>>()?
+        };
+
+        let mut values: Vec<f32> = Vec::new();
+        let mut rows = Vec::new();
+        let mut raw_labels: Vec<String> = Vec::new();
+        for (row, (line_number, line)) in lines.iter().skip(usize::from(has_header)).enumerate() {
+            let fields = split_fields(line, delimiter);
+            if fields.len() != width {
+                return Err(invalid_data(format!("{}:{}: expected {} fields, found {}", path.display(), line_number, width, fields.len())));
+            }
+            let mut parsed = Vec::with_capacity(selected.len());
+            for &j in selected.iter() {
+                let field = fields[j].trim();
+                let value = if is_missing(field) {
+                    f32::NAN
+                }
+                else {
+                    field.parse::<f32>().map_err(|_| {
+                        invalid_data(format!("{}:{}: cannot parse {:?} in column {} as a number", path.display(), line_number, field, names[j]))
+                    })?
+                };
+                if value.is_nan() {
+                    match self.missing {
+                        MissingValues::Error => {
+                            return Err(invalid_data(format!("{}:{}: missing value in column {}", path.display(), line_number, names[j])));
+                        }
+                        MissingValues::DropRows => break,
+                        MissingValues::Mean => {}
+                        MissingValues::Fill(fill) => {
+                            parsed.push(fill);
+                            continue;
+                        }
+                    }
+                }
+                parsed.push(value);
+            }
+            if parsed.len() < selected.len() {
+                continue;
+            }
+            values.extend(parsed);
+            rows.push(row);
+            if let Some(j) = label_index {
+                raw_labels.push(fields[j].trim().to_string());
+            }
+        }
+
+        let mut data = Array2::from_shape_vec((rows.len(), selected.len()), values).unwrap();
+        if self.missing == MissingValues::Mean {
+            for mut column in data.columns_mut() {
+                let present: Vec<f32> = column.iter().copied().filter(|value| !value.is_nan()).collect();
+                let mean = present.iter().sum::<f32>() / present.len().max(1) as f32;
+                column.mapv_inplace(|value| if value.is_nan() { mean } else { value });
+            }
+        }
+        let (labels, label_names) = match label_index {
+            Some(_) => {
+                let (labels, label_names) = encode_labels(&raw_labels);
+                (Some(labels), label_names)
+            }
+            None => (None, Vec::new())
+        };
+        Ok(Dataset {
+            data,
+            column_names: selected.iter().map(|&j| names[j].clone()).collect(),
+            labels,
+            label_names,
+            rows,
+            source: path.to_path_buf(),
+            delimiter,
+            has_header
+        })
+    }
+}
+
+impl Dataset {
+
+    /// Writes the source file back out with a `cluster` column holding `labels` and one
+    /// more column per entry of `extra`, e.g. `("distance", &distances)`. Values are given
+    /// per row of `data`; rows dropped for missing values get empty fields.
+    pub fn write_with_labels(&self, path: impl AsRef<Path>, labels: &[i32], extra: &[(&str, &[f32])]) -> io::Result<()> {
+        if labels.len() != self.rows.len() {
+            return Err(invalid_input(format!("need one label per row, got {} for {} rows", labels.len(), self.rows.len())));
+        }
+        for (name, values) in extra {
+            if values.len() != self.rows.len() {
+                return Err(invalid_input(format!("need one {} per row, got {} for {} rows", name, values.len(), self.rows.len())));
+            }
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        let separator = self.delimiter.to_string();
+        let mut kept = self.rows.iter().enumerate().peekable();
+        for (row, (_, line)) in read_lines(&self.source)?.iter().enumerate() {
+            let mut fields = vec![line.trim_end().to_string()];
+            if self.has_header && row == 0 {
+                fields.push("cluster".to_string());
+                fields.extend(extra.iter().map(|(name, _)| name.to_string()));
+            }
+            else {
+                let data_row = row - usize::from(self.has_header);
+                match kept.next_if(|(_, &r)| r == data_row) {
+                    Some((i, _)) => {
+                        fields.push(labels[i].to_string());
+                        fields.extend(extra.iter().map(|(_, values)| values[i].to_string()));
+                    }
+                    None => fields.extend(std::iter::repeat_n(String::new(), extra.len() + 1))
+                }
+            }
+            writeln!(writer, "{}", fields.join(&separator))?;
+        }
+        writer.flush()
+    }
+}
+
+/// Non-empty lines of `path` with their line numbers, counted from 1.
+fn read_lines(path: &Path) -> io::Result<Vec<(usize, String)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push((i + 1, line));
+        }
+    }
+    Ok(lines)
+}
+
+/// Splits a line at `delimiter` outside double quotes, which are removed, `""` standing
+/// for a literal quote. A space delimiter splits at runs of whitespace.
+pub fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    if delimiter == ' ' {
+        return line.split_whitespace().map(|field| field.to_string()).collect();
+    }
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c)
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The candidate splitting the first lines into the most, equally many, fields.
+fn sniff_delimiter(lines: &[(usize, String)]) -> char {
+    let sample: Vec<&str> = lines.iter().take(5).map(|(_, line)| line.as_str()).collect();
+    let mut best = (' ', 1);
+    for delimiter in [',', '\t', ';', '|'] {
+        let counts: Vec<usize> = sample.iter().map(|line| split_fields(line, delimiter).len()).collect();
+        if counts[0] > best.1 && counts.iter().all(|&count| count == counts[0]) {
+            best = (delimiter, counts[0]);
+        }
+    }
+    best.0
+}
+
+/// A header has a text field where the next line has a number, or text everywhere.
+fn detect_header(first: &[String], second: Option<&[String]>) -> bool {
+    let is_number = |field: &str| field.trim().parse::<f32>().is_ok() || is_missing(field.trim());
+    match second {
+        Some(second) => first.iter().zip(second.iter()).any(|(a, b)| !is_number(a) && is_number(b)),
+        None => first.iter().all(|field| !is_number(field))
+    }
+}
+
+fn is_missing(field: &str) -> bool {
+    field.is_empty() || ["na", "nan", "null", "none", "?"].contains(&field.to_ascii_lowercase().as_str())
+}
+
+/// Integer labels as they are, anything else as codes in order of first appearance.
+fn encode_labels(raw: &[String]) -> (Vec<i32>, Vec<String>) {
+    if raw.iter().all(|label| is_missing(label) || label.parse::<i32>().is_ok()) {
+        let labels = raw.iter().map(|label| label.parse::<i32>().unwrap_or(NOISE)).collect();
+        return (labels, Vec::new());
+    }
+    let mut codes: HashMap<&str, i32> = HashMap::new();
+    let mut names = Vec::new();
+    let labels = raw.iter()
+        .map(|label| {
+            if is_missing(label) {
+                return NOISE;
+            }
+            *codes.entry(label.as_str()).or_insert_with(|| {
+                names.push(label.clone());
+                (names.len() - 1) as i32
+            })
+        })
+        .collect();
+    (labels, names)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a file in the temporary directory, unique per test and process.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("io_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn read(reader: &CsvReader, name: &str, contents: &str) -> io::Result<Dataset> {
+        let path = temp_file(name, contents);
+        let dataset = reader.read(&path);
+        std::fs::remove_file(&path).unwrap();
+        dataset
+    }
+
+    #[test]
+    fn detects_header_and_label_names() {
+        let mut reader = CsvReader::new();
+        reader.set_label_column(Column::Name("label".to_string()));
+        let dataset = read(&reader, "header.csv", "a,b,label\n1,2,x\n3,4,y\n5,6,x\n").unwrap();
+        assert!(dataset.has_header);
+        assert_eq!(dataset.delimiter, ',');
+        assert_eq!(dataset.column_names, vec!["a", "b"]);
+        assert_eq!(dataset.data, array![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]);
+        assert_eq!(dataset.labels, Some(vec![0, 1, 0]));
+        assert_eq!(dataset.label_names, vec!["x", "y"]);
+    }
+
+    #[test]
+    fn detects_missing_header() {
+        let dataset = read(&CsvReader::new(), "no_header.csv", "1,2\n3,4\n").unwrap();
+        assert!(!dataset.has_header);
+        assert_eq!(dataset.column_names, vec!["x0", "x1"]);
+        assert_eq!(dataset.data.shape(), &[2, 2]);
+    }
+
+    #[test]
+    fn sniffs_delimiters() {
+        for (delimiter, contents) in [(';', "1;2\n3;4\n"), ('\t', "1\t2\n3\t4\n"), ('|', "1|2\n3|4\n"), (' ', "1  2\n3 4\n")] {
+            let dataset = read(&CsvReader::new(), "delimiter.txt", contents).unwrap();
+            assert_eq!(dataset.delimiter, delimiter);
+            assert_eq!(dataset.data, array![[1.0, 2.0], [3.0, 4.0]]);
+        }
+    }
+
+    #[test]
+    fn quoted_fields_keep_their_delimiters() {
+        let dataset = read(&CsvReader::new(), "quoted.csv", "name,x\n\"a, b\",1\n\"c\",2\n");
+        // the name column is text, so only the numeric column can be read
+        assert!(dataset.is_err());
+        let mut reader = CsvReader::new();
+        reader.set_columns(vec![Column::Name("x".to_string())]);
+        let dataset = read(&reader, "quoted.csv", "name,x\n\"a, b\",1\n\"c\",2\n").unwrap();
+        assert_eq!(dataset.data, array![[1.0], [2.0]]);
+    }
+
+    #[test]
+    fn handles_missing_values() {
+        let contents = "a,b\n1,\n3,4\n5,NA\n";
+        let mut reader = CsvReader::new();
+        let error = read(&reader, "missing.csv", contents).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains(":2: missing value in column b"));
+
+        reader.set_missing(MissingValues::DropRows);
+        let dataset = read(&reader, "missing.csv", contents).unwrap();
+        assert_eq!(dataset.data, array![[3.0, 4.0]]);
+        assert_eq!(dataset.rows, vec![1]);
+
+        reader.set_missing(MissingValues::Mean);
+        assert_eq!(read(&reader, "missing.csv", contents).unwrap().data, array![[1.0, 4.0], [3.0, 4.0], [5.0, 4.0]]);
+
+        reader.set_missing(MissingValues::Fill(-1.0));
+        assert_eq!(read(&reader, "missing.csv", contents).unwrap().data, array![[1.0, -1.0], [3.0, 4.0], [5.0, -1.0]]);
+    }
+
+    #[test]
+    fn writes_labels_next_to_the_source() {
+        let source = temp_file("source.csv", "a,b\n1,\n3,4\n5,6\n");
+        let mut reader = CsvReader::new();
+        reader.set_missing(MissingValues::DropRows);
+        let dataset = reader.read(&source).unwrap();
+        let output = std::env::temp_dir().join(format!("io_{}_labelled.csv", std::process::id()));
+        dataset.write_with_labels(&output, &[0, 1], &[("distance", &[0.5, 1.5])]).unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        let error = dataset.write_with_labels(&output, &[0], &[]).err().unwrap();
+        let extra_error = dataset.write_with_labels(&output, &[0, 1], &[("distance", &[0.5])]).err().unwrap();
+        std::fs::remove_file(&source).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(written, "a,b,cluster,distance\n1,,,\n3,4,0,0.5\n5,6,1,1.5\n");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(extra_error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
\ No newline at end of file
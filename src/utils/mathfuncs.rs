@@ -1,7 +1,9 @@
-use ndarray::{Array, Array1, Array2, Axis};
+use ndarray::{s, Array, Array1, Array2, ArrayView1, Axis};
 use num::ToPrimitive;
 use rand::{thread_rng, Rng};
 use crate::utils::utility::*;
+use crate::utils::distances::CondensedMatrix;
+use std::collections::HashMap;
 
 pub fn silhouette_score(x: Array2<f32>, assignments: Vec<i32>, centroids: Array2<f32>) -> f32{
     let mut scores: Vec<f32> = vec![0.0; x.shape()[0]];
@@ -25,24 +27,124 @@ pub fn silhouette_score(x: Array2<f32>, assignments: Vec<i32>, centroids: Array2
         }
         scores[i] = (maximum - parent_dist) / maximum;
     }
-    return 0.0;
+    scores.iter().sum::<f32>() / scores.len().max(1).to_f32().unwrap()
+}
+
+/// Mean silhouette over all labelled points from pairwise distances: how much closer
+/// every point is to its own cluster than to the nearest other one. Negative labels
+/// are ignored, points alone in their cluster score 0.
+pub fn silhouette_score_precomputed(distances: &CondensedMatrix, labels: &[i32]) -> f32 {
+    let n = distances.n_points();
+    assert_eq!(labels.len(), n, "need one label per point");
+    let n_clusters = labels.iter().max().map_or(0, |&max| max + 1).max(0).to_usize().unwrap();
+    let mut sizes = vec![0usize; n_clusters];
+    for &label in labels.iter().filter(|&&l| l >= 0) {
+        sizes[label.to_usize().unwrap()] += 1;
+    }
+    let mut total = 0.0;
+    let mut counted = 0;
+    let mut sums = vec![0.0f32; n_clusters];
+    for i in 0..n {
+        if labels[i] < 0 {
+            continue;
+        }
+        let own = labels[i].to_usize().unwrap();
+        counted += 1;
+        if sizes[own] < 2 {
+            continue;
+        }
+        sums.iter_mut().for_each(|sum| *sum = 0.0);
+        for j in 0..n {
+            if labels[j] >= 0 && j != i {
+                sums[labels[j].to_usize().unwrap()] += distances.get(i, j);
+            }
+        }
+        let a = sums[own] / (sizes[own] - 1).to_f32().unwrap();
+        let b = (0..n_clusters)
+            .filter(|&k| k != own && sizes[k] > 0)
+            .map(|k| sums[k] / sizes[k].to_f32().unwrap())
+            .fold(f32::INFINITY, f32::min);
+        if b.is_finite() && a.max(b) > 0.0 {
+            total += (b - a) / a.max(b);
+        }
+    }
+    total / counted.max(1).to_f32().unwrap()
+}
+
+/// Counts of every `(truth, predicted)` label pair and of either label alone.
+type Contingency = (HashMap<(i32, i32), usize>, HashMap<i32, usize>, HashMap<i32, usize>);
+
+fn contingency(truth: &[i32], predicted: &[i32]) -> Contingency {
+    assert_eq!(truth.len(), predicted.len(), "need one predicted label per true label");
+    let mut pairs = HashMap::new();
+    let mut truth_sizes = HashMap::new();
+    let mut predicted_sizes = HashMap::new();
+    for (&t, &p) in truth.iter().zip(predicted.iter()) {
+        *pairs.entry((t, p)).or_insert(0) += 1;
+        *truth_sizes.entry(t).or_insert(0) += 1;
+        *predicted_sizes.entry(p).or_insert(0) += 1;
+    }
+    (pairs, truth_sizes, predicted_sizes)
+}
+
+/// Rand index corrected for chance between two labelings: 1 for identical partitions up
+/// to relabelling, around 0 for random ones. Noise labels count as one more cluster.
+pub fn adjusted_rand_index(truth: &[i32], predicted: &[i32]) -> f32 {
+    let (pairs, truth_sizes, predicted_sizes) = contingency(truth, predicted);
+    let comb2 = |n: &usize| (*n as f64) * (*n as f64 - 1.0) / 2.0;
+    let index: f64 = pairs.values().map(comb2).sum();
+    let truth_sum: f64 = truth_sizes.values().map(comb2).sum();
+    let predicted_sum: f64 = predicted_sizes.values().map(comb2).sum();
+    let expected = truth_sum * predicted_sum / comb2(&truth.len()).max(1.0);
+    let maximum = (truth_sum + predicted_sum) / 2.0;
+    if maximum == expected {
+        return 1.0;
+    }
+    ((index - expected) / (maximum - expected)) as f32
+}
+
+/// Mutual information of two labelings divided by the arithmetic mean of their
+/// entropies, between 0 and 1. Noise labels count as one more cluster.
+pub fn normalized_mutual_info(truth: &[i32], predicted: &[i32]) -> f32 {
+    let (pairs, truth_sizes, predicted_sizes) = contingency(truth, predicted);
+    let n = truth.len() as f64;
+    let entropy = |sizes: &HashMap<i32, usize>| -> f64 {
+        sizes.values().map(|&size| size as f64 / n).map(|p| -p * p.ln()).sum()
+    };
+    let (truth_entropy, predicted_entropy) = (entropy(&truth_sizes), entropy(&predicted_sizes));
+    if truth_entropy == 0.0 && predicted_entropy == 0.0 {
+        return 1.0;
+    }
+    let mutual_info: f64 = pairs
+        .iter()
+        .map(|((t, p), &count)| {
+            let joint = count as f64 / n;
+            joint * (joint * n * n / (truth_sizes[t] as f64 * predicted_sizes[p] as f64)).ln()
+        })
+        .sum();
+    (mutual_info / ((truth_entropy + predicted_entropy) / 2.0)).max(0.0) as f32
 }
 
 pub fn l2(x1: &Array1<f32>, x2: &Array1<f32>, grad: bool) -> f32 {
     if grad {
         //TODO: implement
-        return 0.0;
+        0.0
     }
     else {
-        return squared1d(x1 - x2).sum().sqrt();
+        squared1d(x1 - x2).sum().sqrt()
     }
 }
 
+/// Euclidean distance between two row views, without allocating.
+pub fn l2_view(x1: ArrayView1<f32>, x2: ArrayView1<f32>) -> f32 {
+    x1.iter().zip(x2.iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f32>().sqrt()
+}
+
 pub fn squared1d(x: Array1<f32>) -> Array1<f32> {
     x.iter().map(|x| x * x).collect()
 }
 
-pub fn create_square(min_max_y: &Vec<f32>, min_max_x: &Vec<f32>, n_points: usize, dim: usize) -> Array2<f32> {
+pub fn create_square(min_max_y: &[f32], min_max_x: &[f32], n_points: usize, dim: usize) -> Array2<f32> {
     let mut square = Array::<f32, _>::zeros((n_points, dim));
     let mut rng = thread_rng();
     for i in 0..n_points {
@@ -55,12 +157,12 @@ pub fn create_square(min_max_y: &Vec<f32>, min_max_x: &Vec<f32>, n_points: usize
             }
         }
     }
-    return square
+    square
 }
 
 pub fn cumsum (x: &mut Array1<f32>) {
     let mut last = 0.0;
-    for (_i, val) in x.into_iter().enumerate() {
+    for val in x.into_iter() {
         *val += last;
         last = *val;
     }
@@ -69,7 +171,7 @@ pub fn cumsum (x: &mut Array1<f32>) {
 pub fn center_scale(data: &mut Array2<f32>){
     let std = data.std_axis(Axis(0), 1.);
     let mean = data.mean_axis(Axis(0)).unwrap();
-    for (_i, row) in data.rows_mut().into_iter().enumerate(){
+    for row in data.rows_mut().into_iter(){
         for (j, num) in row.into_iter().enumerate() {
             *num = (*num - mean[j]) / std[j];
         }
@@ -81,11 +183,174 @@ pub fn square(x: f32) -> f32 {
     x * x
 }
 
-pub fn mean_of_vec_arr(vector: &Vec<Array1<f32>>) -> Array1<f32> {
+pub fn mean_of_vec_arr(vector: &[Array1<f32>]) -> Array1<f32> {
     let mut mean = Array1::zeros(vector[0].shape()[0]);
-    for arr in vector.into_iter() {
-        mean = mean + arr;
+    for arr in vector.iter() {
+        mean += arr;
+    }
+    mean /= vector.len().to_f32().unwrap();
+    mean
+}
+
+/// Eigen decomposition of a symmetric matrix by cyclic Jacobi rotations.
+/// Returns the eigenvalues in descending order and the matching eigenvectors as columns.
+pub fn symmetric_eigen(matrix: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = matrix.shape()[0];
+    let mut a = matrix.to_owned();
+    let mut v = Array2::<f64>::eye(n);
+    for _sweep in 0..100 {
+        let off: f64 = (0..n).flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j))).map(|(i, j)| a[[i, j]] * a[[i, j]]).sum();
+        let total: f64 = a.iter().map(|x| x * x).sum();
+        if off <= 1e-24 * total.max(f64::MIN_POSITIVE) {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[[p, q]] == 0.0 {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let akp = a[[k, p]];
+                    let akq = a[[k, q]];
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let apk = a[[p, k]];
+                    let aqk = a[[q, k]];
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let vkp = v[[k, p]];
+                    let vkq = v[[k, q]];
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[[j, j]].total_cmp(&a[[i, i]]));
+    let values = order.iter().map(|&i| a[[i, i]]).collect();
+    let vectors = v.select(Axis(1), &order);
+    (values, vectors)
+}
+
+/// Orthonormalizes the columns of `matrix` in place with modified Gram-Schmidt.
+/// Columns that become linearly dependent are replaced by random directions.
+pub fn orthonormalize(matrix: &mut Array2<f64>) {
+    let mut rng = thread_rng();
+    for j in 0..matrix.shape()[1] {
+        for _attempt in 0..3 {
+            for i in 0..j {
+                let previous = matrix.column(i).to_owned();
+                let projection = previous.dot(&matrix.column(j));
+                matrix.column_mut(j).scaled_add(-projection, &previous);
+            }
+            let norm = matrix.column(j).dot(&matrix.column(j)).sqrt();
+            if norm > 1e-10 {
+                matrix.column_mut(j).mapv_inplace(|x| x / norm);
+                break;
+            }
+            matrix.column_mut(j).mapv_inplace(|_| rng.gen_range(-1.0..1.0));
+        }
+    }
+}
+
+/// The `k` largest eigenvalues, descending, and their eigenvectors as columns of a
+/// symmetric positive semi-definite matrix, by orthogonal iteration with Rayleigh-Ritz.
+pub fn leading_eigenvectors(matrix: &Array2<f64>, k: usize, max_iter: i32) -> (Array1<f64>, Array2<f64>) {
+    let n = matrix.shape()[0];
+    let k = k.min(n);
+    let block = (k + 5).min(n);
+    if block == n {
+        let (values, vectors) = symmetric_eigen(matrix);
+        return (values.slice(s![..k]).to_owned(), vectors.slice(s![.., ..k]).to_owned());
+    }
+    let mut rng = thread_rng();
+    let mut q = Array2::<f64>::from_shape_fn((n, block), |_| rng.gen_range(-1.0..1.0));
+    orthonormalize(&mut q);
+    let mut values = Array1::zeros(block);
+    for _ in 0..max_iter.max(1) {
+        let z = matrix.dot(&q);
+        let (ritz_values, rotation) = symmetric_eigen(&q.t().dot(&z));
+        let ritz_vectors = q.dot(&rotation);
+        let rotated = z.dot(&rotation);
+        values = ritz_values;
+        q = ritz_vectors;
+        let scale = values[0].abs().max(f64::MIN_POSITIVE);
+        let converged = (0..k).all(|i| {
+            let residual = &rotated.column(i) - &(&q.column(i) * values[i]);
+            residual.dot(&residual).sqrt() <= 1e-6 * scale
+        });
+        if converged {
+            break;
+        }
+        q = rotated;
+        orthonormalize(&mut q);
+    }
+    (values.slice(s![..k]).to_owned(), q.slice(s![.., ..k]).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn silhouette_score_is_the_mean_over_points() {
+        let x = array![[0.0, 0.0], [1.0, 0.0], [10.0, 0.0]];
+        let centroids = array![[0.5, 0.0], [10.0, 0.0]];
+        // per point (b - a) / b: 9.5 / 10, 8.5 / 9 and 9.5 / 9.5
+        let expected = (0.95 + 8.5 / 9.0 + 1.0) / 3.0;
+        assert!((silhouette_score(x, vec![0, 0, 1], centroids) - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn silhouette_score_of_a_single_centroid_is_zero() {
+        let x = array![[0.0, 0.0], [1.0, 0.0]];
+        assert_eq!(silhouette_score(x, vec![0, 0], array![[0.5, 0.0]]), 0.0);
+    }
+    /// Checks `matrix * vector = value * vector` for every column, with unit norm.
+    fn assert_eigenpairs(matrix: &Array2<f64>, values: &Array1<f64>, vectors: &Array2<f64>) {
+        for (value, vector) in values.iter().zip(vectors.columns()) {
+            assert!((vector.dot(&vector) - 1.0).abs() < 1e-9);
+            let residual = matrix.dot(&vector) - &vector * *value;
+            assert!(residual.dot(&residual).sqrt() < 1e-8, "{} is no eigenvalue", value);
+        }
+    }
+
+    #[test]
+    fn symmetric_eigen_finds_known_eigenpairs() {
+        let matrix = array![[2.0, 1.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.0, 5.0]];
+        let (values, vectors) = symmetric_eigen(&matrix);
+        assert!((&values - &array![5.0, 3.0, 1.0]).iter().all(|d| d.abs() < 1e-12), "{}", values);
+        assert_eigenpairs(&matrix, &values, &vectors);
+        // up to sign, (0, 0, 1), (1, 1, 0) / sqrt(2) and (1, -1, 0) / sqrt(2)
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((vectors[[2, 0]].abs() - 1.0).abs() < 1e-12);
+        assert!((vectors[[0, 1]] - vectors[[1, 1]]).abs() < 1e-12 && (vectors[[0, 1]].abs() - half).abs() < 1e-12);
+        assert!((vectors[[0, 2]] + vectors[[1, 2]]).abs() < 1e-12 && (vectors[[0, 2]].abs() - half).abs() < 1e-12);
+    }
+
+    #[test]
+    fn leading_eigenvectors_match_the_full_decomposition() {
+        // diagonally dominant, so positive definite with a spread-out spectrum
+        let n = 12;
+        let matrix = Array2::from_shape_fn((n, n), |(i, j)| if i == j { 2.0 * (i + 1) as f64 } else { 1.0 / (1 + i + j) as f64 });
+        let (all, _) = symmetric_eigen(&matrix);
+        let (values, vectors) = leading_eigenvectors(&matrix, 3, 1000);
+        assert!((&values - &all.slice(s![..3])).iter().all(|d| d.abs() < 1e-6), "{} vs {}", values, all);
+        assert_eq!(vectors.shape(), &[n, 3]);
+        for (value, vector) in values.iter().zip(vectors.columns()) {
+            let residual = matrix.dot(&vector) - &vector * *value;
+            assert!(residual.dot(&residual).sqrt() < 1e-4);
+        }
     }
-    mean = mean / vector.len().to_f32().unwrap();
-    return mean;
 }
\ No newline at end of file
@@ -0,0 +1,281 @@
+use ndarray::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Contents of a `.npy` file with every element widened to `f64`.
+struct RawArray {
+    shape: Vec<usize>,
+    fortran_order: bool,
+    /// NumPy dtype kind: `f` float, `i` signed, `u` unsigned integer or `b` bool.
+    kind: char,
+    values: Vec<f64>
+}
+
+/// Reads a 1-D or 2-D `.npy` array of floats, integers or bools, in C or Fortran order
+/// and either byte order. 1-D arrays become a single column.
+pub fn read_npy(path: impl AsRef<Path>) -> io::Result<Array2<f32>> {
+    let path = path.as_ref();
+    let raw = parse(&mut BufReader::new(File::open(path)?), &path.display().to_string())?;
+    to_matrix(raw, &path.display().to_string())
+}
+
+/// Reads a 1-D `.npy` array of integers, e.g. labels saved from Python.
+pub fn read_npy_labels(path: impl AsRef<Path>) -> io::Result<Vec<i32>> {
+    let path = path.as_ref();
+    let source = path.display().to_string();
+    let raw = parse(&mut BufReader::new(File::open(path)?), &source)?;
+    if raw.shape.len() != 1 || raw.kind == 'f' {
+        return Err(invalid_data(format!("{}: labels must be a 1-D integer array, found {} dimensions of kind {}", source, raw.shape.len(), raw.kind)));
+    }
+    raw.values.iter()
+        .map(|&value| {
+            if value < i32::MIN as f64 || value > i32::MAX as f64 {
+                return Err(invalid_data(format!("{}: label {} out of range", source, value)));
+            }
+            Ok(value as i32)
+        })
+        .collect()
+}
+
+/// Every array of a `.npz` archive, as written by `numpy.savez` or `savez_compressed`,
+/// under its key without the `.npy` suffix, in archive order.
+pub fn read_npz(path: impl AsRef<Path>) -> io::Result<Vec<(String, Array2<f32>)>> {
+    let path = path.as_ref();
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+    let mut arrays = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        let name = entry.name().trim_end_matches(".npy").to_string();
+        let source = format!("{}:{}", path.display(), name);
+        let raw = parse(&mut entry, &source)?;
+        arrays.push((name, to_matrix(raw, &source)?));
+    }
+    Ok(arrays)
+}
+
+/// The array stored under `name` in a `.npz` archive.
+pub fn read_npz_array(path: impl AsRef<Path>, name: &str) -> io::Result<Array2<f32>> {
+    let path = path.as_ref();
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+    let source = format!("{}:{}", path.display(), name);
+    let mut entry = archive.by_name(&format!("{}.npy", name))
+        .map_err(|_| invalid_data(format!("{}: no array named {:?}", path.display(), name)))?;
+    let raw = parse(&mut entry, &source)?;
+    to_matrix(raw, &source)
+}
+
+/// Writes `data` as a little-endian float32 `.npy` file in C order, e.g. centroids
+/// or `AggloClusterer::linkage_matrix`.
+pub fn write_npy(path: impl AsRef<Path>, data: &Array2<f32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_header(&mut writer, "<f4", &format!("({}, {})", data.shape()[0], data.shape()[1]))?;
+    for value in data.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Writes labels as a 1-D little-endian int32 `.npy` file.
+pub fn write_npy_labels(path: impl AsRef<Path>, labels: &[i32]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_header(&mut writer, "<i4", &format!("({},)", labels.len()))?;
+    for label in labels {
+        writer.write_all(&label.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Version 1.0 header, padded with spaces so the data starts at a multiple of 64 bytes.
+fn write_header(writer: &mut impl Write, descr: &str, shape: &str) -> io::Result<()> {
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())
+}
+
+fn parse(reader: &mut impl Read, source: &str) -> io::Result<RawArray> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid_data(format!("{}: not a .npy file", source)));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(invalid_data(format!("{}: unsupported .npy version {}", source, version)))
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")
+        .and_then(|value| value.strip_prefix('\'').and_then(|value| value.split('\'').next()))
+        .ok_or_else(|| invalid_data(format!("{}: unsupported dtype, only plain numbers can be read", source)))?;
+    let fortran_order = match header_value(&header, "fortran_order") {
+        Some(value) if value.starts_with("True") => true,
+        Some(value) if value.starts_with("False") => false,
+        _ => return Err(invalid_data(format!("{}: header without fortran_order", source)))
+    };
+    let shape: Vec<usize> = header_value(&header, "shape")
+        .and_then(|value| value.strip_prefix('('))
+        .and_then(|value| value.split(')').next())
+        .ok_or_else(|| invalid_data(format!("{}: header without shape", source)))?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>().map_err(|_| invalid_data(format!("{}: bad dimension {:?}", source, dim))))
+        .collect::<io::Result<_>>()?;
+
+    let mut chars = descr.chars();
+    let (order, kind) = (chars.next().unwrap_or('?'), chars.next().unwrap_or('?'));
+    let size: usize = chars.as_str().parse().unwrap_or(0);
+    let little_endian = match order {
+        '<' => true,
+        '>' => false,
+        '|' | '=' => cfg!(target_endian = "little"),
+        _ => return Err(invalid_data(format!("{}: unsupported dtype {:?}", source, descr)))
+    };
+    let decode: fn(&[u8]) -> f64 = match (kind, size) {
+        ('f', 4) => |b| f32::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('f', 8) => |b| f64::from_le_bytes(b.try_into().unwrap()),
+        ('i', 1) => |b| b[0] as i8 as f64,
+        ('i', 2) => |b| i16::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('i', 4) => |b| i32::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('i', 8) => |b| i64::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('u', 1) | ('b', 1) => |b| b[0] as f64,
+        ('u', 2) => |b| u16::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('u', 4) => |b| u32::from_le_bytes(b.try_into().unwrap()) as f64,
+        ('u', 8) => |b| u64::from_le_bytes(b.try_into().unwrap()) as f64,
+        _ => return Err(invalid_data(format!("{}: unsupported dtype {:?}", source, descr)))
+    };
+
+    let count: usize = shape.iter().product();
+    let mut bytes = vec![0u8; count * size];
+    reader.read_exact(&mut bytes)
+        .map_err(|_| invalid_data(format!("{}: file ends before its {} values", source, count)))?;
+    let values = bytes.chunks_exact_mut(size)
+        .map(|element| {
+            if !little_endian {
+                element.reverse();
+            }
+            decode(element)
+        })
+        .collect();
+    Ok(RawArray { shape, fortran_order, kind, values })
+}
+
+/// The text after `'key':` in a header dict.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    Some(header[start..].trim_start().strip_prefix(':')?.trim_start())
+}
+
+fn to_matrix(raw: RawArray, source: &str) -> io::Result<Array2<f32>> {
+    let values: Vec<f32> = raw.values.into_iter().map(|value| value as f32).collect();
+    let matrix = match raw.shape[..] {
+        [n] => Array2::from_shape_vec((n, 1), values).unwrap(),
+        [rows, columns] if raw.fortran_order => Array2::from_shape_vec((rows, columns).f(), values).unwrap().as_standard_layout().into_owned(),
+        [rows, columns] => Array2::from_shape_vec((rows, columns), values).unwrap(),
+        _ => return Err(invalid_data(format!("{}: expected 1 or 2 dimensions, found {}", source, raw.shape.len())))
+    };
+    Ok(matrix)
+}
+
+fn zip_error(error: zip::result::ZipError) -> io::Error {
+    match error {
+        zip::result::ZipError::Io(error) => error,
+        error => invalid_data(error.to_string())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1.0 `.npy` file holding `data` under the given header fields.
+    fn npy_bytes(descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+        let order = if fortran_order { "True" } else { "False" };
+        let header = format!("{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}\n", descr, order, shape);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn read_bytes(bytes: &[u8]) -> io::Result<Array2<f32>> {
+        to_matrix(parse(&mut &bytes[..], "test")?, "test")
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("npy_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let path = temp_path("data.npy");
+        let data = array![[1.0, -2.5, 3.0], [4.0, 5.0, f32::MAX]];
+        write_npy(&path, &data).unwrap();
+        let read = read_npy(&path);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), data);
+        // the data starts 64-byte aligned, as NumPy expects
+        assert_eq!((bytes.len() - 6 * 4) % 64, 0);
+    }
+
+    #[test]
+    fn labels_round_trip() {
+        let path = temp_path("labels.npy");
+        write_npy_labels(&path, &[0, 1, -1, 2]).unwrap();
+        let labels = read_npy_labels(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(labels.unwrap(), vec![0, 1, -1, 2]);
+    }
+
+    #[test]
+    fn reads_fortran_order() {
+        let data: Vec<u8> = [1.0f32, 4.0, 2.0, 5.0, 3.0, 6.0].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let matrix = read_bytes(&npy_bytes("<f4", true, "(2, 3)", &data)).unwrap();
+        assert_eq!(matrix, array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        assert!(matrix.is_standard_layout());
+    }
+
+    #[test]
+    fn reads_big_endian() {
+        let floats: Vec<u8> = [1.5f64, -2.0].iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_eq!(read_bytes(&npy_bytes(">f8", false, "(2,)", &floats)).unwrap(), array![[1.5], [-2.0]]);
+        let integers: Vec<u8> = [7i32, -300, 65536, 1].iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_eq!(read_bytes(&npy_bytes(">i4", false, "(2, 2)", &integers)).unwrap(), array![[7.0, -300.0], [65536.0, 1.0]]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let data: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let error = |bytes: Vec<u8>| read_bytes(&bytes).err().unwrap().to_string();
+        assert!(error(npy_bytes("<f4", false, "(3,)", &data)).contains("ends before its 3 values"));
+        assert!(error(npy_bytes("<f4", false, "(1, 1, 2)", &data)).contains("expected 1 or 2 dimensions"));
+        assert!(error(npy_bytes("<c8", false, "(1,)", &data)).contains("unsupported dtype"));
+        assert!(error(b"PK\x03\x04 not numpy".to_vec()).contains("not a .npy file"));
+    }
+}
\ No newline at end of file
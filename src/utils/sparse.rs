@@ -0,0 +1,215 @@
+use ndarray::prelude::*;
+use crate::cluster_algos::error::ClusterError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SparseMetric {
+    Euclidean,
+    /// `1 - cos` of the angle between two rows, 1 if either row is all zeros.
+    Cosine
+}
+
+/// Compressed sparse row matrix: the non-zeros of row `i` are `data[indptr[i]..indptr[i + 1]]`
+/// in the columns `indices[indptr[i]..indptr[i + 1]]`, sorted ascending.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsrMatrix {
+    n_rows: usize,
+    n_cols: usize,
+    pub indptr: Vec<usize>,
+    pub indices: Vec<usize>,
+    pub data: Vec<f32>
+}
+
+impl CsrMatrix {
+    /// Validates the three CSR arrays, e.g. as written by SciPy's `csr_matrix`.
+    pub fn from_parts(n_rows: usize, n_cols: usize, indptr: Vec<usize>, indices: Vec<usize>, data: Vec<f32>) -> Result<Self, ClusterError> {
+        let invalid = |message: String| Err(ClusterError::InvalidInput(message));
+        if indptr.len() != n_rows + 1 {
+            return invalid(format!("indptr needs one entry per row plus one, got {} for {} rows", indptr.len(), n_rows));
+        }
+        if indptr[0] != 0 {
+            return invalid(format!("indptr must start at 0, got {}", indptr[0]));
+        }
+        if indices.len() != data.len() {
+            return invalid(format!("indices and data must have the same length, got {} and {}", indices.len(), data.len()));
+        }
+        if indptr[n_rows] != data.len() {
+            return invalid(format!("indptr must end at the number of non-zeros {}, got {}", data.len(), indptr[n_rows]));
+        }
+        if let Some(row) = (0..n_rows).find(|&row| indptr[row] > indptr[row + 1]) {
+            return invalid(format!("indptr must not decrease, but does after row {}", row));
+        }
+        for row in 0..n_rows {
+            let columns = &indices[indptr[row]..indptr[row + 1]];
+            if !columns.windows(2).all(|w| w[0] < w[1]) {
+                return invalid(format!("column indices of row {} must be sorted and unique", row));
+            }
+            if let Some(column) = columns.iter().find(|&&c| c >= n_cols) {
+                return invalid(format!("column index {} out of bounds for {} columns in row {}", column, n_cols, row));
+            }
+        }
+        Ok(Self { n_rows, n_cols, indptr, indices, data })
+    }
+
+    /// Builds a matrix from `(row, column, value)` triplets, summing duplicates.
+    pub fn from_triplets(n_rows: usize, n_cols: usize, triplets: &[(usize, usize, f32)]) -> Result<Self, ClusterError> {
+        if let Some(&(row, column, _)) = triplets.iter().find(|&&(row, column, _)| row >= n_rows || column >= n_cols) {
+            return Err(ClusterError::InvalidInput(format!("triplet ({}, {}) out of bounds for a {} x {} matrix", row, column, n_rows, n_cols)));
+        }
+        let mut sorted = triplets.to_vec();
+        sorted.sort_by_key(|&(row, column, _)| (row, column));
+        let mut indptr = vec![0; n_rows + 1];
+        let mut indices: Vec<usize> = Vec::with_capacity(sorted.len());
+        let mut data: Vec<f32> = Vec::with_capacity(sorted.len());
+        let mut last = None;
+        for (row, column, value) in sorted {
+            if last == Some((row, column)) {
+                *data.last_mut().unwrap() += value;
+                continue;
+            }
+            last = Some((row, column));
+            indptr[row + 1] += 1;
+            indices.push(column);
+            data.push(value);
+        }
+        for row in 0..n_rows {
+            indptr[row + 1] += indptr[row];
+        }
+        Ok(Self { n_rows, n_cols, indptr, indices, data })
+    }
+
+    pub fn from_dense(dense: &Array2<f32>) -> Self {
+        let mut indptr = vec![0];
+        let mut indices = Vec::new();
+        let mut data = Vec::new();
+        for row in dense.rows() {
+            for (column, &value) in row.iter().enumerate() {
+                if value != 0.0 {
+                    indices.push(column);
+                    data.push(value);
+                }
+            }
+            indptr.push(data.len());
+        }
+        Self { n_rows: dense.shape()[0], n_cols: dense.shape()[1], indptr, indices, data }
+    }
+
+    pub fn to_dense(&self) -> Array2<f32> {
+        let mut dense = Array2::<f32>::zeros((self.n_rows, self.n_cols));
+        for i in 0..self.n_rows {
+            let (columns, values) = self.row(i);
+            for (&column, &value) in columns.iter().zip(values) {
+                dense[[i, column]] = value;
+            }
+        }
+        dense
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.n_rows, self.n_cols)
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Column indices and values of the non-zeros of row `i`.
+    pub fn row(&self, i: usize) -> (&[usize], &[f32]) {
+        let (start, end) = (self.indptr[i], self.indptr[i + 1]);
+        (&self.indices[start..end], &self.data[start..end])
+    }
+
+    pub fn row_norms_squared(&self) -> Vec<f32> {
+        (0..self.n_rows).map(|i| self.row(i).1.iter().map(|v| v * v).sum()).collect()
+    }
+
+    /// Dot product of row `i` with a dense vector.
+    pub fn dot_dense(&self, i: usize, vector: ArrayView1<f32>) -> f32 {
+        let (columns, values) = self.row(i);
+        columns.iter().zip(values).map(|(&c, &v)| v * vector[c]).sum()
+    }
+
+    /// Dot product of rows `i` and `j`, merging their sorted column indices.
+    pub fn dot_rows(&self, i: usize, j: usize) -> f32 {
+        let (columns_i, values_i) = self.row(i);
+        let (columns_j, values_j) = self.row(j);
+        let (mut a, mut b, mut dot) = (0, 0, 0.0);
+        while a < columns_i.len() && b < columns_j.len() {
+            match columns_i[a].cmp(&columns_j[b]) {
+                std::cmp::Ordering::Less => a += 1,
+                std::cmp::Ordering::Greater => b += 1,
+                std::cmp::Ordering::Equal => {
+                    dot += values_i[a] * values_j[b];
+                    a += 1;
+                    b += 1;
+                }
+            }
+        }
+        dot
+    }
+
+    /// Distance between rows `i` and `j` given their squared norms from `row_norms_squared`.
+    pub fn distance(&self, i: usize, j: usize, norms_squared: &[f32], metric: SparseMetric) -> f32 {
+        if i == j {
+            return 0.0;
+        }
+        let dot = self.dot_rows(i, j);
+        match metric {
+            SparseMetric::Euclidean => (norms_squared[i] + norms_squared[j] - 2.0 * dot).max(0.0).sqrt(),
+            SparseMetric::Cosine => {
+                let norms = (norms_squared[i] * norms_squared[j]).sqrt();
+                if norms > 0.0 { (1.0 - dot / norms).max(0.0) } else { 1.0 }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(result: Result<CsrMatrix, ClusterError>) -> String {
+        match result {
+            Err(ClusterError::InvalidInput(message)) => message,
+            other => panic!("expected invalid input, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parts_triplets_and_dense_agree() {
+        let dense = array![[1.0, 0.0, 2.0], [0.0, 0.0, 0.0], [0.0, 3.0, 0.0]];
+        let parts = CsrMatrix::from_parts(3, 3, vec![0, 2, 2, 3], vec![0, 2, 1], vec![1.0, 2.0, 3.0]).unwrap();
+        let triplets = CsrMatrix::from_triplets(3, 3, &[(2, 1, 3.0), (0, 2, 1.5), (0, 0, 1.0), (0, 2, 0.5)]).unwrap();
+        assert_eq!(parts, CsrMatrix::from_dense(&dense));
+        assert_eq!(triplets, parts);
+        assert_eq!(parts.to_dense(), dense);
+        assert_eq!(parts.nnz(), 3);
+        assert_eq!(parts.row(1), (&[][..], &[][..]));
+    }
+
+    #[test]
+    fn rejects_malformed_parts() {
+        assert!(invalid(CsrMatrix::from_parts(2, 2, vec![0, 1], vec![0], vec![1.0])).contains("one entry per row"));
+        assert!(invalid(CsrMatrix::from_parts(1, 2, vec![1, 1], vec![0], vec![1.0])).contains("start at 0"));
+        assert!(invalid(CsrMatrix::from_parts(1, 2, vec![0, 1], vec![0, 1], vec![1.0])).contains("same length"));
+        assert!(invalid(CsrMatrix::from_parts(1, 2, vec![0, 1], vec![0, 1], vec![1.0, 2.0])).contains("end at"));
+        assert!(invalid(CsrMatrix::from_parts(2, 2, vec![0, 2, 1], vec![0], vec![1.0])).contains("must not decrease"));
+        assert!(invalid(CsrMatrix::from_parts(1, 2, vec![0, 2], vec![1, 0], vec![1.0, 2.0])).contains("sorted"));
+        assert!(invalid(CsrMatrix::from_parts(1, 2, vec![0, 1], vec![2], vec![1.0])).contains("out of bounds"));
+        assert!(invalid(CsrMatrix::from_triplets(2, 2, &[(0, 0, 1.0), (2, 0, 1.0)])).contains("(2, 0)"));
+    }
+
+    #[test]
+    fn distances_between_rows() {
+        let matrix = CsrMatrix::from_dense(&array![[1.0, 0.0, 1.0], [2.0, 0.0, 2.0], [0.0, 3.0, 0.0], [0.0, 0.0, 0.0]]);
+        let norms = matrix.row_norms_squared();
+        assert_eq!(norms, vec![2.0, 8.0, 9.0, 0.0]);
+        assert_eq!(matrix.dot_rows(0, 1), 4.0);
+        let cosine = |i, j| matrix.distance(i, j, &norms, SparseMetric::Cosine);
+        assert!(cosine(0, 1).abs() < 1e-6);
+        assert!((cosine(0, 2) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine(0, 3), 1.0);
+        assert_eq!(cosine(3, 3), 0.0);
+        assert!((matrix.distance(0, 1, &norms, SparseMetric::Euclidean) - 2.0f32.sqrt()).abs() < 1e-6);
+        assert!((matrix.distance(1, 2, &norms, SparseMetric::Euclidean) - 17.0f32.sqrt()).abs() < 1e-6);
+    }
+}
\ No newline at end of file
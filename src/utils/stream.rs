@@ -0,0 +1,252 @@
+use ndarray::prelude::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Layout of the rows in a file read by `ChunkReader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkFormat {
+    /// One row per line, fields split by `delimiter`, optionally after a header line.
+    Csv { delimiter: char, has_header: bool },
+    /// Row-major little-endian `f32`s without header, `dim` values per row.
+    Binary { dim: usize }
+}
+
+/// Reads a file of points `chunk_size` rows at a time, so the file never has to
+/// fit in memory. Rewind it to make another pass.
+pub struct ChunkReader {
+    pub path: PathBuf,
+    pub format: ChunkFormat,
+    pub chunk_size: usize,
+    reader: BufReader<File>,
+    dim: Option<usize>,
+    line: usize
+}
+
+impl ChunkReader {
+
+    pub fn open(path: impl AsRef<Path>, format: ChunkFormat, chunk_size: usize) -> io::Result<ChunkReader> {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        let path = path.as_ref().to_path_buf();
+        let mut reader = ChunkReader {
+            reader: BufReader::new(File::open(&path)?),
+            path,
+            format,
+            chunk_size,
+            dim: None,
+            line: 0
+        };
+        reader.rewind()?;
+        Ok(reader)
+    }
+
+    /// Starts over at the first row.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.line = 0;
+        match self.format {
+            ChunkFormat::Binary { dim } => self.dim = Some(dim),
+            ChunkFormat::Csv { has_header: true, .. } => {
+                let mut header = String::new();
+                self.reader.read_line(&mut header)?;
+                self.line = 1;
+            }
+            ChunkFormat::Csv { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Number of columns, known once the first row has been read for CSV files.
+    pub fn dim(&self) -> Option<usize> {
+        self.dim
+    }
+
+    /// The next at most `chunk_size` rows, `None` at the end of the file.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Array2<f32>>> {
+        let values = match self.format {
+            ChunkFormat::Csv { delimiter, .. } => self.read_csv(delimiter)?,
+            ChunkFormat::Binary { dim } => self.read_binary(dim)?
+        };
+        let dim = self.dim.unwrap_or(0);
+        if values.is_empty() || dim == 0 {
+            return Ok(None);
+        }
+        let rows = values.len() / dim;
+        Ok(Some(Array2::from_shape_vec((rows, dim), values).unwrap()))
+    }
+
+    fn read_csv(&mut self, delimiter: char) -> io::Result<Vec<f32>> {
+        let mut values = Vec::new();
+        let mut rows = 0;
+        let mut line = String::new();
+        while rows < self.chunk_size {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            self.line += 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let start = values.len();
+            for field in trimmed.split(delimiter) {
+                let value = field.trim().parse::<f32>().map_err(|_| {
+                    invalid_data(format!("{}:{}: cannot parse {:?} as a number", self.path.display(), self.line, field))
+                })?;
+                values.push(value);
+            }
+            let width = values.len() - start;
+            match self.dim {
+                None => self.dim = Some(width),
+                Some(dim) if dim != width => {
+                    return Err(invalid_data(format!("{}:{}: expected {} columns, found {}", self.path.display(), self.line, dim, width)));
+                }
+                Some(_) => {}
+            }
+            rows += 1;
+        }
+        Ok(values)
+    }
+
+    fn read_binary(&mut self, dim: usize) -> io::Result<Vec<f32>> {
+        let mut bytes = Vec::with_capacity(self.chunk_size * dim * 4);
+        (&mut self.reader).take((self.chunk_size * dim * 4) as u64).read_to_end(&mut bytes)?;
+        if bytes.len() % (dim * 4).max(1) != 0 {
+            return Err(invalid_data(format!("{}: file ends inside a row of {} values", self.path.display(), dim)));
+        }
+        Ok(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+    }
+}
+
+impl Iterator for ChunkReader {
+    type Item = io::Result<Array2<f32>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+/// Writes `data` in the `ChunkFormat::Binary` layout.
+pub fn write_binary(path: impl AsRef<Path>, data: &Array2<f32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for value in data.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Second pass over `reader`: labels every chunk with `predict` and writes the labels
+/// to `path`, one per line in row order. Returns the number of rows labelled.
+pub fn write_labels(reader: &mut ChunkReader, path: impl AsRef<Path>, mut predict: impl FnMut(&Array2<f32>) -> Vec<i32>) -> io::Result<usize> {
+    reader.rewind()?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut rows = 0;
+    while let Some(chunk) = reader.next_chunk()? {
+        for label in predict(&chunk) {
+            writeln!(writer, "{}", label)?;
+        }
+        rows += chunk.shape()[0];
+    }
+    writer.flush()?;
+    Ok(rows)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `f` on a temporary file holding `contents`, removing the file afterwards.
+    fn with_file<T>(name: &str, contents: &[u8], f: impl FnOnce(&Path) -> T) -> T {
+        let path = std::env::temp_dir().join(format!("stream_{}_{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let result = f(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn csv(has_header: bool) -> ChunkFormat {
+        ChunkFormat::Csv { delimiter: ';', has_header }
+    }
+
+    #[test]
+    fn reads_csv_in_chunks_after_the_header() {
+        with_file("header.csv", b"x;y\n1;2\n3; 4\n\n5;6\n", |path| {
+            let mut reader = ChunkReader::open(path, csv(true), 2).unwrap();
+            assert_eq!(reader.dim(), None);
+            assert_eq!(reader.next_chunk().unwrap(), Some(array![[1.0, 2.0], [3.0, 4.0]]));
+            assert_eq!(reader.dim(), Some(2));
+            assert_eq!(reader.next_chunk().unwrap(), Some(array![[5.0, 6.0]]));
+            assert_eq!(reader.next_chunk().unwrap(), None);
+            reader.rewind().unwrap();
+            assert_eq!(reader.next_chunk().unwrap(), Some(array![[1.0, 2.0], [3.0, 4.0]]));
+        });
+    }
+
+    #[test]
+    fn header_is_data_unless_declared() {
+        let error = with_file("no_header.csv", b"x;y\n1;2\n", |path| {
+            ChunkReader::open(path, csv(false), 2).unwrap().next_chunk().unwrap_err()
+        });
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains(":1: cannot parse \"x\""), "{}", error);
+    }
+
+    #[test]
+    fn rejects_rows_of_another_width() {
+        let error = with_file("ragged.csv", b"1;2\n3;4\n5\n", |path| {
+            let mut reader = ChunkReader::open(path, csv(false), 2).unwrap();
+            reader.next_chunk().unwrap();
+            reader.next_chunk().unwrap_err()
+        });
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains(":3: expected 2 columns, found 1"), "{}", error);
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let data = Array2::from_shape_fn((5, 3), |(i, j)| (i * 3 + j) as f32 - 0.5);
+        let path = std::env::temp_dir().join(format!("stream_binary_{}", std::process::id()));
+        write_binary(&path, &data).unwrap();
+        let mut reader = ChunkReader::open(&path, ChunkFormat::Binary { dim: 3 }, 2).unwrap();
+        let first: Vec<Array2<f32>> = reader.by_ref().collect::<io::Result<_>>().unwrap();
+        reader.rewind().unwrap();
+        let second: Vec<Array2<f32>> = reader.collect::<io::Result<_>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let shapes: Vec<usize> = first.iter().map(|chunk| chunk.shape()[0]).collect();
+        assert_eq!(shapes, vec![2, 2, 1]);
+        let views: Vec<ArrayView2<f32>> = first.iter().map(|chunk| chunk.view()).collect();
+        assert_eq!(ndarray::concatenate(Axis(0), &views).unwrap(), data);
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn binary_rejects_a_partial_row() {
+        let bytes: Vec<u8> = (0..7).flat_map(|i| (i as f32).to_le_bytes()).collect();
+        let error = with_file("partial.bin", &bytes, |path| {
+            let mut reader = ChunkReader::open(path, ChunkFormat::Binary { dim: 3 }, 2).unwrap();
+            assert_eq!(reader.next_chunk().unwrap().map(|chunk| chunk.shape()[0]), Some(2));
+            reader.next_chunk().unwrap_err()
+        });
+        assert!(error.to_string().contains("ends inside a row of 3 values"), "{}", error);
+    }
+
+    #[test]
+    fn write_labels_labels_every_row_in_order() {
+        let labels_path = std::env::temp_dir().join(format!("stream_labels_{}", std::process::id()));
+        let rows = with_file("labels.csv", b"1;0\n5;0\n2;0\n7;0\n3;0\n", |path| {
+            let mut reader = ChunkReader::open(path, csv(false), 2).unwrap();
+            // a pass already in progress is restarted
+            reader.next_chunk().unwrap();
+            write_labels(&mut reader, &labels_path, |chunk| chunk.column(0).iter().map(|&x| (x > 2.5) as i32).collect())
+        });
+        let written = std::fs::read_to_string(&labels_path).unwrap();
+        std::fs::remove_file(&labels_path).unwrap();
+        assert_eq!(rows.unwrap(), 5);
+        assert_eq!(written, "0\n1\n0\n1\n1\n");
+    }
+}
\ No newline at end of file
@@ -8,15 +8,15 @@ pub fn max(x: Vec<f32>) -> f32 {
             maximum = val;
         }
     }
-    return  maximum;
+    maximum
 }
 
 pub fn max_int(x: Vec<i32>) -> i32 {
     *x.iter().max().unwrap()
 }
 
-pub fn print_vec(vector: &Vec<i32>) {
-    for val in vector.into_iter() {
+pub fn print_vec(vector: &[i32]) {
+    for val in vector.iter() {
         let string = val.to_string();
         print!("{string} ");
     }
@@ -45,5 +45,5 @@ pub fn argwhere(x: Vec<i32>, value: i32) -> usize {
             return i;
         }
     }
-    return usize::MAX;
+    usize::MAX
 }
\ No newline at end of file